@@ -0,0 +1,155 @@
+//! Compares the throughput of `Tun` and `UringTun`, requires `CAP_NET_ADMIN`:
+//!
+//! ```text
+//! sudo -E cargo bench --features io-uring --bench uring
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio_tun::{TunBuilder, UringOptions};
+
+const BATCH: u64 = 1024;
+
+/// An IPv4/UDP packet with an invalid header checksum, which the kernel drops right away.
+fn packet() -> Vec<u8> {
+    let mut packet = vec![0; 64];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&64u16.to_be_bytes());
+    packet[8] = 64;
+    packet[9] = 17;
+    packet[10..12].copy_from_slice(&[0xde, 0xad]);
+    packet[12..16].copy_from_slice(&[10, 78, 0, 2]);
+    packet[16..20].copy_from_slice(&[10, 78, 0, 1]);
+    packet
+}
+
+fn builder(name: &str, network: u8) -> TunBuilder {
+    TunBuilder::new()
+        .name(name)
+        .address(Ipv4Addr::new(10, network, 0, 1))
+        .netmask(Ipv4Addr::new(255, 255, 255, 0))
+        .packet_info(false)
+        .up()
+}
+
+/// Sends UDP packets through the device from another thread until the returned flag is set.
+fn flood(network: u8) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for _ in 0..2 {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let socket = UdpSocket::bind((Ipv4Addr::new(10, network, 0, 1), 0)).unwrap();
+            let target = (Ipv4Addr::new(10, network, 0, 2), 9);
+            while !stop.load(Ordering::Relaxed) {
+                let _ = socket.send_to(&[0; 32], target);
+            }
+        });
+    }
+    stop
+}
+
+fn send(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let _guard = rt.enter();
+    let packet = packet();
+    let mut group = c.benchmark_group("send");
+    group.throughput(Throughput::Elements(BATCH));
+
+    let tun = match builder("bench-tun0", 78).try_build() {
+        Ok(tun) => tun,
+        Err(err) => {
+            eprintln!("skipping benchmarks, creating a device failed: {}", err);
+            return;
+        }
+    };
+    group.bench_function(BenchmarkId::new("AsyncFd", BATCH), |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters * BATCH {
+                    tun.send(&packet).await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+    drop(tun);
+
+    let mut tun = builder("bench-tun0", 78)
+        .try_build_uring(&UringOptions::new().entries(256))
+        .unwrap();
+    group.bench_function(BenchmarkId::new("UringTun", BATCH), |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters * BATCH {
+                    tun.send(&packet).await.unwrap();
+                }
+                tun.flush().unwrap();
+                start.elapsed()
+            })
+        })
+    });
+    group.finish();
+}
+
+fn recv(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let _guard = rt.enter();
+    let mut buf = [0; 1500];
+    let mut group = c.benchmark_group("recv");
+    group.throughput(Throughput::Elements(BATCH));
+
+    let tun = match builder("bench-tun1", 79).try_build() {
+        Ok(tun) => tun,
+        Err(err) => {
+            eprintln!("skipping benchmarks, creating a device failed: {}", err);
+            return;
+        }
+    };
+    let stop = flood(79);
+    group.bench_function(BenchmarkId::new("AsyncFd", BATCH), |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters * BATCH {
+                    tun.recv(&mut buf).await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+    stop.store(true, Ordering::Relaxed);
+    drop(tun);
+
+    let mut tun = builder("bench-tun1", 79)
+        .try_build_uring(&UringOptions::new().entries(256))
+        .unwrap();
+    let stop = flood(79);
+    group.bench_function(BenchmarkId::new("UringTun", BATCH), |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                let mut received = 0;
+                while received < iters * BATCH {
+                    received += tun.recv_batch(|_| {}).await.unwrap() as u64;
+                }
+                start.elapsed()
+            })
+        })
+    });
+    stop.store(true, Ordering::Relaxed);
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(5));
+    targets = send, recv
+}
+criterion_main!(benches);
@@ -1,8 +1,7 @@
 use std::net::Ipv4Addr;
 use std::os::unix::io::AsRawFd;
-use tokio::io::AsyncReadExt;
 use tokio_tun::result::Result;
-use tokio_tun::TunBuilder;
+use tokio_tun::{MultiQueueTun, TunBuilder};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -36,25 +35,11 @@ async fn main() -> Result<()> {
         tuns[0].netmask().unwrap(),
     );
 
-    println!("---------------------");
-    println!("ping 10.1.0.2 to test");
-    println!("---------------------");
-
-    let mut tuns = tuns.into_iter();
-    let mut tun0 = tuns.next().unwrap();
-    let mut tun1 = tuns.next().unwrap();
-    let mut tun2 = tuns.next().unwrap();
-
-    let mut buf0 = [0u8; 1024];
-    let mut buf1 = [0u8; 1024];
-    let mut buf2 = [0u8; 1024];
+    let tun = MultiQueueTun::new(tuns)?;
 
+    let mut buf = [0u8; 1024];
     loop {
-        let (buf, id) = tokio::select! {
-            Ok(n) = tun0.read(&mut buf0) => (&buf0[..n], 0),
-            Ok(n) = tun1.read(&mut buf1) => (&buf1[..n], 1),
-            Ok(n) = tun2.read(&mut buf2) => (&buf2[..n], 2),
-        };
-        println!("reading {} bytes from tuns[{}]: {:?}", buf.len(), id, buf);
+        let (id, n) = tun.recv(&mut buf).await?;
+        println!("reading {} bytes from tuns[{}]: {:?}", n, id, &buf[..n]);
     }
 }
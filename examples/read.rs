@@ -1,8 +1,7 @@
 use std::net::Ipv4Addr;
 use std::os::unix::io::AsRawFd;
-use tokio::io::AsyncReadExt;
 use tokio_tun::result::Result;
-use tokio_tun::TunBuilder;
+use tokio_tun::{Responder, TunBuilder};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -38,11 +37,12 @@ async fn main() -> Result<()> {
     println!("ping 10.1.0.2 to test");
     println!("---------------------");
 
-    let (mut reader, mut _writer) = tokio::io::split(tun);
+    // Answers the echo requests, all other packets are returned by `recv`.
+    let tun = Responder::new(tun).address(Ipv4Addr::new(10, 1, 0, 2));
 
     let mut buf = [0u8; 1024];
     loop {
-        let n = reader.read(&mut buf).await?;
+        let n = tun.recv(&mut buf).await?;
         println!("reading {} bytes: {:?}", n, &buf[..n]);
     }
 }
@@ -0,0 +1,256 @@
+//! Tun/Tap devices driven by the [`async-io`](https://crates.io/crates/async-io) reactor, which
+//! is used by `async-std` and `smol`.
+//!
+//! ```no_run
+//! # fn main() -> tokio_tun::result::Result<()> {
+//! use tokio_tun::TunBuilder;
+//!
+//! async_io::block_on(async {
+//!     let tun = TunBuilder::new().name("tun0").up().try_build_async_io()?;
+//!
+//!     let mut buf = [0u8; 1500];
+//!     let n = tun.recv(&mut buf).await?;
+//!     println!("reading {} bytes: {:?}", n, &buf[..n]);
+//!     Ok(())
+//! })
+//! # }
+//! ```
+
+use crate::linux::alloc;
+use crate::linux::interface::Interface;
+use crate::linux::io::TunIo;
+use crate::linux::params::Params;
+use crate::result::Result;
+use crate::stats::{QueueCounters, QueueStats};
+use ::async_io::Async;
+use futures_io::{AsyncRead, AsyncWrite};
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Represents a Tun/Tap device registered with the `async-io` reactor. Use
+/// [`try_build_async_io`](../struct.TunBuilder.html#method.try_build_async_io) to create a new
+/// instance of [`Tun`](struct.Tun.html).
+///
+/// It implements `AsyncRead` and `AsyncWrite` of the `futures` crate.
+pub struct Tun {
+    iface: Arc<Interface>,
+    io: Async<TunIo>,
+    counters: QueueCounters,
+}
+
+impl AsRawFd for Tun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+impl AsyncRead for Tun {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match self.try_recv(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    ready!(self.io.poll_readable(cx))?
+                }
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Tun {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match self.try_send(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    ready!(self.io.poll_writable(cx))?
+                }
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Tun {
+    /// Creates a new instance of Tun/Tap device.
+    pub(crate) fn new(params: Params) -> Result<Self> {
+        Ok(Self::new_mq(params, 1)?.remove(0))
+    }
+
+    /// Creates a new instance of Tun/Tap device.
+    pub(crate) fn new_mq(params: Params, queues: usize) -> Result<Vec<Self>> {
+        let (iface, queues) = alloc::allocate(params, queues)?;
+        let iface = Arc::new(iface);
+        queues
+            .into_iter()
+            .map(|io| Self::with(iface.clone(), io))
+            .collect()
+    }
+
+    fn with(iface: Arc<Interface>, io: TunIo) -> Result<Self> {
+        Ok(Self {
+            iface,
+            io: Async::new(io)?,
+            counters: Default::default(),
+        })
+    }
+
+    /// Creates a new instance of [`Tun`](struct.Tun.html) from a file descriptor which is
+    /// already attached to a Tun/Tap device.
+    ///
+    /// See [`Tun::from_fd`](../struct.Tun.html#method.from_fd) of the tokio device.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let (iface, io) = alloc::attach(vec![fd])?.remove(0);
+        Self::with(iface, io)
+    }
+
+    /// Deregisters the device from the reactor and returns its file descriptor, which is left
+    /// in non-blocking mode.
+    ///
+    /// The device is not removed as long as the returned file descriptor is open.
+    pub fn into_fd(self) -> OwnedFd {
+        // Dropping the source deregisters it and ignores failures, as the tokio device does.
+        let io = self.io.get_ref().share();
+        drop(self.io);
+        io.into_fd()
+    }
+
+    /// Receives a packet from the Tun/Tap interface
+    ///
+    /// This method takes &self, so it is possible to call this method concurrently with other methods on this struct.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read_with(|_| self.try_recv(buf)).await
+    }
+
+    /// Sends a packet to the Tun/Tap interface
+    ///
+    /// This method takes &self, so it is possible to call this method concurrently with other methods on this struct.
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write_with(|_| self.try_send(buf)).await
+    }
+
+    /// Try to receive a packet from the Tun/Tap interface
+    ///
+    /// When there is no pending data, `Err(io::ErrorKind::WouldBlock)` is returned.
+    pub fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.io.get_ref().recv(buf);
+        self.counters.rx(&res);
+        res
+    }
+
+    /// Try to send a packet to the Tun/Tap interface
+    ///
+    /// When the socket buffer is full, `Err(io::ErrorKind::WouldBlock)` is returned.
+    pub fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.io.get_ref().send(buf);
+        self.counters.tx(&res);
+        res
+    }
+
+    /// Returns the counters of packets received and sent through this queue.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.counters.snapshot()
+    }
+
+    tun_methods!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::alloc::tests::privileged;
+    use crate::TunBuilder;
+    use std::future::poll_fn;
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Returns a device backed by one end of a socket pair, and the other end.
+    ///
+    /// The loopback interface stands in for the device, as only the queue is exercised.
+    fn pair() -> (Tun, Async<TunIo>) {
+        let mut fds = [0; 2];
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        assert_eq!(ret, 0, "{}", io::Error::last_os_error());
+        let (a, b) = unsafe { (TunIo::from_raw_fd(fds[0]), TunIo::from_raw_fd(fds[1])) };
+        let iface = Interface::attached(vec![a.downgrade()], "lo", 0).unwrap();
+        (
+            Tun::with(Arc::new(iface), a).unwrap(),
+            Async::new(b).unwrap(),
+        )
+    }
+
+    #[test]
+    fn read_and_write_packets() {
+        let (mut tun, kernel) = pair();
+        let mut buf = [0u8; 64];
+        thread::scope(|s| {
+            s.spawn(|| {
+                // Wait for the device to block in the reactor first.
+                thread::sleep(Duration::from_millis(20));
+                kernel.get_ref().send(&[4, 5]).unwrap();
+            });
+            ::async_io::block_on(async {
+                let n = poll_fn(|cx| Pin::new(&mut tun).poll_read(cx, &mut buf))
+                    .await
+                    .unwrap();
+                assert_eq!(&buf[..n], &[4, 5]);
+
+                let n = poll_fn(|cx| Pin::new(&mut tun).poll_write(cx, &[1, 2, 3]))
+                    .await
+                    .unwrap();
+                assert_eq!(n, 3);
+                let n = kernel.read_with(|io| io.recv(&mut buf)).await.unwrap();
+                assert_eq!(&buf[..n], &[1, 2, 3]);
+            });
+        });
+        assert_eq!(tun.queue_stats().rx_packets, 1);
+        assert_eq!(tun.queue_stats().tx_packets, 1);
+
+        // The file descriptor is still usable after deregistering it.
+        let io = TunIo::from(tun.into_fd());
+        io.send(&[6]).unwrap();
+        assert_eq!(kernel.get_ref().recv(&mut buf).unwrap(), 1);
+    }
+
+    #[test]
+    fn into_fd_keeps_device() {
+        if !privileged() {
+            return;
+        }
+        let tun = TunBuilder::new()
+            .name("asyncio0")
+            .try_build_async_io()
+            .unwrap();
+        let fd = tun.into_fd();
+        let tun = Tun::from_fd(fd).unwrap();
+        assert_eq!(tun.name(), "asyncio0");
+        let err = tun.try_recv(&mut [0u8; 64]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+}
@@ -0,0 +1,525 @@
+//! A `tunctl`-like command-line tool to manage Tun/Tap devices.
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+use tokio_tun::packet::{Network, Packet, TcpSegment, Transport};
+use tokio_tun::result::Result;
+use tokio_tun::{DeviceKind, Tun, TunBuilder, TunConfig};
+
+#[derive(Parser)]
+#[command(name = "tokio-tun", version, about = "Manage Tun/Tap devices")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Creates a device, which is kept until interrupted unless it is persistent.
+    Create {
+        /// Name of device, it is set by kernel if omitted.
+        #[arg(long, default_value = "")]
+        name: String,
+        /// Creates a TAP device instead of a TUN device.
+        #[arg(long)]
+        tap: bool,
+        /// Sets `IFF_NO_PI`, so no packet information is prepended to packets.
+        #[arg(long)]
+        no_pi: bool,
+        #[arg(long)]
+        mtu: Option<i32>,
+        /// Address along with its prefix length, e.g. `10.0.0.1/24` or `fd00::1/64`. It may be
+        /// repeated for IPv6 addresses, but a device has a single IPv4 address.
+        #[arg(long = "addr", value_parser = parse_cidr)]
+        addresses: Vec<(IpAddr, u8)>,
+        /// Keeps the device after this command exits.
+        #[arg(long)]
+        persist: bool,
+        /// Numeric UID of the owner of device.
+        #[arg(long)]
+        owner: Option<i32>,
+        /// Numeric GID of the group of device.
+        #[arg(long)]
+        group: Option<i32>,
+        /// Leaves the device down.
+        #[arg(long)]
+        down: bool,
+    },
+    /// Deletes a persistent device.
+    Delete { name: String },
+    /// Shows the configuration of a device, or only the features of the tun driver.
+    Show { name: Option<String> },
+    /// Prints the packets of a device, which are captured without attaching to it if it exists,
+    /// otherwise it is created and the packets are read from it.
+    Dump {
+        name: String,
+        /// Creates a TAP device if the device does not exist.
+        #[arg(long)]
+        tap: bool,
+    },
+}
+
+/// Flags reported by `TUNGETIFF` and `TUNGETFEATURES` along with their names.
+const FLAGS: &[(i32, &str)] = &[
+    (libc::IFF_TUN, "tun"),
+    (libc::IFF_TAP, "tap"),
+    (libc::IFF_NAPI, "napi"),
+    (libc::IFF_NAPI_FRAGS, "napi_frags"),
+    (libc::IFF_NO_CARRIER, "no_carrier"),
+    (libc::IFF_MULTI_QUEUE, "multi_queue"),
+    (libc::IFF_ATTACH_QUEUE, "attach_queue"),
+    (libc::IFF_DETACH_QUEUE, "detach_queue"),
+    (libc::IFF_PERSIST, "persist"),
+    (libc::IFF_NO_PI, "no_pi"),
+    (libc::IFF_ONE_QUEUE, "one_queue"),
+    (libc::IFF_VNET_HDR, "vnet_hdr"),
+    (libc::IFF_TUN_EXCL, "tun_excl"),
+];
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    if let Err(err) = run(Cli::parse().command).await {
+        eprintln!("tokio-tun: {}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Create {
+            name,
+            tap,
+            no_pi,
+            mtu,
+            addresses,
+            persist,
+            owner,
+            group,
+            down,
+        } => {
+            if addresses
+                .iter()
+                .filter(|(address, _)| address.is_ipv4())
+                .count()
+                > 1
+            {
+                let mut cli = Cli::command();
+                cli.build();
+                cli.find_subcommand_mut("create")
+                    .unwrap()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--addr accepts only one IPv4 address",
+                    )
+                    .exit();
+            }
+            let mut builder = TunBuilder::new().name(name).tap(tap).packet_info(!no_pi);
+            if let Some(mtu) = mtu {
+                builder = builder.mtu(mtu);
+            }
+            if let Some(owner) = owner {
+                builder = builder.owner(owner);
+            }
+            if let Some(group) = group {
+                builder = builder.group(group);
+            }
+            for (address, prefix_len) in addresses {
+                builder = match address {
+                    IpAddr::V4(address) => builder.address(address).netmask(netmask(prefix_len)),
+                    IpAddr::V6(address) => builder.ipv6_address(address, prefix_len),
+                };
+            }
+            if persist {
+                builder = builder.persist();
+            }
+            if !down {
+                builder = builder.up();
+            }
+            let tun = builder.try_build()?;
+            println!("{}", tun.name());
+            if !persist {
+                tokio::signal::ctrl_c().await?;
+            }
+        }
+        Command::Delete { name } => attach(&name)?.set_persist(false)?,
+        Command::Show { name } => {
+            if let Some(name) = name {
+                show(&TunConfig::from_device(&name)?);
+            }
+            println!("features: {}", flag_names(Tun::features()?));
+        }
+        Command::Dump { name, tap } => {
+            if tun_flags(&name)?.is_some() {
+                let config = TunConfig::from_device(&name)?;
+                return capture(&name, config.kind).await;
+            }
+            let tun = TunBuilder::new().name(name).tap(tap).up().try_build()?;
+            let config = tun.config()?;
+            let mut buf = vec![0; tun.mtu()? as usize + 18 + 4];
+            loop {
+                let n = tun.recv(&mut buf).await?;
+                println!("{}", describe(&buf[..n], config.kind, config.packet_info));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the `IFF_*` flags the device named `name` was created with, or `None` if there is no
+/// such device.
+fn tun_flags(name: &str) -> Result<Option<i32>> {
+    match std::fs::read_to_string(format!("/sys/class/net/{}/tun_flags", name)) {
+        Ok(flags) => Ok(Some(i32::from_str_radix(
+            flags.trim().trim_start_matches("0x"),
+            16,
+        )?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Attaches to the existing device named `name` with the flags it was created with.
+fn attach(name: &str) -> Result<Tun> {
+    let flags = tun_flags(name)?
+        .ok_or_else(|| std::io::Error::other(format!("{}: no such Tun/Tap device", name)))?;
+    let builder = TunBuilder::new()
+        .name(name)
+        .tap(flags & libc::IFF_TAP != 0)
+        .packet_info(flags & libc::IFF_NO_PI == 0)
+        .vnet_hdr(flags & libc::IFF_VNET_HDR != 0);
+    if flags & libc::IFF_MULTI_QUEUE != 0 {
+        // A multi-queue device can only be attached with `IFF_MULTI_QUEUE`, which is set for
+        // more than one queue.
+        Ok(builder.try_build_mq(2)?.swap_remove(0))
+    } else {
+        builder.try_build()
+    }
+}
+
+/// Prints the packets sent and received by the existing device `name` through a packet
+/// socket, so that the process which holds the device still receives all of them.
+async fn capture(name: &str, kind: DeviceKind) -> Result<()> {
+    let protocol = (libc::ETH_P_ALL as u16).to_be();
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            protocol as i32,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let name = CString::new(name)?;
+    let addr = libc::sockaddr_ll {
+        sll_family: libc::AF_PACKET as u16,
+        sll_protocol: protocol,
+        sll_ifindex: unsafe { libc::if_nametoindex(name.as_ptr()) } as i32,
+        ..unsafe { mem::zeroed() }
+    };
+    let len = mem::size_of_val(&addr) as libc::socklen_t;
+    if unsafe { libc::bind(fd.as_raw_fd(), &addr as *const _ as *const _, len) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let fd = unsafe { AsyncFd::register(fd) }?;
+    let mut buf = vec![0; 65536];
+    loop {
+        let mut guard = fd.readable().await?;
+        let res = guard.try_io(|fd| {
+            let n = unsafe {
+                libc::recv(
+                    fd.as_raw_fd(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                    libc::MSG_TRUNC,
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(n as usize)
+        });
+        if let Ok(n) = res {
+            let n = n?.min(buf.len());
+            // Packets of a device carry no packet information, as they are not read from it.
+            println!("{}", describe(&buf[..n], kind, false));
+        }
+    }
+}
+
+fn show(config: &TunConfig) {
+    println!("{}: {}", config.name, flag_names(config_flags(config)));
+    println!(
+        "    state {} mtu {}",
+        if config.up { "UP" } else { "DOWN" },
+        config.mtu.unwrap_or_default()
+    );
+    let id = |id: Option<i32>| id.map_or_else(|| "-".into(), |id| id.to_string());
+    println!("    owner {} group {}", id(config.owner), id(config.group));
+    if let Some(address) = config.address {
+        let prefix_len = config
+            .netmask
+            .map_or(32, |mask| u32::from(mask).count_ones());
+        print!("    inet {}/{}", address, prefix_len);
+        if let Some(destination) = config.destination {
+            print!(" peer {}", destination);
+        }
+        if let Some(broadcast) = config.broadcast {
+            print!(" brd {}", broadcast);
+        }
+        println!();
+    }
+    for (address, prefix_len) in config.ipv6_addresses.iter() {
+        println!("    inet6 {}/{}", address, prefix_len);
+    }
+    if let Some(bridge) = &config.bridge {
+        println!("    master {}", bridge);
+    }
+}
+
+/// Rebuilds the `IFF_*` flags of a device from its configuration.
+fn config_flags(config: &TunConfig) -> i32 {
+    let mut flags = match config.kind {
+        DeviceKind::Tun => libc::IFF_TUN,
+        DeviceKind::Tap => libc::IFF_TAP,
+    };
+    for (set, flag) in [
+        (!config.packet_info, libc::IFF_NO_PI),
+        (config.vnet_hdr, libc::IFF_VNET_HDR),
+        (config.persist, libc::IFF_PERSIST),
+        (config.queues > 1, libc::IFF_MULTI_QUEUE),
+    ] {
+        if set {
+            flags |= flag;
+        }
+    }
+    flags
+}
+
+fn flag_names(flags: i32) -> String {
+    FLAGS
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_cidr(s: &str) -> std::result::Result<(IpAddr, u8), String> {
+    let (address, prefix_len) = match s.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len)),
+        None => (s, None),
+    };
+    let address: IpAddr = address.parse().map_err(|err| format!("{}", err))?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    let prefix_len = match prefix_len {
+        Some(prefix_len) => prefix_len.parse().map_err(|err| format!("{}", err))?,
+        None => max,
+    };
+    if prefix_len > max {
+        return Err(format!("prefix length is greater than {}", max));
+    }
+    Ok((address, prefix_len))
+}
+
+fn netmask(prefix_len: u8) -> Ipv4Addr {
+    Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0))
+}
+
+/// Describes a packet read from a device in a single line.
+fn describe(buf: &[u8], kind: DeviceKind, packet_info: bool) -> String {
+    let Some(packet) = Packet::parse(buf, kind, packet_info) else {
+        return format!("malformed, {} bytes", buf.len());
+    };
+    let len = match packet.ethernet() {
+        Some(frame) => frame.payload().len(),
+        None if packet_info => buf.len() - 4,
+        None => buf.len(),
+    };
+    let network = describe_network(packet.network(), len);
+    match packet.ethernet() {
+        Some(frame) => {
+            let vlan: String = frame
+                .vlan_tags()
+                .map(|tag| format!(" vlan {}", tag.vid))
+                .collect();
+            format!(
+                "{} > {}{}, {}",
+                frame.source(),
+                frame.destination(),
+                vlan,
+                network
+            )
+        }
+        None => network,
+    }
+}
+
+/// Describes the network layer of a packet, which is `len` bytes long.
+fn describe_network(network: Network<'_>, len: usize) -> String {
+    let (src, dst, protocol): (IpAddr, IpAddr, u8) = match network {
+        Network::Ipv4(ip) => (ip.source().into(), ip.destination().into(), ip.protocol()),
+        Network::Ipv6(ip) => (ip.source().into(), ip.destination().into(), ip.protocol()),
+        Network::Arp(arp) => {
+            let (sender, target) = (arp.sender_ip(), arp.target_ip());
+            return match (arp.operation(), sender, target, arp.sender_mac()) {
+                (1, Some(sender), Some(target), _) => {
+                    format!("ARP who-has {} tell {}, {} bytes", target, sender, len)
+                }
+                (2, Some(sender), _, Some(mac)) => {
+                    format!("ARP reply {} is-at {}, {} bytes", sender, mac, len)
+                }
+                (op, ..) => format!("ARP op {}, {} bytes", op, len),
+            };
+        }
+        Network::Other { ethertype, .. } => {
+            return format!("ethertype {:#06x}, {} bytes", ethertype, len)
+        }
+    };
+    match network.transport() {
+        Some(Transport::Tcp(tcp)) => {
+            let names: String = [
+                (TcpSegment::SYN, 'S'),
+                (TcpSegment::ACK, '.'),
+                (TcpSegment::FIN, 'F'),
+                (TcpSegment::RST, 'R'),
+                (TcpSegment::PSH, 'P'),
+            ]
+            .iter()
+            .filter(|&&(flag, _)| tcp.has_flags(flag))
+            .map(|(_, name)| name)
+            .collect();
+            format!(
+                "{}.{} > {}.{}: TCP [{}], {} bytes",
+                src,
+                tcp.src_port(),
+                dst,
+                tcp.dst_port(),
+                names,
+                len
+            )
+        }
+        Some(Transport::Udp(udp)) => format!(
+            "{}.{} > {}.{}: UDP, {} bytes",
+            src,
+            udp.src_port(),
+            dst,
+            udp.dst_port(),
+            len
+        ),
+        Some(Transport::Icmp(icmp)) => {
+            let kind = match icmp.icmp_type() {
+                0 => "echo reply".into(),
+                8 => "echo request".into(),
+                3 => "destination unreachable".into(),
+                kind => format!("type {}", kind),
+            };
+            format!("{} > {}: ICMP {}, {} bytes", src, dst, kind, len)
+        }
+        Some(Transport::Icmpv6(icmp)) => {
+            let kind = match icmp.icmp_type() {
+                129 => "echo reply".into(),
+                128 => "echo request".into(),
+                1 => "destination unreachable".into(),
+                133 => "router solicitation".into(),
+                134 => "router advertisement".into(),
+                135 => "neighbor solicitation".into(),
+                136 => "neighbor advertisement".into(),
+                kind => format!("type {}", kind),
+            };
+            format!("{} > {}: ICMP6 {}, {} bytes", src, dst, kind, len)
+        }
+        Some(Transport::Other { .. }) | None => {
+            format!("{} > {}: protocol {}, {} bytes", src, dst, protocol, len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+    use tokio_tun::packet::PacketBuilder;
+    use tokio_tun::MacAddr;
+
+    #[test]
+    fn cidr() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(parse_cidr("10.0.0.1/24"), Ok((ip("10.0.0.1"), 24)));
+        assert_eq!(parse_cidr("10.0.0.1"), Ok((ip("10.0.0.1"), 32)));
+        assert_eq!(parse_cidr("0.0.0.0/0"), Ok((ip("0.0.0.0"), 0)));
+        assert_eq!(parse_cidr("10.0.0.1/32"), Ok((ip("10.0.0.1"), 32)));
+        assert_eq!(parse_cidr("fd00::1/64"), Ok((ip("fd00::1"), 64)));
+        assert_eq!(parse_cidr("fd00::1"), Ok((ip("fd00::1"), 128)));
+
+        for invalid in [
+            "10.0.0.1/33",
+            "fd00::1/129",
+            "10.0.0.1/",
+            "10.0.0.1/-1",
+            "10.0.0.1/24/8",
+            "10.0.0/24",
+            "vpn0",
+            "",
+        ] {
+            assert!(parse_cidr(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn netmasks() {
+        assert_eq!(netmask(0), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(netmask(1), Ipv4Addr::new(128, 0, 0, 0));
+        assert_eq!(netmask(24), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(netmask(31), Ipv4Addr::new(255, 255, 255, 254));
+        assert_eq!(netmask(32), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn describe_packets() {
+        let src = Ipv4Addr::new(10, 0, 0, 2);
+        let dst = Ipv4Addr::new(10, 0, 0, 1);
+        let udp = PacketBuilder::ipv4(src, dst)
+            .udp(1234, 53)
+            .build(b"query")
+            .unwrap();
+        assert_eq!(
+            describe(&udp, DeviceKind::Tun, false),
+            "10.0.0.2.1234 > 10.0.0.1.53: UDP, 33 bytes"
+        );
+
+        let syn = PacketBuilder::ipv6(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST)
+            .tcp(40000, 80)
+            .flags(TcpSegment::SYN)
+            .packet_info(true)
+            .build(&[])
+            .unwrap();
+        assert_eq!(
+            describe(&syn, DeviceKind::Tun, true),
+            "::1.40000 > ::1.80: TCP [S], 60 bytes"
+        );
+
+        let mac = |last| MacAddr::from([2, 0, 0, 0, 0, last]);
+        let ping = PacketBuilder::ipv4(src, dst)
+            .ethernet(mac(2), mac(1))
+            .echo_request(1, 1)
+            .build(&[0; 8])
+            .unwrap();
+        assert_eq!(
+            describe(&ping, DeviceKind::Tap, false),
+            "02:00:00:00:00:02 > 02:00:00:00:00:01, 10.0.0.2 > 10.0.0.1: ICMP echo request, 36 bytes"
+        );
+
+        assert_eq!(
+            describe(&[0x45, 0, 0], DeviceKind::Tun, false),
+            "malformed, 3 bytes"
+        );
+    }
+}
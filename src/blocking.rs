@@ -0,0 +1,357 @@
+//! Blocking Tun/Tap devices, which do not require an asynchronous runtime.
+//!
+//! ```no_run
+//! # fn main() -> tokio_tun::result::Result<()> {
+//! use std::time::Duration;
+//! use tokio_tun::TunBuilder;
+//!
+//! let tun = TunBuilder::new().name("tun0").up().try_build_blocking()?;
+//! tun.set_read_timeout(Some(Duration::from_secs(1)))?;
+//!
+//! let mut buf = [0u8; 1500];
+//! match tun.recv(&mut buf) {
+//!     Ok(n) => println!("reading {} bytes: {:?}", n, &buf[..n]),
+//!     Err(err) if err.kind() == std::io::ErrorKind::TimedOut => println!("no packet"),
+//!     Err(err) => return Err(err.into()),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::linux::alloc;
+use crate::linux::interface::Interface;
+use crate::linux::io::TunIo;
+use crate::linux::params::Params;
+use crate::result::Result;
+use crate::stats::{QueueCounters, QueueStats};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Represents a blocking Tun/Tap device. Use
+/// [`try_build_blocking`](../struct.TunBuilder.html#method.try_build_blocking) to create a
+/// new instance of [`Tun`](struct.Tun.html).
+///
+/// Reading and writing wait for the device with `poll(2)`, optionally bounded by a timeout.
+pub struct Tun {
+    iface: Arc<Interface>,
+    io: TunIo,
+    counters: QueueCounters,
+    /// Timeouts in milliseconds as passed to `poll(2)`, where `-1` waits forever.
+    read_timeout: AtomicI32,
+    write_timeout: AtomicI32,
+}
+
+impl AsRawFd for Tun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+impl Read for Tun {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recv(buf)
+    }
+}
+
+impl Write for Tun {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Tun {
+    /// Creates a new instance of Tun/Tap device.
+    pub(crate) fn new(params: Params) -> Result<Self> {
+        Ok(Self::new_mq(params, 1)?.remove(0))
+    }
+
+    /// Creates a new instance of Tun/Tap device.
+    pub(crate) fn new_mq(params: Params, queues: usize) -> Result<Vec<Self>> {
+        let (iface, queues) = alloc::allocate(params, queues)?;
+        let iface = Arc::new(iface);
+        Ok(queues
+            .into_iter()
+            .map(|io| Self::with(iface.clone(), io))
+            .collect())
+    }
+
+    fn with(iface: Arc<Interface>, io: TunIo) -> Self {
+        Self {
+            iface,
+            io,
+            counters: Default::default(),
+            read_timeout: AtomicI32::new(-1),
+            write_timeout: AtomicI32::new(-1),
+        }
+    }
+
+    /// Creates a new instance of [`Tun`](struct.Tun.html) from a file descriptor which is
+    /// already attached to a Tun/Tap device.
+    ///
+    /// See [`Tun::from_fd`](../struct.Tun.html#method.from_fd) of the asynchronous device.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let (iface, io) = alloc::attach(vec![fd])?.remove(0);
+        Ok(Self::with(iface, io))
+    }
+
+    /// Returns the file descriptor of device, which is left in non-blocking mode.
+    ///
+    /// The device is not removed as long as the returned file descriptor is open.
+    pub fn into_fd(self) -> OwnedFd {
+        self.io.into_fd()
+    }
+
+    /// Receives a packet from the Tun/Tap interface, waiting at most for the read timeout.
+    ///
+    /// If the timeout elapses, `Err(io::ErrorKind::TimedOut)` is returned.
+    ///
+    /// This method takes &self, so it is possible to call this method concurrently with other methods on this struct.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = deadline(&self.read_timeout);
+        loop {
+            match self.try_recv(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.wait(libc::POLLIN, deadline)?
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Sends a packet to the Tun/Tap interface, waiting at most for the write timeout.
+    ///
+    /// If the timeout elapses, `Err(io::ErrorKind::TimedOut)` is returned.
+    ///
+    /// This method takes &self, so it is possible to call this method concurrently with other methods on this struct.
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let deadline = deadline(&self.write_timeout);
+        loop {
+            match self.try_send(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.wait(libc::POLLOUT, deadline)?
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Try to receive a packet from the Tun/Tap interface
+    ///
+    /// When there is no pending data, `Err(io::ErrorKind::WouldBlock)` is returned.
+    pub fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.io.recv(buf);
+        self.counters.rx(&res);
+        res
+    }
+
+    /// Try to send a packet to the Tun/Tap interface
+    ///
+    /// When the socket buffer is full, `Err(io::ErrorKind::WouldBlock)` is returned.
+    pub fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.io.send(buf);
+        self.counters.tx(&res);
+        res
+    }
+
+    /// Sets the timeout of [`recv`](struct.Tun.html#method.recv), `None` waits forever.
+    ///
+    /// A zero duration is rejected, use [`try_recv`](struct.Tun.html#method.try_recv) instead.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.store(millis(timeout)?, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Sets the timeout of [`send`](struct.Tun.html#method.send), `None` waits forever.
+    ///
+    /// A zero duration is rejected, use [`try_send`](struct.Tun.html#method.try_send) instead.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.write_timeout
+            .store(millis(timeout)?, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the counters of packets received and sent through this queue.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.counters.snapshot()
+    }
+
+    tun_methods!();
+
+    /// Waits until the device is ready for `events` or the deadline passes, where `None` waits
+    /// forever.
+    ///
+    /// The device may turn out not to be ready after all, e.g. if another thread received the
+    /// packet first, so callers retry with the same deadline rather than restarting the timeout.
+    fn wait(&self, events: i16, deadline: Option<Instant>) -> io::Result<()> {
+        let mut fd = libc::pollfd {
+            fd: self.io.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => remaining(deadline),
+                None => -1,
+            };
+            match unsafe { libc::poll(&mut fd, 1, timeout) } {
+                0 => return Err(io::ErrorKind::TimedOut.into()),
+                n if n > 0 => return Ok(()),
+                _ => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the point in time at which a call bounded by `timeout` gives up, see
+/// [`millis`](fn.millis.html).
+fn deadline(timeout: &AtomicI32) -> Option<Instant> {
+    let millis = timeout.load(Ordering::Relaxed);
+    (millis >= 0).then(|| Instant::now() + Duration::from_millis(millis as u64))
+}
+
+/// Returns the milliseconds left until `deadline` for `poll(2)`, rounding up like
+/// [`millis`](fn.millis.html), or `0` once it has passed.
+fn remaining(deadline: Instant) -> i32 {
+    let left = deadline.saturating_duration_since(Instant::now());
+    left.as_nanos()
+        .div_ceil(1_000_000)
+        .try_into()
+        .unwrap_or(i32::MAX)
+}
+
+/// Converts a timeout to milliseconds for `poll(2)`, rounding up so that it never becomes 0.
+fn millis(timeout: Option<Duration>) -> io::Result<i32> {
+    match timeout {
+        None => Ok(-1),
+        Some(timeout) if timeout.is_zero() => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot set a 0 duration timeout",
+        )),
+        Some(timeout) => {
+            let millis = timeout.as_nanos().div_ceil(1_000_000);
+            Ok(millis.try_into().unwrap_or(i32::MAX))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+
+    /// Returns a device backed by one end of a socket pair, and the other end.
+    ///
+    /// The loopback interface stands in for the device, as only the queue is exercised.
+    fn pair() -> (Tun, TunIo) {
+        let mut fds = [0; 2];
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        assert_eq!(ret, 0, "{}", io::Error::last_os_error());
+        let (a, b) = unsafe { (TunIo::from_raw_fd(fds[0]), TunIo::from_raw_fd(fds[1])) };
+        let iface = Interface::attached(vec![a.downgrade()], "lo", 0).unwrap();
+        (Tun::with(Arc::new(iface), a), b)
+    }
+
+    #[test]
+    fn millis_rounds_up() {
+        assert_eq!(millis(None).unwrap(), -1);
+        assert_eq!(millis(Some(Duration::from_nanos(1))).unwrap(), 1);
+        assert_eq!(millis(Some(Duration::from_micros(1500))).unwrap(), 2);
+        assert_eq!(millis(Some(Duration::from_secs(2))).unwrap(), 2000);
+        assert_eq!(millis(Some(Duration::MAX)).unwrap(), i32::MAX);
+        let err = millis(Some(Duration::ZERO)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn zero_timeout_is_rejected() {
+        let (tun, _) = pair();
+        let err = tun.set_read_timeout(Some(Duration::ZERO)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = tun.set_write_timeout(Some(Duration::ZERO)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // The previous timeout is kept.
+        assert_eq!(tun.read_timeout.load(Ordering::Relaxed), -1);
+        assert_eq!(tun.write_timeout.load(Ordering::Relaxed), -1);
+    }
+
+    #[test]
+    fn recv_times_out() {
+        let (tun, _kernel) = pair();
+        tun.set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let start = Instant::now();
+        let err = tun.recv(&mut [0u8; 64]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn recv_without_timeout_waits() {
+        let (tun, kernel) = pair();
+        tun.set_read_timeout(None).unwrap();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                kernel.send(&[1, 2, 3]).unwrap();
+            });
+            let mut buf = [0u8; 64];
+            assert_eq!(tun.recv(&mut buf).unwrap(), 3);
+            assert_eq!(&buf[..3], &[1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn recv_keeps_deadline_when_losing_a_packet() {
+        let (tun, kernel) = pair();
+        tun.set_read_timeout(Some(Duration::from_millis(400)))
+            .unwrap();
+        let start = Instant::now();
+        // Both readers wake up for the single packet, the one which does not get it must still
+        // give up 400ms after it started rather than wait another 400ms.
+        let results = thread::scope(|s| {
+            let readers: Vec<_> = (0..2)
+                .map(|_| s.spawn(|| tun.recv(&mut [0u8; 64]).map_err(|err| err.kind())))
+                .collect();
+            thread::sleep(Duration::from_millis(300));
+            kernel.send(&[1]).unwrap();
+            readers
+                .into_iter()
+                .map(|reader| reader.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert!(results.contains(&Ok(1)));
+        assert!(results.contains(&Err(io::ErrorKind::TimedOut)));
+        assert!(start.elapsed() < Duration::from_millis(600));
+    }
+
+    #[test]
+    fn send_times_out() {
+        let (tun, _kernel) = pair();
+        tun.set_write_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        // Fill the socket buffer, as nobody reads from the other end.
+        while tun.try_send(&[0; 1024]).is_ok() {}
+        let err = tun.send(&[0; 1024]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
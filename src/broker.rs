@@ -0,0 +1,474 @@
+//! Privilege-separated allocation of Tun/Tap devices.
+//!
+//! A [`Broker`](struct.Broker.html) runs with `CAP_NET_ADMIN` and listens on a Unix socket.
+//! Unprivileged clients send the configuration of a device with
+//! [`TunBuilder::try_build_via`](../struct.TunBuilder.html#method.try_build_via), the broker
+//! checks it against its [`Policy`](struct.Policy.html), creates the device and passes its file
+//! descriptors back to the client.
+//!
+//! ```no_run
+//! # async fn broker() -> tokio_tun::result::Result<()> {
+//! use tokio_tun::broker::{Broker, Policy};
+//!
+//! let policy = Policy::new()
+//!     .uid(1000)
+//!     .name_prefix("vpn")
+//!     .network("10.8.0.0".parse()?, 16)
+//!     .max_queues(4);
+//! let listener = tokio::net::UnixListener::bind("/run/tun-broker.sock")?;
+//! Broker::new(policy).serve(listener).await?;
+//! # Ok(())
+//! # }
+//! # async fn client() -> tokio_tun::result::Result<()> {
+//! use tokio_tun::TunBuilder;
+//!
+//! let tun = TunBuilder::new()
+//!     .name("vpn0")
+//!     .address("10.8.0.1".parse()?)
+//!     .up()
+//!     .try_build_via("/run/tun-broker.sock")
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests and replies are JSON documents preceded by their length as a big-endian `u32`.
+//! A successful reply is followed by the file descriptors, see [`fdpass`](../fdpass/index.html).
+
+use crate::builder::TunBuilder;
+use crate::config::TunConfig;
+use crate::fdpass;
+use crate::result::Result;
+use crate::route::Route;
+use crate::tun::Tun;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Maximum length of a request or a reply.
+const MAX_FRAME: usize = 64 * 1024;
+
+/// Default time a client has to send its request after connecting.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Represents the set of devices which clients of a [`Broker`](struct.Broker.html) may
+/// create. An empty policy allows nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    uids: Vec<u32>,
+    name_prefixes: Vec<String>,
+    networks: Vec<(IpAddr, u8)>,
+    max_queues: usize,
+    persist: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            uids: Vec::new(),
+            name_prefixes: Vec::new(),
+            networks: Vec::new(),
+            max_queues: 1,
+            persist: false,
+        }
+    }
+}
+
+impl Policy {
+    /// Creates a new instance of [`Policy`](struct.Policy.html) which allows nothing.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allows clients running as user `uid` (as reported by `SO_PEERCRED`).
+    ///
+    /// A client may only request itself as the owner of device.
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.uids.push(uid);
+        self
+    }
+
+    /// Allows device names starting with `prefix`. Names chosen by kernel are never allowed.
+    pub fn name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.name_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Allows addresses and routes within `network/prefix_len`.
+    pub fn network(mut self, network: IpAddr, prefix_len: u8) -> Self {
+        self.networks.push((network, prefix_len));
+        self
+    }
+
+    /// Sets the maximum number of queues of a device. Default value is 1.
+    pub fn max_queues(mut self, max_queues: usize) -> Self {
+        self.max_queues = max_queues;
+        self
+    }
+
+    /// Allows persistent devices. Default value is `false`.
+    pub fn persist(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+
+    /// Checks the configuration requested by a client running as user `uid`.
+    fn check(&self, config: &TunConfig, uid: u32) -> std::result::Result<(), String> {
+        if !self.uids.contains(&uid) {
+            return Err(format!("user {} is not allowed", uid));
+        }
+        check_name(&config.name)?;
+        if !self
+            .name_prefixes
+            .iter()
+            .any(|prefix| config.name.starts_with(prefix.as_str()))
+        {
+            return Err(format!("name {} is not allowed", config.name));
+        }
+        if config.queues == 0 || config.queues > self.max_queues {
+            return Err(format!("{} queues are not allowed", config.queues));
+        }
+        if config.owner.is_some_and(|owner| owner as u32 != uid) {
+            return Err("owner of device must be the requesting user".into());
+        }
+        if config.group.is_some() {
+            return Err("group of device is not allowed".into());
+        }
+        if config.persist && !self.persist {
+            return Err("persistent devices are not allowed".into());
+        }
+        if config.bridge.is_some() {
+            return Err("bridges are not allowed".into());
+        }
+        // Every address is checked with the prefix of the route which the kernel adds for it.
+        let ipv4_prefix_len = match (config.netmask, config.address) {
+            (Some(netmask), _) => prefix_len(netmask)
+                .ok_or_else(|| format!("netmask {} is not contiguous", netmask))?,
+            (None, Some(address)) => classful_prefix_len(address),
+            (None, None) => 32,
+        };
+        let addresses = [config.address, config.destination]
+            .into_iter()
+            .flatten()
+            .map(|address| (IpAddr::from(address), ipv4_prefix_len))
+            .chain(config.broadcast.map(|broadcast| (broadcast.into(), 32)))
+            .chain(
+                config
+                    .ipv6_addresses
+                    .iter()
+                    .map(|&(address, prefix_len)| (address.into(), prefix_len)),
+            )
+            .chain(config.routes.iter().flat_map(|route: &Route| {
+                let gateway = route.gateway.map(|gateway| {
+                    let bits = if gateway.is_ipv4() { 32 } else { 128 };
+                    (gateway, bits)
+                });
+                [(route.destination, route.prefix_len)]
+                    .into_iter()
+                    .chain(gateway)
+            }));
+        for (address, prefix_len) in addresses {
+            if !self.contains(address, prefix_len) {
+                return Err(format!("{}/{} is not allowed", address, prefix_len));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if `address/prefix_len` lies within one of the allowed networks.
+    fn contains(&self, address: IpAddr, prefix_len: u8) -> bool {
+        self.networks.iter().any(|&(network, len)| {
+            let (address, network, bits) = match (address, network) {
+                (IpAddr::V4(address), IpAddr::V4(network)) => {
+                    (u32::from(address).into(), u32::from(network).into(), 32)
+                }
+                (IpAddr::V6(address), IpAddr::V6(network)) => {
+                    (u128::from(address), u128::from(network), 128)
+                }
+                _ => return false,
+            };
+            let host_bits = bits - u32::from(len).min(bits);
+            let prefix = |address: u128| address.checked_shr(host_bits).unwrap_or(0);
+            prefix_len >= len && prefix(address) == prefix(network)
+        })
+    }
+}
+
+/// Checks that `name` is a valid name of a network device, which is neither truncated nor
+/// interpreted as a pattern (e.g. `tun%d`) by the kernel.
+fn check_name(name: &str) -> std::result::Result<(), String> {
+    if name.is_empty() {
+        return Err("name of device is required".into());
+    }
+    if name.len() >= libc::IFNAMSIZ
+        || !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-'))
+    {
+        return Err(format!("name {:?} is invalid", name));
+    }
+    Ok(())
+}
+
+/// Returns the length of the prefix of `netmask`, or `None` if it is not contiguous.
+fn prefix_len(netmask: Ipv4Addr) -> Option<u8> {
+    let mask = u32::from(netmask);
+    let len = mask.leading_ones();
+    (mask.checked_shl(len).unwrap_or(0) == 0).then_some(len as u8)
+}
+
+/// Returns the length of the prefix which the kernel assumes for `address` if no netmask is
+/// set, i.e. that of its class (`inet_abc_len`).
+fn classful_prefix_len(address: Ipv4Addr) -> u8 {
+    if address.is_broadcast() {
+        return 0;
+    }
+    match address.octets()[0] {
+        0 => 0,
+        1..=127 => 8,
+        128..=191 => 16,
+        192..=223 => 24,
+        _ => 32,
+    }
+}
+
+/// Represents a server which creates devices on behalf of unprivileged clients.
+#[derive(Debug, Clone)]
+pub struct Broker {
+    policy: Arc<Policy>,
+    timeout: Duration,
+}
+
+impl Broker {
+    /// Creates a new instance of [`Broker`](struct.Broker.html) which enforces `policy`.
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Sets the time a client has to send its request after connecting, the connection is
+    /// closed once it elapses. Default value is 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Accepts clients on `listener` forever, each of them being handled on its own task.
+    ///
+    /// The permissions of the socket file decide which users may connect at all.
+    pub async fn serve(&self, listener: UnixListener) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let broker = self.clone();
+            tokio::spawn(async move {
+                // Errors are reported to the client, if it is still listening.
+                let _ = broker.handle(stream).await;
+            });
+        }
+    }
+
+    /// Handles a single request of the client connected to `stream`.
+    ///
+    /// The request must be received within the [`timeout`](#method.timeout) and must not
+    /// exceed 64 KiB.
+    pub async fn handle(&self, mut stream: UnixStream) -> Result<()> {
+        let uid = stream.peer_cred()?.uid();
+        let frame = tokio::time::timeout(self.timeout, read_frame(&mut stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no request was received"))??;
+        let config: TunConfig = serde_json::from_slice(&frame)?;
+        let reply = self
+            .policy
+            .check(&config, uid)
+            .and_then(|()| build(&config).map_err(|err| err.to_string()));
+        match reply {
+            Ok(tuns) => {
+                write_frame(&mut stream, &Ok::<(), String>(())).await?;
+                fdpass::send(&stream, &tuns.iter().collect::<Vec<_>>()).await
+            }
+            Err(err) => write_frame(&mut stream, &Err::<(), String>(err)).await,
+        }
+    }
+}
+
+/// Creates the device described by `config`, which must not exist yet, so that a client can
+/// not take over a device of another user.
+fn build(config: &TunConfig) -> Result<Vec<Tun>> {
+    let builder = TunBuilder::from(config).exclusive(true);
+    if config.queues > 1 {
+        builder.try_build_mq(config.queues)
+    } else {
+        Ok(vec![builder.try_build()?])
+    }
+}
+
+/// Requests the device described by `config` from the broker listening on `path`.
+pub(crate) async fn request(path: &Path, config: &TunConfig) -> Result<Vec<Tun>> {
+    let mut stream = UnixStream::connect(path).await?;
+    write_frame(&mut stream, config).await?;
+    let reply: std::result::Result<(), String> =
+        serde_json::from_slice(&read_frame(&mut stream).await?)?;
+    reply.map_err(io::Error::other)?;
+    let tuns = fdpass::recv(&stream).await?;
+    if tuns.len() != config.queues {
+        return Err(io::Error::other("broker returned an unexpected number of queues").into());
+    }
+    Ok(tuns)
+}
+
+async fn read_frame(stream: &mut UnixStream) -> Result<Vec<u8>> {
+    let len = stream.read_u32().await? as usize;
+    if len > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame is too large").into());
+    }
+    // Exactly `len` bytes are read, so the file descriptors which may follow are not consumed.
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn write_frame(stream: &mut UnixStream, value: &impl serde::Serialize) -> Result<()> {
+    let buf = serde_json::to_vec(value)?;
+    stream.write_u32(buf.len() as u32).await?;
+    stream.write_all(&buf).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy::new()
+            .uid(1000)
+            .name_prefix("vpn")
+            .network("10.8.0.0".parse().unwrap(), 16)
+            .network("fd00::".parse().unwrap(), 64)
+    }
+
+    fn allowed() -> TunConfig {
+        TunConfig {
+            name: "vpn0".into(),
+            address: Some(Ipv4Addr::new(10, 8, 0, 1)),
+            netmask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn allows_config_within_policy() {
+        let config = TunConfig {
+            destination: Some(Ipv4Addr::new(10, 8, 0, 2)),
+            broadcast: Some(Ipv4Addr::new(10, 8, 0, 255)),
+            ipv6_addresses: vec![("fd00::1".parse().unwrap(), 64)],
+            routes: vec![
+                Route::new("10.8.1.0".parse().unwrap(), 24).gateway("10.8.0.2".parse().unwrap())
+            ],
+            ..allowed()
+        };
+        assert_eq!(policy().check(&config, 1000), Ok(()));
+        assert!(policy().check(&config, 1001).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for name in ["", "tun0", "vpn%d", "vpn0/x", "vpné", "vpn0123456789abc"] {
+            let config = TunConfig {
+                name: name.into(),
+                ..allowed()
+            };
+            assert!(policy().check(&config, 1000).is_err(), "{:?}", name);
+        }
+        let config = TunConfig {
+            name: "vpn-0.a_b".into(),
+            ..allowed()
+        };
+        assert_eq!(policy().check(&config, 1000), Ok(()));
+    }
+
+    #[test]
+    fn checks_prefix_of_addresses() {
+        // The netmask adds a route to 0.0.0.0/0.
+        let config = TunConfig {
+            netmask: Some(Ipv4Addr::UNSPECIFIED),
+            ..allowed()
+        };
+        assert!(policy().check(&config, 1000).is_err());
+        // Without a netmask, the class A network 10.0.0.0/8 is routed.
+        let config = TunConfig {
+            netmask: None,
+            ..allowed()
+        };
+        assert!(policy().check(&config, 1000).is_err());
+        let config = TunConfig {
+            netmask: Some(Ipv4Addr::new(255, 0, 255, 0)),
+            ..allowed()
+        };
+        assert!(policy().check(&config, 1000).is_err());
+        let config = TunConfig {
+            ipv6_addresses: vec![("fd00::1".parse().unwrap(), 0)],
+            ..allowed()
+        };
+        assert!(policy().check(&config, 1000).is_err());
+    }
+
+    #[test]
+    fn checks_broadcast_and_gateway() {
+        let config = TunConfig {
+            broadcast: Some(Ipv4Addr::new(192, 168, 0, 255)),
+            ..allowed()
+        };
+        assert!(policy().check(&config, 1000).is_err());
+        let config = TunConfig {
+            routes: vec![
+                Route::new("10.8.1.0".parse().unwrap(), 24).gateway("192.168.0.1".parse().unwrap())
+            ],
+            ..allowed()
+        };
+        assert!(policy().check(&config, 1000).is_err());
+    }
+
+    #[test]
+    fn computes_prefix_len() {
+        assert_eq!(prefix_len(Ipv4Addr::new(255, 255, 255, 0)), Some(24));
+        assert_eq!(prefix_len(Ipv4Addr::BROADCAST), Some(32));
+        assert_eq!(prefix_len(Ipv4Addr::UNSPECIFIED), Some(0));
+        assert_eq!(prefix_len(Ipv4Addr::new(255, 255, 0, 255)), None);
+        assert_eq!(classful_prefix_len(Ipv4Addr::new(10, 0, 0, 1)), 8);
+        assert_eq!(classful_prefix_len(Ipv4Addr::new(172, 16, 0, 1)), 16);
+        assert_eq!(classful_prefix_len(Ipv4Addr::new(192, 168, 0, 1)), 24);
+        assert_eq!(classful_prefix_len(Ipv4Addr::new(0, 0, 0, 1)), 0);
+    }
+
+    async fn handle(broker: &Broker, request: &[u8]) -> io::Error {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(request).await.unwrap();
+        let err = broker.handle(server).await.unwrap_err();
+        *err.downcast::<io::Error>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn times_out_idle_clients() {
+        let broker = Broker::new(policy()).timeout(Duration::from_millis(50));
+        assert_eq!(handle(&broker, &[]).await.kind(), io::ErrorKind::TimedOut);
+        // A partial frame does not extend the timeout.
+        let err = handle(&broker, &[0, 0, 0, 8, b'{']).await;
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn rejects_large_frames() {
+        let broker = Broker::new(policy());
+        let len = (MAX_FRAME as u32 + 1).to_be_bytes();
+        assert_eq!(
+            handle(&broker, &len).await.kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,18 +1,31 @@
 use super::result::Result;
+use crate::config::{DeviceKind, TunConfig};
 #[cfg(target_os = "linux")]
-use crate::linux::params::Params;
+use crate::linux::netns::NetNs;
 #[cfg(target_os = "linux")]
+use crate::linux::params::Params;
+use crate::route::Route;
+#[cfg(all(target_os = "linux", feature = "tokio"))]
 use crate::tun::Tun;
 use core::convert::From;
-use libc::{IFF_NO_PI, IFF_TAP, IFF_TUN};
-use std::net::Ipv4Addr;
+use libc::{IFF_NO_PI, IFF_TAP, IFF_TUN, IFF_TUN_EXCL, IFF_VNET_HDR};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::OwnedFd;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 /// Represents a factory to build new instances of [`Tun`](struct.Tun.html).
-pub struct TunBuilder<'a> {
-    name: &'a str,
+///
+/// A builder owns its configuration, so it can be stored, cloned or sent to another task, and
+/// used to build any number of identically configured devices.
+#[derive(Debug, Clone)]
+pub struct TunBuilder {
+    name: String,
     is_tap: bool,
     packet_info: bool,
+    vnet_hdr: bool,
     persist: bool,
+    exclusive: bool,
     up: bool,
     mtu: Option<i32>,
     owner: Option<i32>,
@@ -21,36 +34,56 @@ pub struct TunBuilder<'a> {
     destination: Option<Ipv4Addr>,
     broadcast: Option<Ipv4Addr>,
     netmask: Option<Ipv4Addr>,
+    ipv6_addresses: Vec<(Ipv6Addr, u8)>,
+    offloads: Option<u32>,
+    routes: Vec<Route>,
+    bridge: Option<String>,
+    #[cfg(target_os = "linux")]
+    netns: Option<NetNs>,
+    device_path: PathBuf,
+    device_dir: Option<Arc<OwnedFd>>,
+    cloexec: bool,
 }
 
-impl<'a> Default for TunBuilder<'a> {
+impl Default for TunBuilder {
     fn default() -> Self {
         Self {
-            name: "",
+            name: String::new(),
             owner: None,
             group: None,
             is_tap: false,
             persist: false,
+            exclusive: false,
             up: false,
             mtu: None,
             packet_info: true,
+            vnet_hdr: false,
             address: None,
             destination: None,
             broadcast: None,
             netmask: None,
+            ipv6_addresses: Vec::new(),
+            offloads: None,
+            routes: Vec::new(),
+            bridge: None,
+            #[cfg(target_os = "linux")]
+            netns: None,
+            device_path: PathBuf::from("/dev/net/tun"),
+            device_dir: None,
+            cloexec: true,
         }
     }
 }
 
-impl<'a> TunBuilder<'a> {
+impl TunBuilder {
     /// Creates a new instance of [`TunBuilder`](struct.TunBuilder.html).
     pub fn new() -> Self {
         Default::default()
     }
 
     /// Sets the name of device (max length: 16 characters), if it is empty, then device name is set by kernel. Default value is empty.
-    pub fn name(mut self, name: &'a str) -> Self {
-        self.name = name;
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
         self
     }
 
@@ -79,6 +112,24 @@ impl<'a> TunBuilder<'a> {
         self
     }
 
+    /// If `vnet_hdr` is true, then `IFF_VNET_HDR` flag is set. Default value is `false`.
+    ///
+    /// Each packet is then preceded by a `struct virtio_net_hdr`, which describes the checksum
+    /// and segmentation offloads of the packet. See [`offloads`](struct.TunBuilder.html#method.offloads).
+    pub fn vnet_hdr(mut self, vnet_hdr: bool) -> Self {
+        self.vnet_hdr = vnet_hdr;
+        self
+    }
+
+    /// Sets the offloads (`TUN_F_*` flags) which userspace is able to handle (`TUNSETOFFLOAD`).
+    ///
+    /// Offloaded packets can only be described with a virtio header, so this is usually used
+    /// together with [`vnet_hdr`](struct.TunBuilder.html#method.vnet_hdr).
+    pub fn offloads(mut self, offloads: u32) -> Self {
+        self.offloads = Some(offloads);
+        self
+    }
+
     /// Sets the MTU (Maximum Transfer Unit) of device.
     ///
     /// MTU defines the maximum size of packets which this device will allow being transmitted or
@@ -140,6 +191,34 @@ impl<'a> TunBuilder<'a> {
         self
     }
 
+    /// Adds an IPv6 address with the given prefix length to device.
+    ///
+    /// This method may be called multiple times to add several addresses.
+    pub fn ipv6_address(mut self, address: Ipv6Addr, prefix_len: u8) -> Self {
+        self.ipv6_addresses.push((address, prefix_len));
+        self
+    }
+
+    /// Adds a route through the device.
+    ///
+    /// Routes are installed once the device is configured and set up, so this requires
+    /// [`up`](struct.TunBuilder.html#method.up), otherwise building fails with
+    /// `InvalidInput` before the device is created. If installing any of the routes fails, the
+    /// routes which were already installed are removed again.
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Attaches the device to the bridge named `bridge`.
+    ///
+    /// The device is enslaved to the bridge before it is set up, so no frame is ever
+    /// transmitted over the device outside of the bridge. This is usually used with TAP devices.
+    pub fn bridge(mut self, bridge: impl Into<String>) -> Self {
+        self.bridge = Some(bridge.into());
+        self
+    }
+
     /// Makes the device persistent.
     ///
     /// Persistent devices stay registered as long as the computer is not restarted.
@@ -148,11 +227,23 @@ impl<'a> TunBuilder<'a> {
     ///
     /// Non-persistent devices on the other hand, are removed as soon as the controlling process
     /// exits.
+    ///
+    /// The device is only made persistent once every other setting was applied, so a device
+    /// created by a build which fails is removed all the same, rather than being left
+    /// half-configured. A device which already existed is kept.
     pub fn persist(mut self) -> Self {
         self.persist = true;
         self
     }
 
+    /// If `exclusive` is true, then building fails if a device named
+    /// [`name`](struct.TunBuilder.html#method.name) exists already (`IFF_TUN_EXCL`), instead of
+    /// attaching to it. Default value is `false`.
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
     /// Sets up the device.
     ///
     /// This means the interface is immediately put into the *up* state.
@@ -162,34 +253,203 @@ impl<'a> TunBuilder<'a> {
         self
     }
 
+    /// Creates the device inside the given network namespace.
+    ///
+    /// The clone device is opened and configured on a dedicated thread which has entered the
+    /// namespace, so the calling thread stays in its own namespace. The returned
+    /// [`Tun`](struct.Tun.html) is registered with the current runtime as usual.
+    ///
+    /// Entering a namespace requires `CAP_SYS_ADMIN`.
+    #[cfg(target_os = "linux")]
+    pub fn netns(mut self, netns: impl Into<NetNs>) -> Self {
+        self.netns = Some(netns.into());
+        self
+    }
+
+    /// Sets the path of the clone device. Default value is `/dev/net/tun`.
+    ///
+    /// This is useful in containers where the device node is bind-mounted elsewhere.
+    pub fn device_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.device_path = path.into();
+        self.device_dir = None;
+        self
+    }
+
+    /// Sets the path of the clone device relative to the directory `dir` (`openat`).
+    ///
+    /// The directory file descriptor is owned by the builder and shared by its clones, so it
+    /// is closed once all of them are dropped. Absolute paths ignore `dir`.
+    pub fn device_path_at(mut self, dir: OwnedFd, path: impl Into<PathBuf>) -> Self {
+        self.device_path = path.into();
+        self.device_dir = Some(Arc::new(dir));
+        self
+    }
+
+    /// If `cloexec` is true, then the clone device is opened with `O_CLOEXEC`, so the device
+    /// is not inherited by child processes. Default value is `true`.
+    pub fn cloexec(mut self, cloexec: bool) -> Self {
+        self.cloexec = cloexec;
+        self
+    }
+
+    /// Returns the `IFF_*` flags supported by the tun driver (`TUNGETFEATURES`), as reported
+    /// by the configured clone device.
+    #[cfg(target_os = "linux")]
+    pub fn features(&self) -> Result<i32> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::AsRawFd;
+        let path = std::ffi::CString::new(self.device_path.as_os_str().as_bytes())?;
+        let dir = self
+            .device_dir
+            .as_ref()
+            .map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd());
+        let fd = crate::linux::alloc::open(dir, &path, libc::O_RDWR | libc::O_CLOEXEC)?;
+        crate::linux::interface::Interface::features(fd.as_raw_fd())
+    }
+
     /// Builds a new instance of [`Tun`](struct.Tun.html).
-    pub fn try_build(self) -> Result<Tun> {
-        Tun::new(self.into())
+    #[cfg(feature = "tokio")]
+    pub fn try_build(&self) -> Result<Tun> {
+        Tun::new(self.try_into()?)
     }
 
     /// Builds multiple instances of [`Tun`](struct.Tun.html) with `IFF_MULTI_QUEUE` flag.
     ///
     /// Internally this creates multiple file descriptors to parallelize packet sending and receiving.
+    #[cfg(all(target_os = "linux", feature = "tokio"))]
+    pub fn try_build_mq(&self, queues: usize) -> Result<Vec<Tun>> {
+        Tun::new_mq(self.try_into()?, queues)
+    }
+
+    /// Builds a new instance of [`blocking::Tun`](blocking/struct.Tun.html), which does not
+    /// require an asynchronous runtime.
     #[cfg(target_os = "linux")]
-    pub fn try_build_mq(self, queues: usize) -> Result<Vec<Tun>> {
-        Tun::new_mq(self.into(), queues)
+    pub fn try_build_blocking(&self) -> Result<crate::blocking::Tun> {
+        crate::blocking::Tun::new(self.try_into()?)
+    }
+
+    /// Builds multiple instances of [`blocking::Tun`](blocking/struct.Tun.html) with
+    /// `IFF_MULTI_QUEUE` flag.
+    #[cfg(target_os = "linux")]
+    pub fn try_build_mq_blocking(&self, queues: usize) -> Result<Vec<crate::blocking::Tun>> {
+        crate::blocking::Tun::new_mq(self.try_into()?, queues)
+    }
+
+    /// Builds a new instance of [`async_io::Tun`](async_io/struct.Tun.html), which is driven by
+    /// the `async-io` reactor of `async-std` and `smol`.
+    #[cfg(all(target_os = "linux", feature = "async-io"))]
+    pub fn try_build_async_io(&self) -> Result<crate::async_io::Tun> {
+        crate::async_io::Tun::new(self.try_into()?)
+    }
+
+    /// Builds multiple instances of [`async_io::Tun`](async_io/struct.Tun.html) with
+    /// `IFF_MULTI_QUEUE` flag.
+    #[cfg(all(target_os = "linux", feature = "async-io"))]
+    pub fn try_build_mq_async_io(&self, queues: usize) -> Result<Vec<crate::async_io::Tun>> {
+        crate::async_io::Tun::new_mq(self.try_into()?, queues)
+    }
+
+    /// Builds a new instance of [`UringTun`](struct.UringTun.html), which reads and writes
+    /// packets through `io_uring`.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn try_build_uring(&self, options: &crate::UringOptions) -> Result<crate::UringTun> {
+        Ok(crate::UringTun::new_mq(self.try_into()?, 1, options)?.remove(0))
+    }
+
+    /// Builds multiple instances of [`UringTun`](struct.UringTun.html) with `IFF_MULTI_QUEUE`
+    /// flag, each with its own ring.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn try_build_mq_uring(
+        &self,
+        queues: usize,
+        options: &crate::UringOptions,
+    ) -> Result<Vec<crate::UringTun>> {
+        crate::UringTun::new_mq(self.try_into()?, queues, options)
+    }
+
+    /// Builds a [`TunPool`](struct.TunPool.html) of `max_queues` queues with `IFF_MULTI_QUEUE`
+    /// flag, whose packets are passed to `handler`.
+    ///
+    /// This must be called within a tokio runtime.
+    #[cfg(all(target_os = "linux", feature = "pool"))]
+    pub fn try_build_pool<H: crate::PacketHandler>(
+        &self,
+        max_queues: usize,
+        handler: H,
+        options: &crate::PoolOptions,
+    ) -> Result<crate::TunPool<H>> {
+        crate::TunPool::new(self.try_into()?, max_queues, handler, options)
+    }
+
+    /// Requests a new instance of [`Tun`](struct.Tun.html) from the
+    /// [`Broker`](broker/struct.Broker.html) listening on the Unix socket at `path`, which
+    /// creates the device on behalf of this unprivileged process.
+    #[cfg(all(target_os = "linux", feature = "broker"))]
+    pub async fn try_build_via(&self, path: impl AsRef<std::path::Path>) -> Result<Tun> {
+        Ok(self.try_build_mq_via(path, 1).await?.remove(0))
+    }
+
+    /// Requests multiple instances of [`Tun`](struct.Tun.html) with `IFF_MULTI_QUEUE` flag
+    /// from the [`Broker`](broker/struct.Broker.html) listening on the Unix socket at `path`.
+    #[cfg(all(target_os = "linux", feature = "broker"))]
+    pub async fn try_build_mq_via(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        queues: usize,
+    ) -> Result<Vec<Tun>> {
+        if self.netns.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "network namespaces are not supported by the broker",
+            )
+            .into());
+        }
+        self.validate()?;
+        let config = TunConfig {
+            queues,
+            ..self.into()
+        };
+        crate::broker::request(path.as_ref(), &config).await
+    }
+}
+
+impl TunBuilder {
+    /// Checks the options which depend on each other, before the device is created.
+    fn validate(&self) -> Result<()> {
+        if !self.routes.is_empty() && !self.up {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "routes require the device to be set up",
+            )
+            .into());
+        }
+        Ok(())
     }
 }
 
-impl<'a> From<TunBuilder<'a>> for Params {
+impl TryFrom<&TunBuilder> for Params {
+    type Error = crate::result::Error;
+
     #[cfg(target_os = "linux")]
-    fn from(builder: TunBuilder) -> Self {
-        Params {
+    fn try_from(builder: &TunBuilder) -> Result<Self> {
+        builder.validate()?;
+        Ok(Params {
             name: if builder.name.is_empty() {
                 None
             } else {
-                Some(builder.name.into())
+                Some(builder.name.clone())
             },
             flags: {
                 let mut flags = if builder.is_tap { IFF_TAP } else { IFF_TUN } as _;
                 if !builder.packet_info {
                     flags |= IFF_NO_PI as i16;
                 }
+                if builder.vnet_hdr {
+                    flags |= IFF_VNET_HDR as i16;
+                }
+                if builder.exclusive {
+                    flags |= IFF_TUN_EXCL as i16;
+                }
                 flags
             },
             persist: builder.persist,
@@ -201,11 +461,86 @@ impl<'a> From<TunBuilder<'a>> for Params {
             destination: builder.destination,
             broadcast: builder.broadcast,
             netmask: builder.netmask,
-        }
+            ipv6_addresses: builder.ipv6_addresses.clone(),
+            offloads: builder.offloads,
+            netns: builder.netns.clone(),
+            routes: builder.routes.clone(),
+            bridge: builder.bridge.clone(),
+            device_path: builder.device_path.clone(),
+            device_dir: builder.device_dir.clone(),
+            cloexec: builder.cloexec,
+        })
     }
 
     #[cfg(not(any(target_os = "linux")))]
-    fn from(builder: TunBuilder) -> Self {
+    fn try_from(builder: &TunBuilder) -> Result<Self> {
         unimplemented!()
     }
 }
+
+impl From<&TunBuilder> for TunConfig {
+    fn from(builder: &TunBuilder) -> Self {
+        TunConfig {
+            name: builder.name.clone(),
+            kind: if builder.is_tap {
+                DeviceKind::Tap
+            } else {
+                DeviceKind::Tun
+            },
+            packet_info: builder.packet_info,
+            vnet_hdr: builder.vnet_hdr,
+            mtu: builder.mtu,
+            owner: builder.owner,
+            group: builder.group,
+            address: builder.address,
+            destination: builder.destination,
+            broadcast: builder.broadcast,
+            netmask: builder.netmask,
+            ipv6_addresses: builder.ipv6_addresses.clone(),
+            routes: builder.routes.clone(),
+            bridge: builder.bridge.clone(),
+            persist: builder.persist,
+            up: builder.up,
+            queues: 1,
+            offloads: builder.offloads,
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn routes_require_up() {
+        let route = Route::new("10.0.0.0".parse().unwrap(), 8);
+        let builder = TunBuilder::new().route(route);
+        let err = Params::try_from(&builder).err().unwrap();
+        let err = err.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(Params::try_from(&builder.up()).is_ok());
+    }
+
+    #[test]
+    fn features_uses_device_path() {
+        let err = TunBuilder::new()
+            .device_path("/dev/net/missing")
+            .features()
+            .err()
+            .unwrap();
+        let err = err.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        if !std::path::Path::new("/dev/net/tun").exists() {
+            eprintln!("skipped, /dev/net/tun does not exist");
+            return;
+        }
+        let features = TunBuilder::new().features().unwrap();
+        assert_ne!(features & libc::IFF_TUN, 0);
+        let dir = std::fs::File::open("/dev/net").unwrap();
+        let builder = TunBuilder::new().device_path_at(dir.into(), "tun");
+        assert_eq!(builder.clone().features().unwrap(), features);
+        drop(builder);
+    }
+}
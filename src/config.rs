@@ -0,0 +1,254 @@
+use crate::builder::TunBuilder;
+use crate::result::Result;
+use crate::route::Route;
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+use crate::tun::Tun;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Represents the kind of a device.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DeviceKind {
+    /// A layer 3 device, which transmits IP packets.
+    #[default]
+    Tun,
+    /// A layer 2 device, which transmits ethernet frames.
+    Tap,
+}
+
+/// Represents an owned, serializable description of a Tun/Tap device.
+///
+/// A configuration can be converted into a [`TunBuilder`](struct.TunBuilder.html), or built
+/// directly with [`try_build`](struct.TunConfig.html#method.try_build). Use
+/// [`Tun::config`](struct.Tun.html#method.config) to dump the configuration of a live device.
+///
+/// With the `serde` feature enabled, it implements `Serialize` and `Deserialize`, every field
+/// being optional and defaulting to the default value of [`TunBuilder`](struct.TunBuilder.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TunConfig {
+    /// Name of device, it is set by kernel if empty.
+    pub name: String,
+    pub kind: DeviceKind,
+    pub packet_info: bool,
+    pub vnet_hdr: bool,
+    pub mtu: Option<i32>,
+    pub owner: Option<i32>,
+    pub group: Option<i32>,
+    pub address: Option<Ipv4Addr>,
+    pub destination: Option<Ipv4Addr>,
+    pub broadcast: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    /// IPv6 addresses of device along with their prefix length.
+    pub ipv6_addresses: Vec<(Ipv6Addr, u8)>,
+    pub routes: Vec<Route>,
+    /// Name of the bridge the device is attached to.
+    pub bridge: Option<String>,
+    pub persist: bool,
+    pub up: bool,
+    /// Number of queues, a multi-queue device is created if it is greater than 1.
+    pub queues: usize,
+    /// Offloads (`TUN_F_*` flags) set with `TUNSETOFFLOAD`.
+    pub offloads: Option<u32>,
+}
+
+impl Default for TunConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            kind: DeviceKind::Tun,
+            packet_info: true,
+            vnet_hdr: false,
+            mtu: None,
+            owner: None,
+            group: None,
+            address: None,
+            destination: None,
+            broadcast: None,
+            netmask: None,
+            ipv6_addresses: Vec::new(),
+            routes: Vec::new(),
+            bridge: None,
+            persist: false,
+            up: false,
+            queues: 1,
+            offloads: None,
+        }
+    }
+}
+
+impl TunConfig {
+    /// Builds the device, returning one instance of [`Tun`](struct.Tun.html) per queue.
+    #[cfg(all(target_os = "linux", feature = "tokio"))]
+    pub fn try_build(&self) -> Result<Vec<Tun>> {
+        let builder = TunBuilder::from(self);
+        if self.queues > 1 {
+            builder.try_build_mq(self.queues)
+        } else {
+            Ok(vec![builder.try_build()?])
+        }
+    }
+
+    /// Dumps the configuration of the existing device `name`, as
+    /// [`Tun::config`](struct.Tun.html#method.config) does, without attaching to it.
+    ///
+    /// Devices are inspected through sysfs and netlink, so this works while other processes
+    /// hold the queues of device, however the offloads are unknown.
+    #[cfg(target_os = "linux")]
+    pub fn from_device(name: &str) -> Result<Self> {
+        crate::linux::interface::Interface::lookup(name)?.config()
+    }
+
+    /// Builds the device, returning one instance of [`blocking::Tun`](blocking/struct.Tun.html)
+    /// per queue.
+    #[cfg(target_os = "linux")]
+    pub fn try_build_blocking(&self) -> Result<Vec<crate::blocking::Tun>> {
+        let builder = TunBuilder::from(self);
+        if self.queues > 1 {
+            builder.try_build_mq_blocking(self.queues)
+        } else {
+            Ok(vec![builder.try_build_blocking()?])
+        }
+    }
+}
+
+impl From<&TunConfig> for TunBuilder {
+    fn from(config: &TunConfig) -> Self {
+        let mut builder = TunBuilder::new()
+            .name(&config.name)
+            .tap(config.kind == DeviceKind::Tap)
+            .packet_info(config.packet_info)
+            .vnet_hdr(config.vnet_hdr);
+        if let Some(mtu) = config.mtu {
+            builder = builder.mtu(mtu);
+        }
+        if let Some(owner) = config.owner {
+            builder = builder.owner(owner);
+        }
+        if let Some(group) = config.group {
+            builder = builder.group(group);
+        }
+        if let Some(address) = config.address {
+            builder = builder.address(address);
+        }
+        if let Some(destination) = config.destination {
+            builder = builder.destination(destination);
+        }
+        if let Some(broadcast) = config.broadcast {
+            builder = builder.broadcast(broadcast);
+        }
+        if let Some(netmask) = config.netmask {
+            builder = builder.netmask(netmask);
+        }
+        for &(address, prefix_len) in config.ipv6_addresses.iter() {
+            builder = builder.ipv6_address(address, prefix_len);
+        }
+        for &route in config.routes.iter() {
+            builder = builder.route(route);
+        }
+        if let Some(bridge) = &config.bridge {
+            builder = builder.bridge(bridge);
+        }
+        if let Some(offloads) = config.offloads {
+            builder = builder.offloads(offloads);
+        }
+        if config.persist {
+            builder = builder.persist();
+        }
+        if config.up {
+            builder = builder.up();
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let config = TunConfig {
+            name: "vpn0".into(),
+            kind: DeviceKind::Tap,
+            packet_info: false,
+            vnet_hdr: true,
+            mtu: Some(1400),
+            owner: Some(1000),
+            group: Some(100),
+            address: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            destination: Some(Ipv4Addr::new(10, 0, 0, 2)),
+            broadcast: Some(Ipv4Addr::new(10, 0, 0, 255)),
+            netmask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+            ipv6_addresses: vec![("fd00::1".parse().unwrap(), 64)],
+            routes: vec![Route::new("10.1.0.0".parse().unwrap(), 16)
+                .gateway("10.0.0.2".parse().unwrap())
+                .metric(10)],
+            bridge: Some("br0".into()),
+            persist: true,
+            up: true,
+            queues: 2,
+            offloads: Some(1),
+        };
+        // The field names are part of the protocol of the broker.
+        let json = serde_json::json!({
+            "name": "vpn0",
+            "kind": "tap",
+            "packet_info": false,
+            "vnet_hdr": true,
+            "mtu": 1400,
+            "owner": 1000,
+            "group": 100,
+            "address": "10.0.0.1",
+            "destination": "10.0.0.2",
+            "broadcast": "10.0.0.255",
+            "netmask": "255.255.255.0",
+            "ipv6_addresses": [["fd00::1", 64]],
+            "routes": [{
+                "destination": "10.1.0.0",
+                "prefix_len": 16,
+                "gateway": "10.0.0.2",
+                "metric": 10,
+            }],
+            "bridge": "br0",
+            "persist": true,
+            "up": true,
+            "queues": 2,
+            "offloads": 1,
+        });
+        assert_eq!(serde_json::to_value(&config).unwrap(), json);
+        assert_eq!(serde_json::from_value::<TunConfig>(json).unwrap(), config);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_defaults() {
+        let config: TunConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, TunConfig::default());
+        let config: TunConfig = serde_json::from_str(r#"{"kind": "tun", "mtu": null}"#).unwrap();
+        assert_eq!(config, TunConfig::default());
+        assert!(serde_json::from_str::<TunConfig>(r#"{"kind": "Tap"}"#).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dump_packet_info() {
+        if !crate::linux::alloc::tests::privileged() {
+            return;
+        }
+        for packet_info in [false, true] {
+            let tun = TunBuilder::new()
+                .name("tuncfg0")
+                .packet_info(packet_info)
+                .try_build_blocking()
+                .unwrap();
+            let config = tun.config().unwrap();
+            assert_eq!(config.packet_info, packet_info);
+            assert_eq!(config.kind, DeviceKind::Tun);
+            assert!(!config.persist);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+#[cfg(all(target_os = "linux", feature = "async-io"))]
+use crate::async_io;
+use crate::result::Result;
+#[cfg(feature = "tokio")]
+use crate::tun::Tun;
+use std::future::Future;
+use std::io;
+
+/// Represents a packet-oriented device, such as [`Tun`](struct.Tun.html) or
+/// [`MockTun`](struct.MockTun.html).
+///
+/// Code which only sends and receives packets can be generic over this trait, so it can be
+/// tested against a [`MockTun`](struct.MockTun.html) without privileges.
+pub trait Device {
+    /// Receives a packet from the device.
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> + Send;
+
+    /// Sends a packet to the device.
+    fn send(&self, buf: &[u8]) -> impl Future<Output = io::Result<usize>> + Send;
+
+    /// Tries to receive a packet from the device, `Err(io::ErrorKind::WouldBlock)` is returned
+    /// if there is no pending packet.
+    fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Tries to send a packet to the device, `Err(io::ErrorKind::WouldBlock)` is returned if the
+    /// buffer of device is full.
+    fn try_send(&self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Returns the name of device.
+    fn name(&self) -> &str;
+
+    /// Returns the value of MTU.
+    fn mtu(&self) -> Result<i32>;
+}
+
+#[cfg(feature = "tokio")]
+impl Device for Tun {
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> + Send {
+        Tun::recv(self, buf)
+    }
+
+    fn send(&self, buf: &[u8]) -> impl Future<Output = io::Result<usize>> + Send {
+        Tun::send(self, buf)
+    }
+
+    fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        Tun::try_recv(self, buf)
+    }
+
+    fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+        Tun::try_send(self, buf)
+    }
+
+    fn name(&self) -> &str {
+        Tun::name(self)
+    }
+
+    fn mtu(&self) -> Result<i32> {
+        Tun::mtu(self)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "async-io"))]
+impl Device for async_io::Tun {
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> + Send {
+        async_io::Tun::recv(self, buf)
+    }
+
+    fn send(&self, buf: &[u8]) -> impl Future<Output = io::Result<usize>> + Send {
+        async_io::Tun::send(self, buf)
+    }
+
+    fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        async_io::Tun::try_recv(self, buf)
+    }
+
+    fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+        async_io::Tun::try_send(self, buf)
+    }
+
+    fn name(&self) -> &str {
+        async_io::Tun::name(self)
+    }
+
+    fn mtu(&self) -> Result<i32> {
+        async_io::Tun::mtu(self)
+    }
+}
@@ -0,0 +1,241 @@
+use crate::linux::interface::Interface;
+use crate::linux::netlink::{self, ifinfomsg, Attrs, Messages, Netlink};
+use crate::result::Error;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+
+const RTMGRP_LINK: u32 = 0x1;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+/// Multicast groups which [`LinkEvents`](struct.LinkEvents.html) subscribes to.
+pub(crate) const GROUPS: u32 = RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR;
+
+/// Represents a change of a Tun/Tap device which was observed by the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The device was set up.
+    Up,
+    /// The device was set down.
+    Down,
+    /// The MTU of device was changed to the given value.
+    MtuChanged(u32),
+    /// The device was renamed to the given name.
+    Renamed(String),
+    /// An address was added to the device.
+    AddressAdded { address: IpAddr, prefix_len: u8 },
+    /// An address was removed from the device.
+    AddressRemoved { address: IpAddr, prefix_len: u8 },
+    /// The device was deleted, this is the last event of the stream.
+    Deleted,
+}
+
+/// Represents the attributes of a link which are tracked to detect changes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct LinkState {
+    pub up: bool,
+    pub mtu: u32,
+    pub name: String,
+}
+
+impl LinkState {
+    /// Parses the payload of a `RTM_NEWLINK` message, returning the interface index and state.
+    pub fn parse(payload: &[u8]) -> Option<(i32, Self)> {
+        let hdr: ifinfomsg = netlink::read(payload)?;
+        let mut state = LinkState {
+            up: hdr.ifi_flags & libc::IFF_UP as u32 != 0,
+            ..Default::default()
+        };
+        for (kind, attr) in Attrs::after::<ifinfomsg>(payload) {
+            match kind {
+                netlink::IFLA_MTU => state.mtu = netlink::parse_u32(attr)?,
+                netlink::IFLA_IFNAME => state.name = netlink::parse_str(attr)?,
+                _ => {}
+            }
+        }
+        Some((hdr.ifi_index, state))
+    }
+}
+
+/// Represents a stream of [`LinkEvent`](enum.LinkEvent.html)s of a single device.
+///
+/// Use [`Tun::events`](struct.Tun.html#method.events) to create a new instance. The stream ends
+/// after [`LinkEvent::Deleted`](enum.LinkEvent.html#variant.Deleted) is yielded.
+///
+/// If notifications were lost because the socket buffer overflowed, the device is fetched
+/// again and the differences to the last known state are yielded instead. Errors of the socket
+/// are yielded as they occur.
+pub struct LinkEvents {
+    io: AsyncFd<Netlink>,
+    iface: Arc<Interface>,
+    state: LinkState,
+    addresses: Vec<(IpAddr, u8)>,
+    pending: VecDeque<LinkEvent>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl LinkEvents {
+    /// Creates a new instance from a socket subscribed to [`GROUPS`](constant.GROUPS.html),
+    /// and the state of the device fetched afterwards.
+    pub(crate) fn new(
+        netlink: Netlink,
+        iface: Arc<Interface>,
+        state: LinkState,
+        addresses: Vec<(IpAddr, u8)>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            io: unsafe { AsyncFd::register(netlink) }?,
+            iface,
+            state,
+            addresses,
+            pending: VecDeque::new(),
+            buf: vec![0; 32 * 1024],
+            done: false,
+        })
+    }
+
+    /// Queues the events which lead from the last known state of the link to `state`.
+    fn update(&mut self, state: LinkState) {
+        if state.up != self.state.up {
+            self.pending.push_back(if state.up {
+                LinkEvent::Up
+            } else {
+                LinkEvent::Down
+            });
+        }
+        if state.mtu != self.state.mtu {
+            self.pending.push_back(LinkEvent::MtuChanged(state.mtu));
+        }
+        if state.name != self.state.name {
+            self.pending
+                .push_back(LinkEvent::Renamed(state.name.clone()));
+        }
+        self.state = state;
+    }
+
+    /// Fetches the link and its addresses after notifications were lost, and queues the events
+    /// which lead from the last known state to them.
+    fn resync(&mut self) -> io::Result<()> {
+        let link = match self.iface.link().map_err(io_error) {
+            Ok(link) => link,
+            Err(err) if err.raw_os_error() == Some(libc::ENODEV) => {
+                self.pending.push_back(LinkEvent::Deleted);
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        let (_, state) = LinkState::parse(&link)
+            .ok_or_else(|| io::Error::other("invalid RTM_NEWLINK response"))?;
+        let addresses = self.iface.addresses().map_err(io_error)?;
+        self.update(state);
+        for &(address, prefix_len) in self.addresses.iter() {
+            if !addresses.contains(&(address, prefix_len)) {
+                self.pending.push_back(LinkEvent::AddressRemoved {
+                    address,
+                    prefix_len,
+                });
+            }
+        }
+        for &(address, prefix_len) in addresses.iter() {
+            if !self.addresses.contains(&(address, prefix_len)) {
+                self.pending.push_back(LinkEvent::AddressAdded {
+                    address,
+                    prefix_len,
+                });
+            }
+        }
+        self.addresses = addresses;
+        Ok(())
+    }
+
+    fn parse(&mut self, n: usize) {
+        let buf = std::mem::take(&mut self.buf);
+        for (hdr, payload) in Messages(&buf[..n]) {
+            match hdr.nlmsg_type {
+                netlink::RTM_NEWLINK => match LinkState::parse(payload) {
+                    Some((index, state)) if index == self.iface.index() => self.update(state),
+                    _ => {}
+                },
+                netlink::RTM_DELLINK => {
+                    if matches!(LinkState::parse(payload), Some((index, _)) if index == self.iface.index())
+                    {
+                        self.pending.push_back(LinkEvent::Deleted);
+                    }
+                }
+                kind @ (netlink::RTM_NEWADDR | netlink::RTM_DELADDR) => {
+                    if let Some((index, address, prefix_len)) = netlink::parse_addr(payload) {
+                        if index != self.iface.index() as u32 {
+                            continue;
+                        }
+                        // Addresses which are known already are announced again when their
+                        // flags change, or after the state was fetched again.
+                        let entry = (address, prefix_len);
+                        let known = self.addresses.contains(&entry);
+                        if kind == netlink::RTM_NEWADDR && !known {
+                            self.addresses.push(entry);
+                            self.pending.push_back(LinkEvent::AddressAdded {
+                                address,
+                                prefix_len,
+                            });
+                        } else if kind == netlink::RTM_DELADDR && known {
+                            self.addresses.retain(|&other| other != entry);
+                            self.pending.push_back(LinkEvent::AddressRemoved {
+                                address,
+                                prefix_len,
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.buf = buf;
+    }
+}
+
+impl Stream for LinkEvents {
+    type Item = io::Result<LinkEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                if event == LinkEvent::Deleted {
+                    this.pending.clear();
+                    this.done = true;
+                }
+                return Poll::Ready(Some(Ok(event)));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+
+            let mut guard = ready!(this.io.poll_read_ready(cx))?;
+            let res = guard.try_io(|inner| inner.get_ref().recv(&mut this.buf));
+            drop(guard);
+            match res {
+                Ok(Ok(n)) => this.parse(n),
+                // The socket buffer overflowed and some notifications were lost.
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::ENOBUFS) => this.resync()?,
+                Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Converts an error of a request to the kernel, which is an `io::Error` unless a response
+/// was malformed.
+fn io_error(err: Error) -> io::Error {
+    match err.downcast::<io::Error>() {
+        Ok(err) => *err,
+        Err(err) => io::Error::other(err.to_string()),
+    }
+}
@@ -0,0 +1,233 @@
+//! Passing Tun/Tap devices between processes over Unix sockets (`SCM_RIGHTS`).
+//!
+//! This allows a small privileged helper to create devices and hand them over to an
+//! unprivileged process:
+//!
+//! ```no_run
+//! # async fn helper(stream: tokio::net::UnixStream) -> tokio_tun::result::Result<()> {
+//! use tokio_tun::{fdpass, TunBuilder};
+//!
+//! let queues = TunBuilder::new().name("tun0").up().try_build_mq(4)?;
+//! fdpass::send(&stream, &queues.iter().collect::<Vec<_>>()).await?;
+//! # Ok(())
+//! # }
+//! # async fn dataplane(stream: tokio::net::UnixStream) -> tokio_tun::result::Result<()> {
+//! let queues = tokio_tun::fdpass::recv(&stream).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::result::Result;
+use crate::tun::Tun;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use tokio::io::Interest;
+use tokio::net::UnixStream;
+
+/// Maximum number of file descriptors the kernel accepts in one message (`SCM_MAX_FD`).
+const MAX_FDS: usize = 253;
+
+/// Sends the file descriptors of `tuns` in a single message.
+///
+/// The devices stay usable in this process, they are closed once both the local and the
+/// remote instances are dropped.
+pub async fn send(stream: &UnixStream, tuns: &[&Tun]) -> Result<()> {
+    if tuns.is_empty() || tuns.len() > MAX_FDS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("between 1 and {} devices can be sent at once", MAX_FDS),
+        )
+        .into());
+    }
+    let fds = tuns.iter().map(|tun| tun.as_raw_fd()).collect::<Vec<_>>();
+    stream
+        .async_io(Interest::WRITABLE, || sendmsg(stream.as_raw_fd(), &fds))
+        .await?;
+    Ok(())
+}
+
+/// Receives the devices sent with [`send`](fn.send.html).
+///
+/// Consecutive file descriptors of the same multi-queue device are received as queues of
+/// one device, as if they were built with
+/// [`try_build_mq`](../struct.TunBuilder.html#method.try_build_mq).
+pub async fn recv(stream: &UnixStream) -> Result<Vec<Tun>> {
+    let fds = stream
+        .async_io(Interest::READABLE, || recvmsg(stream.as_raw_fd()))
+        .await?;
+    Tun::from_fds(fds)
+}
+
+fn sendmsg(socket: RawFd, fds: &[RawFd]) -> io::Result<()> {
+    // At least one byte of data has to be sent along with the control message.
+    let mut data = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let size = mem::size_of_val(fds) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(size) } as usize];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast(), fds.len());
+    }
+    if unsafe { libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn recvmsg(socket: RawFd) -> io::Result<Vec<OwnedFd>> {
+    let mut data = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let size = (MAX_FDS * mem::size_of::<RawFd>()) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(size) } as usize];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+    let n = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Take ownership of every received file descriptor first, so they are closed on error.
+    let mut fds = Vec::new();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        if hdr.cmsg_level == libc::SOL_SOCKET && hdr.cmsg_type == libc::SCM_RIGHTS {
+            let len = hdr.cmsg_len as usize - unsafe { libc::CMSG_LEN(0) } as usize;
+            let data = unsafe { libc::CMSG_DATA(cmsg) }.cast::<RawFd>();
+            for i in 0..len / mem::size_of::<RawFd>() {
+                let fd = unsafe { data.add(i).read_unaligned() };
+                fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    if n == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::other("control message is truncated"));
+    }
+    if fds.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message carries no file descriptors",
+        ));
+    }
+    Ok(fds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+    use crate::mock::MockTun;
+    use crate::TunBuilder;
+
+    async fn send_fds(stream: &UnixStream, fds: &[RawFd]) -> io::Result<()> {
+        stream
+            .async_io(Interest::WRITABLE, || sendmsg(stream.as_raw_fd(), fds))
+            .await
+    }
+
+    async fn recv_fds(stream: &UnixStream) -> io::Result<Vec<OwnedFd>> {
+        stream
+            .async_io(Interest::READABLE, || recvmsg(stream.as_raw_fd()))
+            .await
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let pairs = (0..3)
+            .map(|_| MockTun::pair("mock0", 1500).unwrap())
+            .collect::<Vec<_>>();
+        let fds = pairs
+            .iter()
+            .map(|(tun, _)| tun.as_raw_fd())
+            .collect::<Vec<_>>();
+        send_fds(&a, &fds).await.unwrap();
+        let received = recv_fds(&b).await.unwrap();
+        assert_eq!(received.len(), 3);
+
+        // Each received file descriptor refers to the socket which was sent.
+        let mut buf = [0; 16];
+        for (i, (fd, (_, kernel))) in received.iter().zip(pairs.iter()).enumerate() {
+            let packet = [i as u8; 4];
+            let n = unsafe { libc::send(fd.as_raw_fd(), packet.as_ptr().cast(), 4, 0) };
+            assert_eq!(n, 4);
+            let n = kernel.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &packet);
+        }
+    }
+
+    #[tokio::test]
+    async fn max_fds() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (tun, _kernel) = MockTun::pair("mock0", 1500).unwrap();
+        let fds = vec![tun.as_raw_fd(); MAX_FDS];
+        send_fds(&a, &fds).await.unwrap();
+        assert_eq!(recv_fds(&b).await.unwrap().len(), MAX_FDS);
+
+        // The kernel refuses more file descriptors than `SCM_MAX_FD`.
+        let fds = vec![tun.as_raw_fd(); MAX_FDS + 1];
+        let err = send_fds(&a, &fds).await.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+
+        let err = send(&a, &[]).await.unwrap_err();
+        let err = err.downcast::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn message_without_fds() {
+        let (a, b) = UnixStream::pair().unwrap();
+        a.writable().await.unwrap();
+        a.try_write(&[0]).unwrap();
+        let err = recv_fds(&b).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        drop(a);
+        let err = recv(&b).await.err().unwrap();
+        let err = err.downcast::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn devices() {
+        if !crate::linux::alloc::tests::privileged() {
+            return;
+        }
+        let (a, b) = UnixStream::pair().unwrap();
+        let queues = TunBuilder::new().name("tunpass0").try_build_mq(2).unwrap();
+        let err = send(&a, &vec![&queues[0]; MAX_FDS + 1]).await.unwrap_err();
+        let err = err.downcast::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        send(&a, &queues.iter().collect::<Vec<_>>()).await.unwrap();
+        let received = recv(&b).await.unwrap();
+        assert_eq!(received.len(), 2);
+        for tun in received.iter() {
+            assert_eq!(tun.name(), "tunpass0");
+            assert_eq!(tun.config().unwrap().queues, 2);
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Flow keys of packets and the Toeplitz hash used by Receive Side Scaling (RSS).
+//!
+//! Hashing the flow of a packet selects the same queue for all of its packets, so they are
+//! never reordered. The hash is the one specified by Microsoft RSS, which NICs and the RSS
+//! steering of virtio-net compute. The tun driver selects the queue of a packet sent by the
+//! kernel by a hash of its own, unless a steering program is attached:
+//! [`set_rss_steering`](../struct.Tun.html#method.set_rss_steering) attaches one which
+//! computes this hash, so the kernel then chooses the queue given by
+//! [`FlowKey::rss_queue`](struct.FlowKey.html#method.rss_queue):
+//!
+//! ```
+//! use tokio_tun::flow::{FlowKey, RSS_KEY};
+//!
+//! let key = FlowKey {
+//!     src: "66.9.149.187".parse().unwrap(),
+//!     dst: "161.142.100.80".parse().unwrap(),
+//!     protocol: 6,
+//!     src_port: 2794,
+//!     dst_port: 1766,
+//! };
+//! assert_eq!(key.rss_hash(&RSS_KEY), 0x51ccc178);
+//! ```
+
+use crate::config::DeviceKind;
+use crate::packet::{Echo, Network, Packet, Transport};
+use std::net::{IpAddr, Ipv6Addr};
+
+/// The default key of Microsoft RSS, which is also used by most NIC drivers.
+pub const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+/// Represents the flow of an IPv4 or IPv6 packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// Transport protocol, e.g. `6` for TCP.
+    pub protocol: u8,
+    /// Ports of TCP and UDP, or the identifier of ICMP echo messages as source port. Zero if
+    /// the packet has none, e.g. a non-first fragment.
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    /// Parses the flow of an IPv4 or IPv6 packet, as received from a TUN device without packet
+    /// information.
+    pub fn from_ip(packet: &[u8]) -> Option<Self> {
+        Self::from_packet(&Packet::parse(packet, DeviceKind::Tun, false)?)
+    }
+
+    /// Parses the flow of an Ethernet frame, which may carry 802.1Q tags, as received from a
+    /// TAP device without packet information.
+    pub fn from_ethernet(frame: &[u8]) -> Option<Self> {
+        Self::from_packet(&Packet::parse(frame, DeviceKind::Tap, false)?)
+    }
+
+    /// Parses the flow of a packet received from a device created with `flags`, skipping the
+    /// packet information and virtio-net headers.
+    pub(crate) fn from_device(flags: i16, packet: &[u8]) -> Option<Self> {
+        Self::from_packet(&Packet::from_device(flags, packet)?)
+    }
+
+    /// Returns the flow of `packet`, or `None` if it is neither an IPv4 nor an IPv6 packet.
+    ///
+    /// All fragments of a packet share its flow, so none of them has ports, not even the first
+    /// one which carries them.
+    pub fn from_packet(packet: &Packet<'_>) -> Option<Self> {
+        let (src, dst, protocol) = match packet.network() {
+            Network::Ipv4(ip) => (ip.source().into(), ip.destination().into(), ip.protocol()),
+            Network::Ipv6(ip) => (ip.source().into(), ip.destination().into(), ip.protocol()),
+            _ => return None,
+        };
+        let echo = |echo: Option<Echo>| echo.map_or((0, 0), |echo| (echo.identifier, 0));
+        let (src_port, dst_port) = match packet.transport() {
+            Some(Transport::Tcp(tcp)) => (tcp.src_port(), tcp.dst_port()),
+            Some(Transport::Udp(udp)) => (udp.src_port(), udp.dst_port()),
+            Some(Transport::Icmp(icmp)) => echo(icmp.echo()),
+            Some(Transport::Icmpv6(icmp)) => echo(icmp.echo()),
+            _ => (0, 0),
+        };
+        Some(Self {
+            src,
+            dst,
+            protocol,
+            src_port,
+            dst_port,
+        })
+    }
+
+    /// Returns the Toeplitz hash of the addresses, followed by the ports for TCP and UDP.
+    ///
+    /// The key must be at least 4 bytes longer than the input, i.e. 40 bytes for IPv6.
+    pub fn rss_hash(&self, key: &[u8]) -> u32 {
+        let mut input = [0; 36];
+        let mut len = match (self.src, self.dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                input[..4].copy_from_slice(&src.octets());
+                input[4..8].copy_from_slice(&dst.octets());
+                8
+            }
+            (src, dst) => {
+                input[..16].copy_from_slice(&to_ipv6(src).octets());
+                input[16..32].copy_from_slice(&to_ipv6(dst).octets());
+                32
+            }
+        };
+        if matches!(self.protocol as i32, libc::IPPROTO_TCP | libc::IPPROTO_UDP) {
+            input[len..len + 2].copy_from_slice(&self.src_port.to_be_bytes());
+            input[len + 2..len + 4].copy_from_slice(&self.dst_port.to_be_bytes());
+            len += 4;
+        }
+        toeplitz_hash(key, &input[..len])
+    }
+
+    /// Returns the queue out of `queues` which the steering program attached by
+    /// [`set_rss_steering`](../struct.Tun.html#method.set_rss_steering) chooses for this flow,
+    /// i.e. the lower 16 bits of the RSS hash modulo `queues`.
+    pub fn rss_queue(&self, key: &[u8], queues: usize) -> usize {
+        (self.rss_hash(key) as u16 as usize) % queues.max(1)
+    }
+}
+
+/// Computes the Toeplitz hash of `input` with `key` as specified by Microsoft RSS.
+///
+/// Bits of `input` beyond the first `8 * (key.len() - 4)` are ignored.
+pub fn toeplitz_hash(key: &[u8], input: &[u8]) -> u32 {
+    let mut hash = 0;
+    let mut bits = key.iter().copied();
+    // The leftmost 32 bits of key, shifted left by one bit for every bit of input.
+    let mut window = u32::from_be_bytes([0; 4].map(|_| bits.next().unwrap_or_default()));
+    for byte in input.iter().take(key.len().saturating_sub(4)) {
+        let next = bits.next().unwrap_or_default();
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = window << 1 | u32::from(next >> (7 - bit) & 1);
+        }
+    }
+    hash
+}
+
+fn to_ipv6(address: IpAddr) -> Ipv6Addr {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped(),
+        IpAddr::V6(address) => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mac::MacAddr;
+    use crate::packet::PacketBuilder;
+    use std::net::Ipv4Addr;
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const DST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const SRC6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+    const DST6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+
+    fn key(src: IpAddr, dst: IpAddr, protocol: i32, src_port: u16, dst_port: u16) -> FlowKey {
+        FlowKey {
+            src,
+            dst,
+            protocol: protocol as u8,
+            src_port,
+            dst_port,
+        }
+    }
+
+    fn udp4() -> PacketBuilder {
+        PacketBuilder::ipv4(SRC, DST).udp(1000, 2000)
+    }
+
+    /// Returns an IPv6 packet which carries `headers`, the first of which is `protocol`,
+    /// followed by a UDP header.
+    fn ipv6_with(protocol: i32, headers: &[u8]) -> Vec<u8> {
+        let mut payload = headers.to_vec();
+        payload.extend_from_slice(&[0x03, 0xe8, 0x07, 0xd0, 0, 8, 0, 0]);
+        PacketBuilder::ipv6(SRC6, DST6)
+            .protocol(protocol as u8)
+            .build(&payload)
+            .unwrap()
+    }
+
+    #[test]
+    fn flow_of_ip_packets() {
+        let udp = key(SRC.into(), DST.into(), libc::IPPROTO_UDP, 1000, 2000);
+        let packet = udp4().build(b"flow").unwrap();
+        assert_eq!(FlowKey::from_ip(&packet), Some(udp));
+
+        let packet = PacketBuilder::ipv6(SRC6, DST6)
+            .tcp(1000, 2000)
+            .build(&[])
+            .unwrap();
+        let tcp = key(SRC6.into(), DST6.into(), libc::IPPROTO_TCP, 1000, 2000);
+        assert_eq!(FlowKey::from_ip(&packet), Some(tcp));
+
+        // Neither IPv4 nor IPv6.
+        assert_eq!(FlowKey::from_ip(&[0x10; 40]), None);
+        assert_eq!(FlowKey::from_ip(&[]), None);
+
+        // Malformed headers are rejected like by the packet views.
+        let mut packet = udp4().build(&[]).unwrap();
+        packet[0] = 0x44;
+        assert_eq!(FlowKey::from_ip(&packet), None);
+        packet[0] = 0x45;
+        packet[3] += 1;
+        assert_eq!(FlowKey::from_ip(&packet), None);
+    }
+
+    #[test]
+    fn flow_skips_headers_of_device() {
+        let udp = key(SRC.into(), DST.into(), libc::IPPROTO_UDP, 1000, 2000);
+        let no_pi = libc::IFF_TUN as i16 | libc::IFF_NO_PI as i16;
+        let packet = udp4().build(&[]).unwrap();
+        assert_eq!(FlowKey::from_device(no_pi, &packet), Some(udp));
+
+        // The ethertype of the packet information is used.
+        let packet = udp4().packet_info(true).build(&[]).unwrap();
+        assert_eq!(
+            FlowKey::from_device(libc::IFF_TUN as i16, &packet),
+            Some(udp)
+        );
+
+        let mut packet = vec![0; 10];
+        udp4().write(&[], &mut packet).unwrap();
+        let vnet_hdr = no_pi | libc::IFF_VNET_HDR as i16;
+        assert_eq!(FlowKey::from_device(vnet_hdr, &packet), Some(udp));
+
+        let mac = MacAddr::new(2, 0, 0, 0, 0, 1);
+        let tap = libc::IFF_TAP as i16 | libc::IFF_NO_PI as i16;
+        let frame = udp4().ethernet(mac, mac).build(&[]).unwrap();
+        assert_eq!(FlowKey::from_device(tap, &frame), Some(udp));
+        assert_eq!(FlowKey::from_ethernet(&frame), Some(udp));
+
+        // 802.1ad and 802.1Q tags between the addresses and the ethertype.
+        let mut tagged = frame[..12].to_vec();
+        tagged.extend_from_slice(&[0x88, 0xa8, 0, 10, 0x81, 0, 0, 20]);
+        tagged.extend_from_slice(&frame[12..]);
+        assert_eq!(FlowKey::from_ethernet(&tagged), Some(udp));
+
+        let frame = udp4()
+            .ethernet(mac, mac)
+            .packet_info(true)
+            .build(&[])
+            .unwrap();
+        assert_eq!(
+            FlowKey::from_device(libc::IFF_TAP as i16, &frame),
+            Some(udp)
+        );
+    }
+
+    #[test]
+    fn fragments_have_no_ports() {
+        let mut packet = udp4().build(&[]).unwrap();
+        let ports = FlowKey::from_ip(&packet).unwrap();
+        let fragment = FlowKey {
+            src_port: 0,
+            dst_port: 0,
+            ..ports
+        };
+        // The first fragment has no ports either, so that it shares the flow of the others.
+        for (flags, key) in [(0x4000, ports), (0x2000, fragment), (0x0001, fragment)] {
+            packet[6..8].copy_from_slice(&u16::to_be_bytes(flags));
+            assert_eq!(FlowKey::from_ip(&packet), Some(key), "{:#x}", flags);
+        }
+    }
+
+    #[test]
+    fn flow_skips_ipv6_extension_headers() {
+        let udp = key(SRC6.into(), DST6.into(), libc::IPPROTO_UDP, 1000, 2000);
+        let udp_fragment = FlowKey {
+            src_port: 0,
+            dst_port: 0,
+            ..udp
+        };
+        let hop_by_hop = [libc::IPPROTO_DSTOPTS as u8, 0, 1, 4, 0, 0, 0, 0];
+        let destination = [libc::IPPROTO_UDP as u8, 0, 1, 4, 0, 0, 0, 0];
+        let mut headers = hop_by_hop.to_vec();
+        headers.extend_from_slice(&destination);
+        let packet = ipv6_with(libc::IPPROTO_HOPOPTS, &headers);
+        assert_eq!(FlowKey::from_ip(&packet), Some(udp));
+
+        // An atomic fragment is a whole packet, unlike the first of several fragments.
+        let atomic = [libc::IPPROTO_UDP as u8, 0, 0, 0, 0, 0, 0, 1];
+        let packet = ipv6_with(libc::IPPROTO_FRAGMENT, &atomic);
+        assert_eq!(FlowKey::from_ip(&packet), Some(udp));
+        let first = [libc::IPPROTO_UDP as u8, 0, 0, 1, 0, 0, 0, 1];
+        let packet = ipv6_with(libc::IPPROTO_FRAGMENT, &first);
+        assert_eq!(FlowKey::from_ip(&packet), Some(udp_fragment));
+        let last = [libc::IPPROTO_UDP as u8, 0, 0, 8, 0, 0, 0, 1];
+        let packet = ipv6_with(libc::IPPROTO_FRAGMENT, &last);
+        assert_eq!(FlowKey::from_ip(&packet), Some(udp_fragment));
+
+        // The length of an authentication header counts 4 byte units, minus 2.
+        let mut ah = vec![libc::IPPROTO_UDP as u8, 2, 0, 0];
+        ah.extend_from_slice(&[0; 12]);
+        let packet = ipv6_with(libc::IPPROTO_AH, &ah);
+        assert_eq!(FlowKey::from_ip(&packet), Some(udp));
+    }
+
+    #[test]
+    fn icmp_echo_identifier_is_source_port() {
+        let packet = PacketBuilder::ipv4(SRC, DST)
+            .echo_request(0x1234, 7)
+            .build(&[])
+            .unwrap();
+        let echo = key(SRC.into(), DST.into(), libc::IPPROTO_ICMP, 0x1234, 0);
+        assert_eq!(FlowKey::from_ip(&packet), Some(echo));
+        let packet = PacketBuilder::ipv6(SRC6, DST6)
+            .echo_reply(0x1234, 7)
+            .build(&[])
+            .unwrap();
+        let echo = key(SRC6.into(), DST6.into(), libc::IPPROTO_ICMPV6, 0x1234, 0);
+        assert_eq!(FlowKey::from_ip(&packet), Some(echo));
+
+        // Other messages have no identifier.
+        let packet = PacketBuilder::ipv4(SRC, DST)
+            .icmp(3, 1, [0x12, 0x34, 0, 0])
+            .build(&[])
+            .unwrap();
+        let unreachable = key(SRC.into(), DST.into(), libc::IPPROTO_ICMP, 0, 0);
+        assert_eq!(FlowKey::from_ip(&packet), Some(unreachable));
+    }
+
+    /// Verification suite of Microsoft RSS, as `(source, port, destination, port, IP hash, TCP
+    /// hash)`.
+    const IPV6_VECTORS: [(&str, u16, &str, u16, u32, u32); 3] = [
+        (
+            "3ffe:2501:200:1fff::7",
+            2794,
+            "3ffe:2501:200:3::1",
+            1766,
+            0x2cc18cd5,
+            0x40207d3d,
+        ),
+        (
+            "3ffe:501:8::260:97ff:fe40:efab",
+            14230,
+            "ff02::1",
+            4739,
+            0x0f0c461c,
+            0xdde51bbf,
+        ),
+        (
+            "3ffe:1900:4545:3:200:f8ff:fe21:67cf",
+            44251,
+            "fe80::200:f8ff:fe21:67cf",
+            38024,
+            0x4b61e985,
+            0x02d1feef,
+        ),
+    ];
+
+    const IPV4_VECTORS: [(&str, u16, &str, u16, u32, u32); 3] = [
+        (
+            "66.9.149.187",
+            2794,
+            "161.142.100.80",
+            1766,
+            0x323e8fc2,
+            0x51ccc178,
+        ),
+        (
+            "199.92.111.2",
+            14230,
+            "65.69.140.83",
+            4739,
+            0xd718262a,
+            0xc626b0ea,
+        ),
+        (
+            "24.19.198.95",
+            12898,
+            "12.22.207.184",
+            38024,
+            0xd2d0a5de,
+            0x5c2b394a,
+        ),
+    ];
+
+    fn check(vectors: &[(&str, u16, &str, u16, u32, u32)]) {
+        for &(src, src_port, dst, dst_port, ip_hash, tcp_hash) in vectors {
+            let key = FlowKey {
+                src: src.parse().unwrap(),
+                dst: dst.parse().unwrap(),
+                protocol: libc::IPPROTO_TCP as u8,
+                src_port,
+                dst_port,
+            };
+            assert_eq!(key.rss_hash(&RSS_KEY), tcp_hash, "{}", src);
+            let key = FlowKey {
+                protocol: libc::IPPROTO_NONE as u8,
+                ..key
+            };
+            assert_eq!(key.rss_hash(&RSS_KEY), ip_hash, "{}", src);
+        }
+    }
+
+    #[test]
+    fn rss_hash_of_ipv4() {
+        check(&IPV4_VECTORS);
+    }
+
+    #[test]
+    fn rss_hash_of_ipv6() {
+        check(&IPV6_VECTORS);
+    }
+}
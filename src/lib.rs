@@ -1,16 +1,84 @@
+// Taken from the `futures` crate
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+macro_rules! ready {
+    ($e:expr $(,)?) => {
+        match $e {
+            std::task::Poll::Ready(t) => t,
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        }
+    };
+}
+
+#[macro_use]
+mod macros;
+
 #[cfg(target_os = "linux")]
 mod linux {
     pub mod address;
+    pub mod alloc;
     pub mod interface;
     pub mod io;
+    pub mod netlink;
+    pub mod netns;
     pub mod params;
     pub mod request;
+    pub mod steering;
 }
 
 mod builder;
+mod config;
+mod device;
+#[cfg(feature = "tokio")]
+mod events;
+mod mac;
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+mod mock;
+#[cfg(feature = "tokio")]
+mod multiqueue;
+#[cfg(all(target_os = "linux", feature = "pool"))]
+mod pool;
+#[cfg(feature = "tokio")]
+mod responder;
+mod route;
+mod stats;
+#[cfg(feature = "tokio")]
 mod tun;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
 
+#[cfg(all(target_os = "linux", feature = "async-io"))]
+pub mod async_io;
+#[cfg(target_os = "linux")]
+pub mod blocking;
+#[cfg(all(target_os = "linux", feature = "broker"))]
+pub mod broker;
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+pub mod fdpass;
+pub mod flow;
+#[cfg(target_os = "linux")]
+pub mod netlink;
+pub mod packet;
 pub mod result;
 
 pub use self::builder::TunBuilder;
+pub use self::config::{DeviceKind, TunConfig};
+pub use self::device::Device;
+#[cfg(feature = "tokio")]
+pub use self::events::{LinkEvent, LinkEvents};
+#[cfg(target_os = "linux")]
+pub use self::linux::netns::NetNs;
+pub use self::mac::{MacAddr, MacAddrParseError};
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+pub use self::mock::MockTun;
+#[cfg(feature = "tokio")]
+pub use self::multiqueue::MultiQueueTun;
+#[cfg(all(target_os = "linux", feature = "pool"))]
+pub use self::pool::{PacketHandler, PoolOptions, TunPool};
+#[cfg(feature = "tokio")]
+pub use self::responder::Responder;
+pub use self::route::Route;
+pub use self::stats::{QueueStats, Stats};
+#[cfg(feature = "tokio")]
 pub use self::tun::Tun;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use self::uring::{UringOptions, UringTun};
@@ -0,0 +1,291 @@
+use super::interface::Interface;
+use super::io::TunIo;
+use super::params::Params;
+use crate::result::Result;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+
+/// Allocates a device with `queues` queues as described by `params`.
+pub fn allocate(mut params: Params, queues: usize) -> Result<(Interface, Vec<TunIo>)> {
+    match params.netns.take() {
+        Some(netns) => netns.run(|| {
+            let (mut iface, queues) = allocate_here(params, queues)?;
+            iface.pin_netns()?;
+            Ok((iface, queues))
+        }),
+        None => allocate_here(params, queues),
+    }
+}
+
+/// Allocates the device in the network namespace of the calling thread.
+///
+/// Every resource is owned as soon as it is acquired, so all of them are released if any
+/// step fails. Closing the queues removes a half-configured device, unless it existed
+/// beforehand as a persistent device.
+fn allocate_here(params: Params, queues: usize) -> Result<(Interface, Vec<TunIo>)> {
+    let path = CString::new(params.device_path.as_os_str().as_bytes())?;
+    let dir = params
+        .device_dir
+        .as_ref()
+        .map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd());
+    let mut flags = libc::O_RDWR | libc::O_NONBLOCK;
+    if params.cloexec {
+        flags |= libc::O_CLOEXEC;
+    }
+
+    let queues = (0..queues)
+        .map(|_| {
+            step()?;
+            Ok(TunIo::from(open(dir, &path, flags)?))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut iface = Interface::new(
+        &queues,
+        params.name.as_deref().unwrap_or_default(),
+        params.flags,
+    )?;
+    iface.init(params)?;
+    Ok((iface, queues))
+}
+
+/// Opens the clone device at `path`, relative to the directory `dir` (`openat`).
+pub fn open(dir: RawFd, path: &CStr, flags: i32) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::openat(dir, path.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of the step which fails, counting from 1, and the number of steps taken so far.
+    static FAILURE: std::cell::Cell<(usize, usize)> = const { std::cell::Cell::new((0, 0)) };
+}
+
+/// Marks a step of allocating a device, which tests make fail to check that the resources
+/// of the previous steps are released.
+pub(super) fn step() -> io::Result<()> {
+    #[cfg(test)]
+    {
+        let (fail_at, steps) = FAILURE.get();
+        FAILURE.set((fail_at, steps + 1));
+        if steps + 1 == fail_at {
+            return Err(io::Error::other("injected failure"));
+        }
+    }
+    Ok(())
+}
+
+/// Recovers the devices which `fds` are attached to, where consecutive file descriptors of the
+/// same device share it as queues.
+pub fn attach(fds: Vec<OwnedFd>) -> Result<Vec<(Arc<Interface>, TunIo)>> {
+    let mut devices: Vec<(String, i16, Vec<TunIo>)> = Vec::new();
+    for fd in fds {
+        set_nonblocking(fd.as_raw_fd(), true)?;
+        let (name, flags) = Interface::attachment(fd.as_raw_fd())?;
+        let io = TunIo::from(fd);
+        match devices.last_mut() {
+            Some((last, _, queues)) if *last == name => queues.push(io),
+            _ => devices.push((name, flags, vec![io])),
+        }
+    }
+    let mut queues = Vec::new();
+    for (name, flags, ios) in devices {
+        let fds = ios.iter().map(TunIo::downgrade).collect();
+        let iface = Arc::new(Interface::attached(fds, &name, flags)?);
+        queues.extend(ios.into_iter().map(|io| (iface.clone(), io)));
+    }
+    Ok(queues)
+}
+
+/// Sets or clears `O_NONBLOCK` on the file descriptor.
+pub fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    let mut flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if nonblocking {
+        flags |= libc::O_NONBLOCK;
+    } else {
+        flags &= !libc::O_NONBLOCK;
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::builder::TunBuilder;
+    use std::ffi::CString;
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use std::os::unix::io::IntoRawFd;
+
+    /// Returns true if devices can be created, otherwise the test is skipped.
+    pub(crate) fn privileged() -> bool {
+        let privileged = unsafe { libc::geteuid() } == 0;
+        if !privileged {
+            eprintln!("skipped, creating devices requires root");
+        }
+        privileged
+    }
+
+    pub(crate) fn exists(name: &str) -> bool {
+        let name = CString::new(name).unwrap();
+        unsafe { libc::if_nametoindex(name.as_ptr()) != 0 }
+    }
+
+    /// Returns the number of file descriptors of this process attached to device `name`.
+    pub(crate) fn attached(name: &str) -> usize {
+        let line = format!("iff:\t{}\n", name);
+        fs::read_dir("/proc/self/fdinfo")
+            .unwrap()
+            .filter_map(|entry| fs::read_to_string(entry.ok()?.path()).ok())
+            .filter(|info| info.contains(&line))
+            .count()
+    }
+
+    /// Fails every step of allocating the device described by `builder` in turn, until the
+    /// allocation succeeds.
+    fn fail_every_step(builder: &TunBuilder, name: &str, existed: bool) {
+        for fail_at in 1.. {
+            FAILURE.set((fail_at, 0));
+            let result = allocate(Params::try_from(builder).unwrap(), 2);
+            FAILURE.set((0, 0));
+            match result {
+                Ok((iface, queues)) => {
+                    assert!(exists(name));
+                    assert_eq!(attached(name), 2);
+                    if !existed {
+                        iface.persist(false).unwrap();
+                    }
+                    drop(queues);
+                    return;
+                }
+                Err(err) => {
+                    assert_eq!(err.to_string(), "injected failure", "step {}", fail_at);
+                    assert_eq!(attached(name), 0, "step {}", fail_at);
+                    assert_eq!(exists(name), existed, "step {}", fail_at);
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    fn builder(name: &str) -> TunBuilder {
+        TunBuilder::new()
+            .name(name)
+            .mtu(1400)
+            .address(Ipv4Addr::new(10, 250, 0, 1))
+            .netmask(Ipv4Addr::new(255, 255, 255, 0))
+            .ipv6_address("fd00:250::1".parse().unwrap(), 64)
+            .route(crate::route::Route::new("10.251.0.0".parse().unwrap(), 16))
+            .up()
+    }
+
+    #[test]
+    fn failed_allocation_removes_device() {
+        if !privileged() {
+            return;
+        }
+        fail_every_step(&builder("tunfail0"), "tunfail0", false);
+        assert!(!exists("tunfail0"));
+    }
+
+    #[test]
+    fn failed_allocation_removes_persistent_device() {
+        if !privileged() {
+            return;
+        }
+        // Persistence is only set once every other step succeeded.
+        fail_every_step(&builder("tunfail1").persist(), "tunfail1", false);
+        assert!(!exists("tunfail1"));
+    }
+
+    #[test]
+    fn failed_allocation_keeps_existing_device() {
+        if !privileged() {
+            return;
+        }
+        let (iface, queues) = allocate(
+            Params::try_from(&TunBuilder::new().name("tunfail2")).unwrap(),
+            2,
+        )
+        .unwrap();
+        iface.persist(true).unwrap();
+        drop((iface, queues));
+
+        let builder = TunBuilder::new().name("tunfail2").up();
+        fail_every_step(&builder, "tunfail2", true);
+        let (iface, queues) = allocate(Params::try_from(&builder).unwrap(), 2).unwrap();
+        iface.persist(false).unwrap();
+        drop(queues);
+        assert!(!exists("tunfail2"));
+    }
+
+    #[test]
+    fn attach_recovers_flags() {
+        if !privileged() {
+            return;
+        }
+        for (tap, packet_info) in [(false, false), (false, true), (true, false), (true, true)] {
+            let builder = TunBuilder::new()
+                .name("tunattach0")
+                .tap(tap)
+                .packet_info(packet_info);
+            let (_, mut queues) = allocate(Params::try_from(&builder).unwrap(), 1).unwrap();
+            let fd = unsafe { OwnedFd::from_raw_fd(queues.remove(0).into_raw_fd()) };
+            let (iface, _) = attach(vec![fd]).unwrap().remove(0);
+            assert_eq!(iface.name(), "tunattach0");
+            let flags = iface.attach_flags() as i32;
+            assert_eq!(flags & libc::IFF_TAP != 0, tap);
+            assert_eq!(flags & libc::IFF_NO_PI == 0, packet_info);
+        }
+    }
+
+    #[test]
+    fn settings_skip_closed_queues() {
+        if !privileged() {
+            return;
+        }
+        let builder = TunBuilder::new().name("tunqueue0");
+        let (iface, mut queues) = allocate(Params::try_from(&builder).unwrap(), 2).unwrap();
+        // The settings of the whole device are applied through the remaining queue.
+        drop(queues.remove(0));
+        assert_eq!(iface.queues(), 1);
+        iface.persist(true).unwrap();
+        assert_ne!(iface.tun_flags().unwrap() & libc::IFF_PERSIST as i16, 0);
+
+        // The number of a file descriptor which was taken out of its queue may be reused once
+        // it is closed, so it is never used again.
+        drop(queues.remove(0).into_fd());
+        let _reused = fs::File::open("/dev/null").unwrap();
+        assert!(iface.persist(false).is_err());
+        assert!(exists("tunqueue0"));
+
+        let (iface, _queues) = allocate(Params::try_from(&builder).unwrap(), 2).unwrap();
+        iface.persist(false).unwrap();
+    }
+
+    #[cfg(feature = "pool")]
+    #[test]
+    fn settings_skip_detached_queues() {
+        if !privileged() {
+            return;
+        }
+        let builder = TunBuilder::new().name("tunqueue1");
+        let (iface, queues) = allocate(Params::try_from(&builder).unwrap(), 2).unwrap();
+        Interface::set_queue(queues[0].as_raw_fd(), false).unwrap();
+        iface.persist(true).unwrap();
+        iface.persist(false).unwrap();
+        assert_eq!(iface.tun_flags().unwrap() & libc::IFF_PERSIST as i16, 0);
+    }
+}
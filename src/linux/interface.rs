@@ -1,13 +1,41 @@
+use super::alloc::step;
+use super::io::TunIo;
+use super::netlink::{self, ifaddrmsg, ifinfomsg, rtmsg, Attrs, Message, Netlink};
+use super::netns::NetNs;
 use super::params::Params;
 use super::request::ifreq;
+use crate::config::{DeviceKind, TunConfig};
 use crate::linux::address::Ipv4AddrExt;
+use crate::mac::MacAddr;
 use crate::result::Result;
-use std::net::Ipv4Addr;
+use crate::route::Route;
+use crate::stats::Stats;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex, Weak};
 
 nix::ioctl_write_int!(tunsetiff, b'T', 202);
 nix::ioctl_write_int!(tunsetpersist, b'T', 203);
 nix::ioctl_write_int!(tunsetowner, b'T', 204);
 nix::ioctl_write_int!(tunsetgroup, b'T', 206);
+nix::ioctl_read!(tungetfeatures, b'T', 207, libc::c_uint);
+nix::ioctl_write_int!(tunsetoffload, b'T', 208);
+nix::ioctl_write_ptr!(tunsettxfilter, b'T', 209, libc::c_uint);
+#[cfg(feature = "pool")]
+nix::ioctl_write_int!(tunsetqueue, b'T', 217);
+nix::ioctl_write_ptr_bad!(
+    tunsetsteeringebpf,
+    nix::request_code_read!(b'T', 224, std::mem::size_of::<libc::c_int>()),
+    libc::c_int
+);
+nix::ioctl_read_bad!(
+    tungetiff,
+    nix::request_code_read!(b'T', 210, std::mem::size_of::<libc::c_uint>()),
+    ifreq
+);
+
+const TUN_FLT_ALLMULTI: u16 = 0x0001;
 
 nix::ioctl_write_ptr_bad!(siocsifmtu, libc::SIOCSIFMTU, ifreq);
 nix::ioctl_write_ptr_bad!(siocsifflags, libc::SIOCSIFFLAGS, ifreq);
@@ -22,77 +50,483 @@ nix::ioctl_read_bad!(siocgifaddr, libc::SIOCGIFADDR, ifreq);
 nix::ioctl_read_bad!(siocgifdstaddr, libc::SIOCGIFDSTADDR, ifreq);
 nix::ioctl_read_bad!(siocgifbrdaddr, libc::SIOCGIFBRDADDR, ifreq);
 nix::ioctl_read_bad!(siocgifnetmask, libc::SIOCGIFNETMASK, ifreq);
+nix::ioctl_read_bad!(siocgifindex, libc::SIOCGIFINDEX, ifreq);
+nix::ioctl_read_bad!(siocgifname, libc::SIOCGIFNAME, ifreq);
 
-#[derive(Clone)]
 pub struct Interface {
-    fds: Vec<i32>,
-    socket: i32,
+    /// Queues of device, which are owned by their `TunIo`. Queues which were closed since are
+    /// dropped from the list when it is used.
+    fds: Mutex<Vec<Weak<OwnedFd>>>,
+    socket: OwnedFd,
+    netlink: Mutex<Netlink>,
     name: String,
+    index: i32,
+    flags: i16,
+    netns: Option<NetNs>,
+    offloads: Option<u32>,
 }
 
 impl Interface {
-    pub fn new(fds: Vec<i32>, name: &str, mut flags: i16) -> Result<Self> {
+    /// Attaches the clone devices `queues` to device `name`, which is created if it does not
+    /// exist.
+    pub fn new(queues: &[TunIo], name: &str, mut flags: i16) -> Result<Self> {
         let mut req = ifreq::new(name);
-        if fds.len() > 1 {
+        if queues.len() > 1 {
             flags |= libc::IFF_MULTI_QUEUE as i16;
         }
         req.ifr_ifru.ifru_flags = flags;
-        for &fd in &fds {
-            unsafe { tunsetiff(fd, &req as *const _ as _) }?;
+        for queue in queues {
+            step()?;
+            unsafe { tunsetiff(queue.as_raw_fd(), &req as *const _ as _) }?;
+            // Further queues attach to the device which the first one created.
+            flags &= !(libc::IFF_TUN_EXCL as i16);
+            req.ifr_ifru.ifru_flags = flags;
         }
+        let fds = queues.iter().map(TunIo::downgrade).collect();
+        Self::attached(fds, req.name(), flags)
+    }
+
+    /// Creates an interface for queues which are already attached to device `name`.
+    pub fn attached(fds: Vec<Weak<OwnedFd>>, name: &str, flags: i16) -> Result<Self> {
+        let mut req = ifreq::new(name);
+        let socket =
+            unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if socket < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+        unsafe { siocgifindex(socket.as_raw_fd(), &mut req) }?;
         Ok(Interface {
-            fds,
-            socket: unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) },
-            name: req.name().to_owned(),
+            fds: Mutex::new(fds),
+            socket,
+            netlink: Mutex::new(Netlink::new()?),
+            name: name.to_owned(),
+            index: unsafe { req.ifr_ifru.ifru_ivalue },
+            flags,
+            netns: None,
+            offloads: None,
         })
     }
 
-    pub fn init(&self, params: Params) -> Result<()> {
+    /// Creates an interface for the existing device `name` without attaching to it, so that it
+    /// can be inspected while other processes hold its queues. Settings which are applied
+    /// through a queue, such as [`persist`](#method.persist), fail on it.
+    pub fn lookup(name: &str) -> Result<Self> {
+        let flags = sysfs_flags(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no such Tun/Tap device", name),
+            )
+        })?;
+        Self::attached(Vec::new(), name, flags)
+    }
+
+    /// Configures the device as described by `params`.
+    ///
+    /// The device is made persistent last, so that it is removed if any of the other steps
+    /// fails.
+    pub fn init(&mut self, params: Params) -> Result<()> {
         if let Some(mtu) = params.mtu {
+            step()?;
             self.mtu(Some(mtu))?;
         }
+        if let Some(offloads) = params.offloads {
+            step()?;
+            self.offload(offloads)?;
+        }
         if let Some(owner) = params.owner {
+            step()?;
             self.owner(owner)?;
         }
         if let Some(group) = params.group {
+            step()?;
             self.group(group)?;
         }
         if let Some(address) = params.address {
+            step()?;
             self.address(Some(address))?;
         }
         if let Some(netmask) = params.netmask {
+            step()?;
             self.netmask(Some(netmask))?;
         }
         if let Some(destination) = params.destination {
+            step()?;
             self.destination(Some(destination))?;
         }
         if let Some(broadcast) = params.broadcast {
+            step()?;
             self.broadcast(Some(broadcast))?;
         }
-        if params.persist {
-            self.persist()?;
+        for &(address, prefix_len) in params.ipv6_addresses.iter() {
+            step()?;
+            self.add_address(address.into(), prefix_len)?;
+        }
+        if let Some(bridge) = params.bridge {
+            step()?;
+            self.master(Some(&bridge))?;
         }
         if params.up {
+            step()?;
             self.flags(Some(libc::IFF_UP as i16 | libc::IFF_RUNNING as i16))?;
         }
+        for (i, route) in params.routes.iter().enumerate() {
+            if let Err(err) = step()
+                .map_err(Into::into)
+                .and_then(|()| self.add_route(route))
+            {
+                for route in params.routes[..i].iter() {
+                    let _ = self.remove_route(route);
+                }
+                return Err(err);
+            }
+        }
+        if params.persist {
+            step()?;
+            self.persist(true)?;
+        }
         Ok(())
     }
 
-    pub fn files(&self) -> &[i32] {
-        &self.fds
+    /// Dumps the configuration of device, see [`Tun::config`](struct.Tun.html#method.config).
+    pub fn config(&self) -> Result<TunConfig> {
+        let flags = self.tun_flags()?;
+        let address = optional(self.address(None))?;
+        let unset = |addr: Option<Ipv4Addr>| {
+            addr.filter(|&addr| !addr.is_unspecified() && Some(addr) != address)
+        };
+        Ok(TunConfig {
+            name: self.name.clone(),
+            kind: if flags & libc::IFF_TAP as i16 != 0 {
+                DeviceKind::Tap
+            } else {
+                DeviceKind::Tun
+            },
+            packet_info: flags & libc::IFF_NO_PI as i16 == 0,
+            vnet_hdr: flags & libc::IFF_VNET_HDR as i16 != 0,
+            mtu: Some(self.mtu(None)?),
+            owner: self.sysfs_id("owner"),
+            group: self.sysfs_id("group"),
+            address,
+            destination: unset(optional(self.destination(None))?),
+            broadcast: unset(optional(self.broadcast(None))?),
+            netmask: match address {
+                Some(_) => optional(self.netmask(None))?,
+                None => None,
+            },
+            ipv6_addresses: self.ipv6_addresses()?,
+            routes: self.routes(false)?,
+            bridge: self.master_name()?,
+            persist: flags & libc::IFF_PERSIST as i16 != 0,
+            up: self.flags(None)? & libc::IFF_UP as i16 != 0,
+            queues: self.queues(),
+            offloads: self.offloads,
+        })
+    }
+
+    /// Returns the number of queues, which are read from sysfs for an interface without any.
+    pub fn queues(&self) -> usize {
+        let queues = self.queue_fds().len();
+        if queues > 0 {
+            return queues;
+        }
+        std::fs::read_dir(format!("/sys/class/net/{}/queues", self.name))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_name().to_string_lossy().starts_with("rx-"))
+                    .count()
+            })
+            .unwrap_or_default()
+            .max(1)
     }
 
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
 
+    pub fn index(&self) -> i32 {
+        self.index
+    }
+
+    /// Returns the flags the device was attached with, e.g. `IFF_TAP` or `IFF_NO_PI`.
+    pub fn attach_flags(&self) -> i16 {
+        self.flags
+    }
+
+    fn request(&self, msg: Message) -> Result<Vec<netlink::Response>> {
+        self.netlink
+            .lock()
+            .map_err(|_| io::Error::other("netlink socket is poisoned"))?
+            .request(msg)
+    }
+
+    pub fn move_to_netns(&self, fd: i32) -> Result<()> {
+        let msg = Message::new(netlink::RTM_NEWLINK, 0)
+            .header(&ifinfomsg {
+                ifi_index: self.index,
+                ..Default::default()
+            })
+            .attr_u32(netlink::IFLA_NET_NS_FD, fd as u32);
+        self.request(msg)?;
+        Ok(())
+    }
+
+    /// Returns the index of the interface named `name`.
+    pub fn index_of(&self, name: &str) -> Result<i32> {
+        let mut req = ifreq::new(name);
+        unsafe { siocgifindex(self.socket.as_raw_fd(), &mut req) }?;
+        Ok(unsafe { req.ifr_ifru.ifru_ivalue })
+    }
+
+    /// Returns the name of the interface whose index is `index`.
+    pub fn name_of(&self, index: i32) -> Result<String> {
+        let mut req = ifreq::new("");
+        req.ifr_ifru.ifru_ivalue = index;
+        unsafe { siocgifname(self.socket.as_raw_fd(), &mut req) }?;
+        Ok(req.name().to_owned())
+    }
+
+    /// Returns the name of the master device, if the device is enslaved.
+    pub fn master_name(&self) -> Result<Option<String>> {
+        let link = self.link()?;
+        match Attrs::after::<ifinfomsg>(&link)
+            .find(|&(kind, _)| kind == netlink::IFLA_MASTER)
+            .and_then(|(_, attr)| netlink::parse_u32(attr))
+        {
+            Some(index) if index != 0 => Ok(Some(self.name_of(index as i32)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Enslaves the device to the `master` interface, or releases it if `master` is `None`.
+    pub fn master(&self, master: Option<&str>) -> Result<()> {
+        let master = match master {
+            Some(name) => self.index_of(name)?,
+            None => 0,
+        };
+        let msg = Message::new(netlink::RTM_NEWLINK, 0)
+            .header(&ifinfomsg {
+                ifi_index: self.index,
+                ..Default::default()
+            })
+            .attr_u32(netlink::IFLA_MASTER, master as u32);
+        self.request(msg)?;
+        Ok(())
+    }
+
+    /// Remembers the network namespace of the calling thread as the namespace of device, so
+    /// that sockets created later on are opened in it.
+    pub fn pin_netns(&mut self) -> Result<()> {
+        static NETNS: &[u8] = b"/proc/thread-self/ns/net\0";
+        let fd = unsafe { libc::open(NETNS.as_ptr().cast(), libc::O_RDONLY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        self.netns = Some(unsafe { OwnedFd::from_raw_fd(fd) }.into());
+        Ok(())
+    }
+
+    /// Opens a netlink socket in the namespace of device which is subscribed to `groups`.
+    #[cfg(feature = "tokio")]
+    pub fn subscribe(&self, groups: u32) -> Result<Netlink> {
+        match &self.netns {
+            Some(netns) => netns.run(|| Netlink::subscribe(groups)),
+            None => Netlink::subscribe(groups),
+        }
+    }
+
+    /// Returns the payload of the `RTM_NEWLINK` message describing the device.
+    pub fn link(&self) -> Result<Vec<u8>> {
+        let msg = Message::new(netlink::RTM_GETLINK, 0).header(&ifinfomsg {
+            ifi_index: self.index,
+            ..Default::default()
+        });
+        self.request(msg)?
+            .into_iter()
+            .find(|res| res.kind == netlink::RTM_NEWLINK)
+            .map(|res| res.payload)
+            .ok_or_else(|| io::Error::other("missing RTM_NEWLINK response").into())
+    }
+
+    pub fn add_address(&self, address: IpAddr, prefix_len: u8) -> Result<()> {
+        let family = match address {
+            IpAddr::V4(_) => libc::AF_INET,
+            IpAddr::V6(_) => libc::AF_INET6,
+        };
+        let msg = Message::new(
+            netlink::RTM_NEWADDR,
+            netlink::NLM_F_CREATE | netlink::NLM_F_EXCL,
+        )
+        .header(&ifaddrmsg {
+            ifa_family: family as u8,
+            ifa_prefixlen: prefix_len,
+            ifa_index: self.index as u32,
+            ..Default::default()
+        })
+        .attr_ip(netlink::IFA_LOCAL, address)
+        .attr_ip(netlink::IFA_ADDRESS, address);
+        self.request(msg)?;
+        Ok(())
+    }
+
+    /// Returns the addresses of device along with their prefix length.
+    #[cfg(feature = "tokio")]
+    pub fn addresses(&self) -> Result<Vec<(IpAddr, u8)>> {
+        let msg =
+            Message::new(netlink::RTM_GETADDR, netlink::NLM_F_DUMP).header(&ifaddrmsg::default());
+        Ok(self
+            .request(msg)?
+            .iter()
+            .filter(|res| res.kind == netlink::RTM_NEWADDR)
+            .filter_map(|res| netlink::parse_addr(&res.payload))
+            .filter(|&(index, _, _)| index == self.index as u32)
+            .map(|(_, address, prefix_len)| (address, prefix_len))
+            .collect())
+    }
+
+    /// Returns the global IPv6 addresses of device, link-local addresses are skipped.
+    pub fn ipv6_addresses(&self) -> Result<Vec<(Ipv6Addr, u8)>> {
+        let msg = Message::new(netlink::RTM_GETADDR, netlink::NLM_F_DUMP).header(&ifaddrmsg {
+            ifa_family: libc::AF_INET6 as u8,
+            ..Default::default()
+        });
+        Ok(self
+            .request(msg)?
+            .iter()
+            .filter(|res| res.kind == netlink::RTM_NEWADDR)
+            .filter(|res| {
+                netlink::read::<ifaddrmsg>(&res.payload)
+                    .is_some_and(|hdr| hdr.ifa_scope != netlink::RT_SCOPE_LINK)
+            })
+            .filter_map(|res| netlink::parse_addr(&res.payload))
+            .filter_map(|(index, address, prefix_len)| match address {
+                IpAddr::V6(address) if index == self.index as u32 => Some((address, prefix_len)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub fn add_route(&self, route: &Route) -> Result<()> {
+        let flags = netlink::NLM_F_CREATE | netlink::NLM_F_EXCL;
+        self.request(self.route_message(netlink::RTM_NEWROUTE, flags, route)?)?;
+        Ok(())
+    }
+
+    pub fn remove_route(&self, route: &Route) -> Result<()> {
+        self.request(self.route_message(netlink::RTM_DELROUTE, 0, route)?)?;
+        Ok(())
+    }
+
+    /// Returns the routes through the device, routes which the kernel derived from the
+    /// addresses of device are skipped unless `kernel` is true.
+    pub fn routes(&self, kernel: bool) -> Result<Vec<Route>> {
+        let msg =
+            Message::new(netlink::RTM_GETROUTE, netlink::NLM_F_DUMP).header(&rtmsg::default());
+        Ok(self
+            .request(msg)?
+            .iter()
+            .filter(|res| res.kind == netlink::RTM_NEWROUTE)
+            .filter(|res| {
+                kernel
+                    || netlink::read::<rtmsg>(&res.payload)
+                        .is_some_and(|hdr| hdr.rtm_protocol != netlink::RTPROT_KERNEL)
+            })
+            .filter_map(|res| self.parse_route(&res.payload))
+            .collect())
+    }
+
+    fn route_message(&self, kind: u16, flags: u16, route: &Route) -> Result<Message> {
+        let (family, max_len) = match route.destination {
+            IpAddr::V4(_) => (libc::AF_INET, 32),
+            IpAddr::V6(_) => (libc::AF_INET6, 128),
+        };
+        if route.prefix_len > max_len {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid prefix length").into(),
+            );
+        }
+        if matches!(route.gateway, Some(gateway) if gateway.is_ipv4() != route.destination.is_ipv4())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gateway and destination belong to different address families",
+            )
+            .into());
+        }
+        let deleting = kind == netlink::RTM_DELROUTE;
+        let mut msg = Message::new(kind, flags).header(&rtmsg {
+            rtm_family: family as u8,
+            rtm_dst_len: route.prefix_len,
+            rtm_table: netlink::RT_TABLE_MAIN,
+            rtm_protocol: if deleting { 0 } else { netlink::RTPROT_BOOT },
+            rtm_scope: match route.gateway {
+                _ if deleting => netlink::RT_SCOPE_NOWHERE,
+                Some(_) => netlink::RT_SCOPE_UNIVERSE,
+                None => netlink::RT_SCOPE_LINK,
+            },
+            rtm_type: netlink::RTN_UNICAST,
+            ..Default::default()
+        });
+        if route.prefix_len > 0 {
+            msg = msg.attr_ip(netlink::RTA_DST, route.destination);
+        }
+        if let Some(gateway) = route.gateway {
+            msg = msg.attr_ip(netlink::RTA_GATEWAY, gateway);
+        }
+        msg = msg.attr_u32(netlink::RTA_OIF, self.index as u32);
+        if let Some(metric) = route.metric {
+            msg = msg.attr_u32(netlink::RTA_PRIORITY, metric);
+        }
+        Ok(msg)
+    }
+
+    fn parse_route(&self, payload: &[u8]) -> Option<Route> {
+        let hdr: rtmsg = netlink::read(payload)?;
+        if hdr.rtm_type != netlink::RTN_UNICAST {
+            return None;
+        }
+        let mut route = Route::new(
+            match hdr.rtm_family as i32 {
+                libc::AF_INET => Ipv4Addr::UNSPECIFIED.into(),
+                libc::AF_INET6 => Ipv6Addr::UNSPECIFIED.into(),
+                _ => return None,
+            },
+            hdr.rtm_dst_len,
+        );
+        let mut table = hdr.rtm_table as u32;
+        let mut oif = None;
+        for (kind, attr) in Attrs::after::<rtmsg>(payload) {
+            match kind {
+                netlink::RTA_DST => route.destination = netlink::parse_ip(hdr.rtm_family, attr)?,
+                netlink::RTA_GATEWAY => route.gateway = netlink::parse_ip(hdr.rtm_family, attr),
+                netlink::RTA_PRIORITY => route.metric = netlink::parse_u32(attr),
+                netlink::RTA_TABLE => table = netlink::parse_u32(attr)?,
+                netlink::RTA_OIF => oif = netlink::parse_u32(attr),
+                _ => {}
+            }
+        }
+        if oif != Some(self.index as u32) || table != netlink::RT_TABLE_MAIN as u32 {
+            return None;
+        }
+        Some(route)
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        let link = self.link()?;
+        Attrs::after::<ifinfomsg>(&link)
+            .find(|&(kind, _)| kind == netlink::IFLA_STATS64)
+            .and_then(|(_, payload)| Stats::from_stats64(payload))
+            .ok_or_else(|| io::Error::other("missing IFLA_STATS64 attribute").into())
+    }
+
     pub fn mtu(&self, mtu: Option<i32>) -> Result<i32> {
         let mut req = ifreq::new(self.name());
         if let Some(mtu) = mtu {
             req.ifr_ifru.ifru_mtu = mtu;
-            unsafe { siocsifmtu(self.socket, &req) }?;
+            unsafe { siocsifmtu(self.socket.as_raw_fd(), &req) }?;
         } else {
-            unsafe { siocgifmtu(self.socket, &mut req) }?;
+            unsafe { siocgifmtu(self.socket.as_raw_fd(), &mut req) }?;
         }
         Ok(unsafe { req.ifr_ifru.ifru_mtu })
     }
@@ -101,10 +535,10 @@ impl Interface {
         let mut req = ifreq::new(self.name());
         if let Some(netmask) = netmask {
             req.ifr_ifru.ifru_netmask = netmask.to_address();
-            unsafe { siocsifnetmask(self.socket, &req) }?;
+            unsafe { siocsifnetmask(self.socket.as_raw_fd(), &req) }?;
             return Ok(netmask);
         }
-        unsafe { siocgifnetmask(self.socket, &mut req) }?;
+        unsafe { siocgifnetmask(self.socket.as_raw_fd(), &mut req) }?;
         Ok(unsafe { Ipv4Addr::from_address(req.ifr_ifru.ifru_netmask) })
     }
 
@@ -112,10 +546,10 @@ impl Interface {
         let mut req = ifreq::new(self.name());
         if let Some(address) = address {
             req.ifr_ifru.ifru_addr = address.to_address();
-            unsafe { siocsifaddr(self.socket, &req) }?;
+            unsafe { siocsifaddr(self.socket.as_raw_fd(), &req) }?;
             return Ok(address);
         }
-        unsafe { siocgifaddr(self.socket, &mut req) }?;
+        unsafe { siocgifaddr(self.socket.as_raw_fd(), &mut req) }?;
         Ok(unsafe { Ipv4Addr::from_address(req.ifr_ifru.ifru_addr) })
     }
 
@@ -123,10 +557,10 @@ impl Interface {
         let mut req = ifreq::new(self.name());
         if let Some(dst) = dst {
             req.ifr_ifru.ifru_dstaddr = dst.to_address();
-            unsafe { siocsifdstaddr(self.socket, &req) }?;
+            unsafe { siocsifdstaddr(self.socket.as_raw_fd(), &req) }?;
             return Ok(dst);
         }
-        unsafe { siocgifdstaddr(self.socket, &mut req) }?;
+        unsafe { siocgifdstaddr(self.socket.as_raw_fd(), &mut req) }?;
         Ok(unsafe { Ipv4Addr::from_address(req.ifr_ifru.ifru_dstaddr) })
     }
 
@@ -134,47 +568,175 @@ impl Interface {
         let mut req = ifreq::new(self.name());
         if let Some(broadcast) = broadcast {
             req.ifr_ifru.ifru_broadaddr = broadcast.to_address();
-            unsafe { siocsifbrdaddr(self.socket, &req) }?;
+            unsafe { siocsifbrdaddr(self.socket.as_raw_fd(), &req) }?;
             return Ok(broadcast);
         }
-        unsafe { siocgifbrdaddr(self.socket, &mut req) }?;
+        unsafe { siocgifbrdaddr(self.socket.as_raw_fd(), &mut req) }?;
         Ok(unsafe { Ipv4Addr::from_address(req.ifr_ifru.ifru_broadaddr) })
     }
 
     pub fn flags(&self, flags: Option<i16>) -> Result<i16> {
         let mut req = ifreq::new(self.name());
-        unsafe { siocgifflags(self.socket, &mut req) }?;
+        unsafe { siocgifflags(self.socket.as_raw_fd(), &mut req) }?;
         if let Some(flags) = flags {
             unsafe { req.ifr_ifru.ifru_flags |= flags };
-            unsafe { siocsifflags(self.socket, &req) }?;
+            unsafe { siocsifflags(self.socket.as_raw_fd(), &req) }?;
         }
         Ok(unsafe { req.ifr_ifru.ifru_flags })
     }
 
     pub fn owner(&self, owner: i32) -> Result<()> {
-        for fd in self.fds.iter() {
-            unsafe { tunsetowner(*fd, owner as _) }?;
+        for fd in self.queue_fds() {
+            unsafe { tunsetowner(fd.as_raw_fd(), owner as _) }?;
         }
         Ok(())
     }
 
     pub fn group(&self, group: i32) -> Result<()> {
-        for fd in self.fds.iter() {
-            unsafe { tunsetgroup(*fd, group as _) }?;
+        for fd in self.queue_fds() {
+            unsafe { tunsetgroup(fd.as_raw_fd(), group as _) }?;
+        }
+        Ok(())
+    }
+
+    pub fn persist(&self, persist: bool) -> Result<()> {
+        self.control(|fd| Ok(unsafe { tunsetpersist(fd, persist as _) }?))?;
+        Ok(())
+    }
+
+    pub fn offload(&mut self, offloads: u32) -> Result<()> {
+        for fd in self.queue_fds() {
+            unsafe { tunsetoffload(fd.as_raw_fd(), offloads as _) }?;
         }
+        self.offloads = Some(offloads);
         Ok(())
     }
 
-    pub fn persist(&self) -> Result<()> {
-        for fd in self.fds.iter() {
-            unsafe { tunsetpersist(*fd, 1) }?;
+    /// Returns the flags of device (`TUNGETIFF`), including `IFF_PERSIST`.
+    ///
+    /// `IFF_NO_PI` is taken from the flags the device was attached with, see `get_iff`.
+    pub fn tun_flags(&self) -> Result<i16> {
+        if self.queue_fds().is_empty() {
+            return sysfs_flags(&self.name)
+                .ok_or_else(|| io::Error::other("device is not visible in sysfs").into());
+        }
+        let (_, flags) = self.control(get_iff)?;
+        Ok(flags | self.flags & libc::IFF_NO_PI as i16)
+    }
+
+    /// Returns the flags supported by the tun driver (`TUNGETFEATURES`), `fd` may be any open
+    /// clone device.
+    pub fn features(fd: i32) -> Result<i32> {
+        let mut features = 0;
+        unsafe { tungetfeatures(fd, &mut features) }?;
+        Ok(features as i32)
+    }
+
+    /// Returns the name and flags of the device which `fd` is attached to.
+    ///
+    /// `IFF_NO_PI` is read from sysfs, see `get_iff`, and assumed to be unset if the device is
+    /// not visible there.
+    pub fn attachment(fd: i32) -> Result<(String, i16)> {
+        let (name, flags) = get_iff(fd)?;
+        let sysfs = sysfs_flags(&name).unwrap_or_default();
+        Ok((name, flags | sysfs & libc::IFF_NO_PI as i16))
+    }
+
+    /// Reads a numeric id (e.g. `owner` or `group`) of device from sysfs, `-1` means unset.
+    pub fn sysfs_id(&self, attr: &str) -> Option<i32> {
+        let path = format!("/sys/class/net/{}/{}", self.name, attr);
+        match std::fs::read_to_string(path).ok()?.trim().parse() {
+            Ok(-1) | Err(_) => None,
+            Ok(id) => Some(id),
+        }
+    }
+
+    pub fn tx_filter(&self, addrs: &[MacAddr], allmulti: bool) -> Result<()> {
+        // Layout of `struct tun_filter`: flags (u16), count (u16) and `count` addresses.
+        let mut filter = Vec::with_capacity(4 + addrs.len() * 6);
+        let flags = if allmulti { TUN_FLT_ALLMULTI } else { 0 };
+        let count = u16::try_from(addrs.len())?;
+        filter.extend_from_slice(&flags.to_ne_bytes());
+        filter.extend_from_slice(&count.to_ne_bytes());
+        for addr in addrs {
+            filter.extend_from_slice(&addr.octets());
         }
+        self.control(|fd| Ok(unsafe { tunsettxfilter(fd, filter.as_ptr().cast()) }?))?;
+        Ok(())
+    }
+
+    /// Attaches the steering program `prog`, or detaches it if `None` (`TUNSETSTEERINGEBPF`).
+    ///
+    /// The kernel holds a reference to the program, so it may be closed once attached.
+    pub fn steering(&self, prog: Option<&OwnedFd>) -> Result<()> {
+        let fd = prog.map_or(-1, |prog| prog.as_raw_fd());
+        self.control(|queue| Ok(unsafe { tunsetsteeringebpf(queue, &fd) }?))?;
         Ok(())
     }
+
+    /// Attaches or detaches the queue `fd` of a multiqueue device (`TUNSETQUEUE`).
+    #[cfg(feature = "pool")]
+    pub fn set_queue(fd: RawFd, attach: bool) -> Result<()> {
+        let mut req = ifreq::new("");
+        req.ifr_ifru.ifru_flags = if attach {
+            libc::IFF_ATTACH_QUEUE
+        } else {
+            libc::IFF_DETACH_QUEUE
+        } as _;
+        unsafe { tunsetqueue(fd, &req as *const _ as _) }?;
+        Ok(())
+    }
+
+    /// Returns the queues of device which are still open, dropping the closed ones from the
+    /// list. The queues can not be closed while they are in use.
+    fn queue_fds(&self) -> Vec<Arc<OwnedFd>> {
+        let mut fds = self.fds.lock().unwrap_or_else(|err| err.into_inner());
+        fds.retain(|fd| fd.strong_count() > 0);
+        fds.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Calls `f` with a queue to apply a setting to the whole device.
+    ///
+    /// A queue which was detached with `TUNSETQUEUE` rejects such settings with `EBADFD`, so
+    /// they are applied through the first queue which accepts them.
+    fn control<T>(&self, f: impl Fn(RawFd) -> Result<T>) -> Result<T> {
+        let mut res = Err(io::Error::other("device has no open queues").into());
+        for fd in self.queue_fds() {
+            res = f(fd.as_raw_fd());
+            match &res {
+                Err(err) if err.downcast_ref() == Some(&nix::errno::Errno::EBADFD) => continue,
+                _ => break,
+            }
+        }
+        res
+    }
+}
+
+/// Returns the name and flags of the device which `fd` is attached to (`TUNGETIFF`), without
+/// `IFF_NO_PI`.
+///
+/// The kernel sets `IFF_NOFILTER`, which shares its bit with `IFF_NO_PI`, whenever no socket
+/// filter is attached to the queue, so the bit does not tell whether packets carry the packet
+/// information header.
+fn get_iff(fd: RawFd) -> Result<(String, i16)> {
+    let mut req = ifreq::new("");
+    unsafe { tungetiff(fd, &mut req) }?;
+    let flags = unsafe { req.ifr_ifru.ifru_flags } & !(libc::IFF_NO_PI as i16);
+    Ok((req.name().to_owned(), flags))
+}
+
+/// Reads the flags of device `name` from sysfs, or returns `None` if it is not a Tun/Tap
+/// device visible there.
+fn sysfs_flags(name: &str) -> Option<i16> {
+    let flags = std::fs::read_to_string(format!("/sys/class/net/{}/tun_flags", name)).ok()?;
+    i16::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok()
 }
 
-impl Drop for Interface {
-    fn drop(&mut self) {
-        unsafe { libc::close(self.socket) };
+/// Maps the error returned for an unassigned address to `None`.
+fn optional<T>(res: Result<T>) -> Result<Option<T>> {
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.downcast_ref() == Some(&nix::errno::Errno::EADDRNOTAVAIL) => Ok(None),
+        Err(err) => Err(err),
     }
 }
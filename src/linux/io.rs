@@ -1,24 +1,42 @@
 use std::convert::From;
 use std::io::{self, Read, Write};
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Weak};
+use std::thread;
 
-pub struct TunIo(RawFd);
+/// Represents a queue of device, which owns its file descriptor.
+///
+/// The [`Interface`](../interface/struct.Interface.html) of device only holds weak references
+/// to the queues, so the file descriptor is closed as soon as the queue is dropped.
+pub struct TunIo(Arc<OwnedFd>);
 
-impl From<RawFd> for TunIo {
-    fn from(fd: RawFd) -> Self {
-        Self(fd)
+impl From<OwnedFd> for TunIo {
+    fn from(fd: OwnedFd) -> Self {
+        Self(Arc::new(fd))
     }
 }
 
 impl FromRawFd for TunIo {
     unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        Self(fd)
+        Self::from(OwnedFd::from_raw_fd(fd))
     }
 }
 
 impl AsRawFd for TunIo {
     fn as_raw_fd(&self) -> RawFd {
-        self.0
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsFd for TunIo {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl IntoRawFd for TunIo {
+    fn into_raw_fd(self) -> RawFd {
+        self.into_fd().into_raw_fd()
     }
 }
 
@@ -34,7 +52,7 @@ impl Write for TunIo {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let ret = unsafe { libc::fsync(self.0) };
+        let ret = unsafe { libc::fsync(self.as_raw_fd()) };
         if ret < 0 {
             return Err(io::Error::last_os_error());
         }
@@ -43,8 +61,42 @@ impl Write for TunIo {
 }
 
 impl TunIo {
+    /// Duplicates the file descriptor, which then refers to the same queue.
+    #[cfg(feature = "pool")]
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self::from(self.0.try_clone()?))
+    }
+
+    /// Returns another handle to the file descriptor, which keeps it open as long as either
+    /// handle is alive.
+    #[cfg(feature = "async-io")]
+    pub fn share(&self) -> Self {
+        Self(self.0.clone())
+    }
+
+    /// Returns a weak reference to the file descriptor, which can not be upgraded once the
+    /// queue is dropped.
+    pub fn downgrade(&self) -> Weak<OwnedFd> {
+        Arc::downgrade(&self.0)
+    }
+
+    /// Returns the file descriptor, waiting for the interface of device to release it if it
+    /// is applying a setting through this queue.
+    pub fn into_fd(self) -> OwnedFd {
+        let mut fd = self.0;
+        loop {
+            match Arc::try_unwrap(fd) {
+                Ok(fd) => return fd,
+                Err(shared) => {
+                    fd = shared;
+                    thread::yield_now();
+                }
+            }
+        }
+    }
+
     pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        let n = unsafe { libc::read(self.0, buf.as_ptr() as *mut _, buf.len() as _) };
+        let n = unsafe { libc::read(self.as_raw_fd(), buf.as_ptr() as *mut _, buf.len() as _) };
         if n < 0 {
             return Err(io::Error::last_os_error());
         }
@@ -52,16 +104,10 @@ impl TunIo {
     }
 
     pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        let n = unsafe { libc::write(self.0, buf.as_ptr() as *const _, buf.len() as _) };
+        let n = unsafe { libc::write(self.as_raw_fd(), buf.as_ptr() as *const _, buf.len() as _) };
         if n < 0 {
             return Err(io::Error::last_os_error());
         }
         Ok(n as _)
     }
 }
-
-impl Drop for TunIo {
-    fn drop(&mut self) {
-        unsafe { libc::close(self.0) };
-    }
-}
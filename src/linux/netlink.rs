@@ -0,0 +1,405 @@
+#![allow(non_camel_case_types)]
+#![allow(dead_code)]
+
+use crate::result::Result;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+pub const NLMSG_ERROR: u16 = 2;
+pub const NLMSG_DONE: u16 = 3;
+
+pub const NLM_F_REQUEST: u16 = 0x01;
+pub const NLM_F_MULTI: u16 = 0x02;
+pub const NLM_F_ACK: u16 = 0x04;
+pub const NLM_F_DUMP: u16 = 0x300;
+pub const NLM_F_EXCL: u16 = 0x200;
+pub const NLM_F_CREATE: u16 = 0x400;
+
+pub const RTM_NEWLINK: u16 = 16;
+pub const RTM_DELLINK: u16 = 17;
+pub const RTM_GETLINK: u16 = 18;
+pub const RTM_SETLINK: u16 = 19;
+pub const RTM_NEWADDR: u16 = 20;
+pub const RTM_DELADDR: u16 = 21;
+pub const RTM_GETADDR: u16 = 22;
+pub const RTM_NEWROUTE: u16 = 24;
+pub const RTM_DELROUTE: u16 = 25;
+pub const RTM_GETROUTE: u16 = 26;
+
+pub const IFLA_IFNAME: u16 = 3;
+pub const IFLA_MTU: u16 = 4;
+pub const IFLA_MASTER: u16 = 10;
+pub const IFLA_LINKINFO: u16 = 18;
+pub const IFLA_STATS64: u16 = 23;
+pub const IFLA_NET_NS_FD: u16 = 28;
+
+pub const IFLA_INFO_KIND: u16 = 1;
+pub const IFLA_INFO_DATA: u16 = 2;
+pub const VETH_INFO_PEER: u16 = 1;
+
+pub const IFA_ADDRESS: u16 = 1;
+pub const IFA_LOCAL: u16 = 2;
+
+pub const RTA_DST: u16 = 1;
+pub const RTA_OIF: u16 = 4;
+pub const RTA_GATEWAY: u16 = 5;
+pub const RTA_PRIORITY: u16 = 6;
+pub const RTA_TABLE: u16 = 15;
+
+pub const RT_TABLE_MAIN: u8 = 254;
+pub const RTPROT_KERNEL: u8 = 2;
+pub const RTPROT_BOOT: u8 = 3;
+pub const RT_SCOPE_UNIVERSE: u8 = 0;
+pub const RT_SCOPE_LINK: u8 = 253;
+pub const RT_SCOPE_NOWHERE: u8 = 255;
+pub const RTN_UNICAST: u8 = 1;
+
+const NLMSG_HDRLEN: usize = mem::size_of::<nlmsghdr>();
+const RTA_HDRLEN: usize = mem::size_of::<rtattr>();
+const NLA_TYPE_MASK: u16 = 0x3fff;
+const RECV_BUF_SIZE: usize = 32 * 1024;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct nlmsghdr {
+    pub nlmsg_len: u32,
+    pub nlmsg_type: u16,
+    pub nlmsg_flags: u16,
+    pub nlmsg_seq: u32,
+    pub nlmsg_pid: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rtattr {
+    pub rta_len: u16,
+    pub rta_type: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ifinfomsg {
+    pub ifi_family: u8,
+    pub ifi_pad: u8,
+    pub ifi_type: u16,
+    pub ifi_index: i32,
+    pub ifi_flags: u32,
+    pub ifi_change: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ifaddrmsg {
+    pub ifa_family: u8,
+    pub ifa_prefixlen: u8,
+    pub ifa_flags: u8,
+    pub ifa_scope: u8,
+    pub ifa_index: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rtmsg {
+    pub rtm_family: u8,
+    pub rtm_dst_len: u8,
+    pub rtm_src_len: u8,
+    pub rtm_tos: u8,
+    pub rtm_table: u8,
+    pub rtm_protocol: u8,
+    pub rtm_scope: u8,
+    pub rtm_type: u8,
+    pub rtm_flags: u32,
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Reads a `repr(C)` header from the beginning of `buf`.
+pub fn read<T: Copy>(buf: &[u8]) -> Option<T> {
+    if buf.len() < mem::size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { ptr::read_unaligned(buf.as_ptr().cast::<T>()) })
+}
+
+/// Parses an address attribute of the given address family.
+pub fn parse_ip(family: u8, payload: &[u8]) -> Option<IpAddr> {
+    match family as i32 {
+        libc::AF_INET => Some(Ipv4Addr::from(<[u8; 4]>::try_from(payload).ok()?).into()),
+        libc::AF_INET6 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(payload).ok()?).into()),
+        _ => None,
+    }
+}
+
+pub fn parse_u32(payload: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(payload.try_into().ok()?))
+}
+
+/// Parses a null terminated string attribute.
+pub fn parse_str(payload: &[u8]) -> Option<String> {
+    let payload = payload.split(|&b| b == 0).next()?;
+    Some(std::str::from_utf8(payload).ok()?.to_owned())
+}
+
+/// Parses the payload of a `RTM_NEWADDR` or `RTM_DELADDR` message, returning the interface
+/// index, the local address and its prefix length.
+pub fn parse_addr(payload: &[u8]) -> Option<(u32, IpAddr, u8)> {
+    let hdr: ifaddrmsg = read(payload)?;
+    let (mut address, mut local) = (None, None);
+    for (kind, attr) in Attrs::after::<ifaddrmsg>(payload) {
+        match kind {
+            IFA_ADDRESS => address = parse_ip(hdr.ifa_family, attr),
+            IFA_LOCAL => local = parse_ip(hdr.ifa_family, attr),
+            _ => {}
+        }
+    }
+    // On point-to-point links, `IFA_ADDRESS` holds the address of the peer.
+    Some((hdr.ifa_index, local.or(address)?, hdr.ifa_prefixlen))
+}
+
+fn bytes_of<T: Copy>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts((value as *const T).cast::<u8>(), mem::size_of::<T>()) }
+}
+
+/// Represents an outgoing rtnetlink request.
+pub struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    /// Creates a new request, non-dump requests are always acknowledged by the kernel.
+    pub fn new(kind: u16, mut flags: u16) -> Self {
+        if flags & NLM_F_DUMP != NLM_F_DUMP {
+            flags |= NLM_F_ACK;
+        }
+        let hdr = nlmsghdr {
+            nlmsg_type: kind,
+            nlmsg_flags: flags | NLM_F_REQUEST,
+            ..Default::default()
+        };
+        Self {
+            buf: bytes_of(&hdr).to_vec(),
+        }
+    }
+
+    /// Appends the family specific header (e.g. `ifinfomsg`) of the message.
+    pub fn header<T: Copy>(mut self, header: &T) -> Self {
+        self.buf.extend_from_slice(bytes_of(header));
+        self.pad();
+        self
+    }
+
+    pub fn attr(mut self, kind: u16, payload: &[u8]) -> Self {
+        let attr = rtattr {
+            rta_len: (RTA_HDRLEN + payload.len()) as u16,
+            rta_type: kind,
+        };
+        self.buf.extend_from_slice(bytes_of(&attr));
+        self.buf.extend_from_slice(payload);
+        self.pad();
+        self
+    }
+
+    pub fn attr_u32(self, kind: u16, value: u32) -> Self {
+        self.attr(kind, &value.to_ne_bytes())
+    }
+
+    pub fn attr_ip(self, kind: u16, addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => self.attr(kind, &addr.octets()),
+            IpAddr::V6(addr) => self.attr(kind, &addr.octets()),
+        }
+    }
+
+    /// Appends a null terminated string attribute.
+    pub fn attr_str(self, kind: u16, value: &str) -> Self {
+        let mut payload = Vec::with_capacity(value.len() + 1);
+        payload.extend_from_slice(value.as_bytes());
+        payload.push(0);
+        self.attr(kind, &payload)
+    }
+
+    /// Appends a nested attribute whose payload is written by `f`.
+    pub fn nested(mut self, kind: u16, f: impl FnOnce(Self) -> Self) -> Self {
+        let start = self.buf.len();
+        self = self.attr(kind, &[]);
+        self = f(self);
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+
+    fn pad(&mut self) {
+        self.buf.resize(align(self.buf.len()), 0);
+    }
+
+    fn finish(&mut self, seq: u32) -> &[u8] {
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        &self.buf
+    }
+}
+
+/// Represents a single message received from the kernel.
+pub struct Response {
+    pub kind: u16,
+    pub payload: Vec<u8>,
+}
+
+/// Iterates over the `rtattr` attributes of a message, yielding `(type, payload)`.
+pub struct Attrs<'a>(&'a [u8]);
+
+impl<'a> Attrs<'a> {
+    /// Creates an iterator over the attributes which follow a header of type `T`.
+    pub fn after<T>(payload: &'a [u8]) -> Self {
+        Self(
+            payload
+                .get(align(mem::size_of::<T>())..)
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self(buf)
+    }
+}
+
+impl<'a> Iterator for Attrs<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let attr: rtattr = read(self.0)?;
+        let len = attr.rta_len as usize;
+        if len < RTA_HDRLEN || len > self.0.len() {
+            return None;
+        }
+        let payload = &self.0[RTA_HDRLEN..len];
+        self.0 = self.0.get(align(len)..).unwrap_or_default();
+        Some((attr.rta_type & NLA_TYPE_MASK, payload))
+    }
+}
+
+/// Represents a `NETLINK_ROUTE` socket.
+pub struct Netlink {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl Netlink {
+    pub fn new() -> Result<Self> {
+        Self::open(0, 0)
+    }
+
+    /// Creates a non-blocking socket which is subscribed to the given multicast `groups`.
+    pub fn subscribe(groups: u32) -> Result<Self> {
+        Self::open(groups, libc::SOCK_NONBLOCK)
+    }
+
+    fn open(groups: u32, flags: i32) -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let netlink = Self { fd, seq: 0 };
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as _;
+        addr.nl_groups = groups;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                (&addr as *const libc::sockaddr_nl).cast(),
+                mem::size_of::<libc::sockaddr_nl>() as _,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(netlink)
+    }
+
+    /// Sends `msg` and collects the responses until the kernel acknowledges the request or
+    /// finishes the dump.
+    pub fn request(&mut self, mut msg: Message) -> Result<Vec<Response>> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        let buf = msg.finish(seq);
+        let n = unsafe { libc::send(self.fd, buf.as_ptr().cast(), buf.len(), 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut responses = Vec::new();
+        let mut buf = vec![0u8; RECV_BUF_SIZE];
+        loop {
+            let n = self.recv(&mut buf)?;
+            for (hdr, payload) in Messages(&buf[..n]) {
+                if hdr.nlmsg_seq != seq {
+                    continue;
+                }
+                match hdr.nlmsg_type {
+                    NLMSG_ERROR | NLMSG_DONE => {
+                        let code = read::<i32>(payload).unwrap_or_default();
+                        if code < 0 {
+                            return Err(io::Error::from_raw_os_error(-code).into());
+                        }
+                        return Ok(responses);
+                    }
+                    kind => responses.push(Response {
+                        kind,
+                        payload: payload.to_vec(),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Receives a datagram, which may contain multiple messages, into `buf`.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::recv(self.fd, buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as _)
+    }
+}
+
+impl AsRawFd for Netlink {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Netlink {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Iterates over the messages of a received datagram, yielding `(header, payload)`.
+pub struct Messages<'a>(pub &'a [u8]);
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = (nlmsghdr, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let hdr: nlmsghdr = read(self.0)?;
+        let len = hdr.nlmsg_len as usize;
+        if len < NLMSG_HDRLEN || len > self.0.len() {
+            return None;
+        }
+        let payload = &self.0[NLMSG_HDRLEN..len];
+        self.0 = self.0.get(align(len)..).unwrap_or_default();
+        Some((hdr, payload))
+    }
+}
@@ -0,0 +1,104 @@
+use crate::result::Result;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+/// Represents a network namespace.
+///
+/// A namespace is referred to either by the path of its file (e.g. `/var/run/netns/<name>` or
+/// `/proc/<pid>/ns/net`), or by a file descriptor which is open on such a file. The file
+/// descriptor is owned, and shared by the clones, so it stays open as long as any of them is
+/// used.
+#[derive(Debug, Clone)]
+pub enum NetNs {
+    Path(PathBuf),
+    Fd(Arc<OwnedFd>),
+}
+
+impl PartialEq for NetNs {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Path(a), Self::Path(b)) => a == b,
+            (Self::Fd(a), Self::Fd(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for NetNs {}
+
+impl From<PathBuf> for NetNs {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&Path> for NetNs {
+    fn from(path: &Path) -> Self {
+        Self::Path(path.to_owned())
+    }
+}
+
+impl From<&str> for NetNs {
+    fn from(path: &str) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+impl From<OwnedFd> for NetNs {
+    fn from(fd: OwnedFd) -> Self {
+        Self::Fd(Arc::new(fd))
+    }
+}
+
+impl NetNs {
+    /// Calls `f` with a file descriptor referring to the namespace.
+    pub(crate) fn with_fd<T>(&self, f: impl FnOnce(RawFd) -> Result<T>) -> Result<T> {
+        let path = match self {
+            Self::Fd(fd) => return f(fd.as_raw_fd()),
+            Self::Path(path) => CString::new(path.as_os_str().as_bytes())?,
+        };
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        f(fd.as_raw_fd())
+    }
+
+    /// Runs `f` on a dedicated thread which has entered the namespace.
+    ///
+    /// Sockets and devices created by `f` stay in the namespace after the thread exits.
+    pub(crate) fn run<T: Send>(&self, f: impl FnOnce() -> Result<T> + Send) -> Result<T> {
+        self.with_fd(|fd| {
+            thread::scope(|scope| {
+                scope
+                    .spawn(|| {
+                        if unsafe { libc::setns(fd, libc::CLONE_NEWNET) } < 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                        f().map_err(into_io_error)
+                    })
+                    .join()
+                    .map_err(|_| io::Error::other("network namespace thread panicked"))?
+                    .map_err(Into::into)
+            })
+        })
+    }
+}
+
+/// Converts an error into one which can be sent across threads.
+fn into_io_error(err: crate::result::Error) -> io::Error {
+    let err = match err.downcast::<io::Error>() {
+        Ok(err) => return *err,
+        Err(err) => err,
+    };
+    match err.downcast::<nix::errno::Errno>() {
+        Ok(errno) => io::Error::from_raw_os_error(*errno as i32),
+        Err(err) => io::Error::other(err.to_string()),
+    }
+}
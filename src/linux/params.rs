@@ -1,4 +1,9 @@
-use std::net::Ipv4Addr;
+use super::netns::NetNs;
+use crate::route::Route;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::OwnedFd;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 /// Represents parameters for creating a new Tun/Tap device on Linux.
 #[cfg(target_os = "linux")]
@@ -14,4 +19,12 @@ pub struct Params {
     pub destination: Option<Ipv4Addr>,
     pub broadcast: Option<Ipv4Addr>,
     pub netmask: Option<Ipv4Addr>,
+    pub ipv6_addresses: Vec<(Ipv6Addr, u8)>,
+    pub offloads: Option<u32>,
+    pub netns: Option<NetNs>,
+    pub routes: Vec<Route>,
+    pub bridge: Option<String>,
+    pub device_path: PathBuf,
+    pub device_dir: Option<Arc<OwnedFd>>,
+    pub cloexec: bool,
 }
@@ -62,9 +62,11 @@ impl ifreq {
     pub fn new(name: &str) -> Self {
         let mut req: ifreq = unsafe { mem::zeroed() };
         if !name.is_empty() {
-            let len = name.len().min(IFNAMSIZ as usize - 1);
-            // Done just to make sure we don't truncate
-            // on an UTF-8 code point boundary.
+            let mut len = name.len().min(IFNAMSIZ as usize - 1);
+            // Make sure we don't truncate within an UTF-8 code point.
+            while !name.is_char_boundary(len) {
+                len -= 1;
+            }
             let name = &name[..len];
             unsafe {
                 ptr::copy_nonoverlapping(
@@ -0,0 +1,396 @@
+//! The steering program of multiqueue devices (`TUNSETSTEERINGEBPF`), which selects the queue
+//! of a packet by the RSS hash of its flow.
+//!
+//! The kernel runs the program for every packet it sends to the device, and passes it to
+//! queue `(hash as u16) % queues`. The program is a socket filter which loads the headers of
+//! the packet relative to its network header, so it works for TUN and TAP devices alike.
+
+use crate::flow::toeplitz_hash;
+use crate::packet::{ETH_P_IP, ETH_P_IPV6};
+use std::ffi::CStr;
+use std::io;
+use std::ops::Range;
+use std::os::unix::io::{FromRawFd, OwnedFd};
+
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_PROG_TYPE_SOCKET_FILTER: u32 = 1;
+
+/// Offset of loads relative to the network header (`SKF_NET_OFF`).
+const NET: i32 = -0x100000;
+/// Offset of `protocol` in `struct __sk_buff`, which holds the ethertype in network order.
+const SKB_PROTOCOL: i16 = 16;
+
+/// Number of IPv6 extension headers which the program follows. Packets with more headers are
+/// hashed as if they had no ports.
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+// Instruction classes, sizes, modes and operations of eBPF.
+const LD: u8 = 0x00;
+const LDX: u8 = 0x01;
+const JMP: u8 = 0x05;
+const ALU: u8 = 0x04;
+const ALU64: u8 = 0x07;
+const W: u8 = 0x00;
+const H: u8 = 0x08;
+const B: u8 = 0x10;
+const ABS: u8 = 0x20;
+const IND: u8 = 0x40;
+const MEM: u8 = 0x60;
+const X: u8 = 0x08;
+const ADD: u8 = 0x00;
+const AND: u8 = 0x50;
+const LSH: u8 = 0x60;
+const XOR: u8 = 0xa0;
+const MOV: u8 = 0xb0;
+const JA: u8 = 0x00;
+const JEQ: u8 = 0x10;
+const JSET: u8 = 0x40;
+const JNE: u8 = 0x50;
+const EXIT: u8 = 0x90;
+
+// Registers: `R0` receives loads, `R6` holds the context as required by loads, `R7` the hash,
+// `R8` the offset of the current header and `R9` its protocol.
+const R0: u8 = 0;
+const R1: u8 = 1;
+const R6: u8 = 6;
+const R7: u8 = 7;
+const R8: u8 = 8;
+const R9: u8 = 9;
+
+/// `struct bpf_insn`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+/// The leading fields of `union bpf_attr` used by `BPF_PROG_LOAD`.
+#[repr(C)]
+struct ProgLoad {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+}
+
+/// Loads a program which returns the hash of the flow of a packet, as
+/// [`FlowKey::rss_hash`](../../flow/struct.FlowKey.html#method.rss_hash) computes it with
+/// `key`.
+pub fn load(key: &[u8]) -> io::Result<OwnedFd> {
+    let insns = program(key);
+    let license: &CStr = c"MIT OR Apache-2.0";
+    let attr = ProgLoad {
+        prog_type: BPF_PROG_TYPE_SOCKET_FILTER,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_PROG_LOAD,
+            &attr as *const ProgLoad,
+            std::mem::size_of::<ProgLoad>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as _) })
+}
+
+/// Assembles the program, which hashes the addresses and, for TCP and UDP packets which are
+/// not fragments, the ports.
+///
+/// The hash is the XOR of a 32-bit window of the key for every set bit of the input, so the
+/// program tests each bit and XORs the window of its position, which is computed here.
+fn program(key: &[u8]) -> Vec<Insn> {
+    let mut asm = Assembler::default();
+    let done = asm.label();
+    let ipv6 = asm.label();
+    let ports = asm.label();
+    asm.alu64(MOV | X, R6, R1, 0);
+    asm.alu64(MOV, R7, 0, 0);
+    asm.push(LDX | MEM | W, R0, R6, SKB_PROTOCOL, 0);
+    asm.jump(JEQ, R0, be(ETH_P_IPV6), ipv6);
+    asm.jump(JNE, R0, be(ETH_P_IP), done);
+
+    // IPv4: the addresses, then the ports following the header unless it is a fragment.
+    asm.hash(key, 0..8, |asm, byte| {
+        asm.push(LD | ABS | B, 0, 0, 0, NET + 12 + byte)
+    });
+    asm.push(LD | ABS | B, 0, 0, 0, NET + 9);
+    asm.alu64(MOV | X, R9, R0, 0);
+    asm.push(LD | ABS | H, 0, 0, 0, NET + 6);
+    asm.jump(JSET, R0, 0x3fff, done);
+    asm.push(LD | ABS | B, 0, 0, 0, NET);
+    asm.alu64(AND, R0, 0, 0x0f);
+    asm.alu64(LSH, R0, 0, 2);
+    asm.alu64(MOV | X, R8, R0, 0);
+    asm.jump(JA, 0, 0, ports);
+
+    // IPv6: the addresses, then the ports following the extension headers unless the packet
+    // is a fragment.
+    asm.bind(ipv6);
+    asm.hash(key, 0..32, |asm, byte| {
+        asm.push(LD | ABS | B, 0, 0, 0, NET + 8 + byte)
+    });
+    asm.push(LD | ABS | B, 0, 0, 0, NET + 6);
+    asm.alu64(MOV | X, R9, R0, 0);
+    asm.alu64(MOV, R8, 0, 40);
+    for _ in 0..MAX_EXTENSION_HEADERS {
+        let options = asm.label();
+        let fragment = asm.label();
+        let ah = asm.label();
+        asm.jump(JEQ, R9, libc::IPPROTO_HOPOPTS, options);
+        asm.jump(JEQ, R9, libc::IPPROTO_ROUTING, options);
+        asm.jump(JEQ, R9, libc::IPPROTO_DSTOPTS, options);
+        asm.jump(JEQ, R9, libc::IPPROTO_FRAGMENT, fragment);
+        asm.jump(JEQ, R9, libc::IPPROTO_AH, ah);
+        asm.jump(JA, 0, 0, ports);
+        // Loads clobber `R1` to `R5`, so the next header is moved to `R9` before the length of
+        // this one is loaded into `R0` and added to `R8`. The length of options counts 8 byte
+        // units, not including the first one.
+        let next = asm.label();
+        asm.bind(options);
+        asm.next_header();
+        asm.push(LD | IND | B, 0, R8, 0, NET + 1);
+        asm.alu64(ADD, R0, 0, 1);
+        asm.alu64(LSH, R0, 0, 3);
+        asm.jump(JA, 0, 0, next);
+        // Any fragment but an atomic one has no ports.
+        asm.bind(fragment);
+        asm.push(LD | IND | H, 0, R8, 0, NET + 2);
+        asm.jump(JSET, R0, 0xfff9, done);
+        asm.next_header();
+        asm.alu64(MOV, R0, 0, 8);
+        asm.jump(JA, 0, 0, next);
+        // The length of an authentication header counts 4 byte units, not including the first
+        // two.
+        asm.bind(ah);
+        asm.next_header();
+        asm.push(LD | IND | B, 0, R8, 0, NET + 1);
+        asm.alu64(ADD, R0, 0, 2);
+        asm.alu64(LSH, R0, 0, 2);
+        asm.bind(next);
+        asm.alu64(ADD | X, R8, R0, 0);
+    }
+    asm.jump(JA, 0, 0, done);
+
+    // The ports at offset `R8` of TCP and UDP, which follow the addresses in the input.
+    asm.bind(ports);
+    let transport = asm.label();
+    asm.jump(JEQ, R9, libc::IPPROTO_TCP, transport);
+    asm.jump(JEQ, R9, libc::IPPROTO_UDP, transport);
+    asm.jump(JA, 0, 0, done);
+    asm.bind(transport);
+    // The IPv4 addresses are 8 bytes, IPv6 ones 32, which is known from the protocol.
+    let ipv6_ports = asm.label();
+    asm.push(LDX | MEM | W, R0, R6, SKB_PROTOCOL, 0);
+    asm.jump(JEQ, R0, be(ETH_P_IPV6), ipv6_ports);
+    asm.hash(key, 8..12, |asm, byte| {
+        asm.push(LD | IND | B, 0, R8, 0, NET + byte)
+    });
+    asm.jump(JA, 0, 0, done);
+    asm.bind(ipv6_ports);
+    asm.hash(key, 32..36, |asm, byte| {
+        asm.push(LD | IND | B, 0, R8, 0, NET + byte)
+    });
+
+    asm.bind(done);
+    asm.alu64(MOV | X, R0, R7, 0);
+    asm.push(JMP | EXIT, 0, 0, 0, 0);
+    asm.finish()
+}
+
+/// Returns the value of the `protocol` field of `struct __sk_buff` for `ethertype`.
+fn be(ethertype: u16) -> i32 {
+    ethertype.to_be() as i32
+}
+
+#[derive(Default)]
+struct Assembler {
+    insns: Vec<Insn>,
+    /// Instruction of every bound label.
+    labels: Vec<Option<usize>>,
+    /// Jumps and the labels they target.
+    jumps: Vec<(usize, usize)>,
+}
+
+impl Assembler {
+    fn push(&mut self, code: u8, dst: u8, src: u8, off: i16, imm: i32) {
+        #[cfg(target_endian = "little")]
+        let regs = dst | src << 4;
+        #[cfg(target_endian = "big")]
+        let regs = dst << 4 | src;
+        self.insns.push(Insn {
+            code,
+            regs,
+            off,
+            imm,
+        });
+    }
+
+    /// Loads the next header field of the extension header at `R8` into `R9`.
+    fn next_header(&mut self) {
+        self.push(LD | IND | B, 0, R8, 0, NET);
+        self.alu64(MOV | X, R9, R0, 0);
+    }
+
+    fn alu64(&mut self, op: u8, dst: u8, src: u8, imm: i32) {
+        self.push(ALU64 | op, dst, src, 0, imm);
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn bind(&mut self, label: usize) {
+        self.labels[label] = Some(self.insns.len());
+    }
+
+    /// Jumps to `label` if `dst` compares to `imm` by `op`.
+    fn jump(&mut self, op: u8, dst: u8, imm: i32, label: usize) {
+        self.jumps.push((self.insns.len(), label));
+        self.push(JMP | op, dst, 0, 0, imm);
+    }
+
+    /// XORs the hash with the window of every set bit of the bytes `input` of the input,
+    /// where `load` loads the n-th of them into `R0`.
+    fn hash(&mut self, key: &[u8], input: Range<usize>, load: impl Fn(&mut Self, i32)) {
+        for (n, byte) in input.enumerate() {
+            load(self, n as i32);
+            for bit in 0..8 {
+                let mut bits = vec![0; byte + 1];
+                bits[byte] = 0x80 >> bit;
+                let window = toeplitz_hash(key, &bits);
+                // Skips the XOR unless the bit is set.
+                self.push(JMP | JSET, R0, 0, 1, 0x80 >> bit);
+                self.push(JMP | JA, 0, 0, 1, 0);
+                self.push(ALU | XOR, R7, 0, 0, window as i32);
+            }
+        }
+    }
+
+    /// Resolves the jumps to their labels.
+    fn finish(mut self) -> Vec<Insn> {
+        for (insn, label) in self.jumps {
+            let target = self.labels[label].expect("unbound label");
+            self.insns[insn].off = (target - insn - 1) as i16;
+        }
+        self.insns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{FlowKey, RSS_KEY};
+    use crate::linux::alloc::tests::privileged;
+    use crate::TunBuilder;
+    use std::collections::BTreeSet;
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+    use std::os::unix::io::AsRawFd;
+    use std::thread;
+    use std::time::Duration;
+
+    const QUEUES: usize = 4;
+
+    /// Sends datagrams of many flows to `dst` through the device of `queues`, and returns the
+    /// flow of every packet of them along with the queue which received it.
+    ///
+    /// IPv6 datagrams carry an empty hop-by-hop options header if `hop_by_hop` is true.
+    fn send_flows(
+        queues: &[crate::blocking::Tun],
+        dst: IpAddr,
+        len: usize,
+        hop_by_hop: bool,
+    ) -> Vec<(FlowKey, usize)> {
+        let bind: IpAddr = match dst {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        for _ in 0..32 {
+            let socket = UdpSocket::bind((bind, 0)).unwrap();
+            if hop_by_hop {
+                // The next header and length, followed by a `PadN` option of 4 bytes.
+                let options = [0u8, 0, 1, 4, 0, 0, 0, 0];
+                let ret = unsafe {
+                    libc::setsockopt(
+                        socket.as_raw_fd(),
+                        libc::IPPROTO_IPV6,
+                        libc::IPV6_HOPOPTS,
+                        options.as_ptr().cast(),
+                        options.len() as _,
+                    )
+                };
+                assert_eq!(ret, 0, "{}", io::Error::last_os_error());
+            }
+            socket.send_to(&vec![0; len], (dst, 9)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        let mut flows = Vec::new();
+        let mut buf = [0u8; 2048];
+        for (queue, tun) in queues.iter().enumerate() {
+            while let Ok(n) = tun.try_recv(&mut buf) {
+                match tun.flow_key(&buf[..n]) {
+                    Some(key) if key.dst == dst => flows.push((key, queue)),
+                    _ => {}
+                }
+            }
+        }
+        flows
+    }
+
+    #[test]
+    fn steers_flows_by_rss_hash() {
+        if !privileged() {
+            return;
+        }
+        let queues = TunBuilder::new()
+            .name("steer0")
+            .address(Ipv4Addr::new(10, 254, 0, 1))
+            .netmask(Ipv4Addr::new(255, 255, 255, 0))
+            .ipv6_address("fd00:254::1".parse().unwrap(), 64)
+            .packet_info(false)
+            .up()
+            .try_build_mq_blocking(QUEUES)
+            .unwrap();
+        queues[0].set_rss_steering(Some(&RSS_KEY)).unwrap();
+
+        // Datagrams which are fragmented have no ports, so all of their fragments are hashed
+        // by the addresses alone.
+        let mut used = BTreeSet::new();
+        for (dst, len, hop_by_hop, packets) in [
+            ("10.254.0.2", 64, false, 32),
+            ("10.254.0.2", 3000, false, 3 * 32),
+            ("fd00:254::2", 64, false, 32),
+            ("fd00:254::2", 64, true, 32),
+            ("fd00:254::2", 3000, false, 3 * 32),
+        ] {
+            let flows = send_flows(&queues, dst.parse().unwrap(), len, hop_by_hop);
+            assert_eq!(flows.len(), packets, "{} {}", dst, len);
+            for (key, queue) in flows {
+                assert_eq!(key.rss_queue(&RSS_KEY, QUEUES), queue, "{:?}", key);
+                used.insert(queue);
+            }
+        }
+        assert!(used.len() > 1);
+
+        queues[0].set_rss_steering(None).unwrap();
+    }
+}
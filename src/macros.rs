@@ -0,0 +1,179 @@
+/// Implements the methods of a Tun/Tap device which only depend on its `iface` field, so they
+/// are shared by the asynchronous and the blocking device.
+macro_rules! tun_methods {
+    () => {
+        /// Returns the name of Tun/Tap device.
+        pub fn name(&self) -> &str {
+            self.iface.name()
+        }
+
+        /// Returns the value of MTU.
+        pub fn mtu(&self) -> $crate::result::Result<i32> {
+            self.iface.mtu(None)
+        }
+
+        /// Returns the IPv4 address of MTU.
+        pub fn address(&self) -> $crate::result::Result<std::net::Ipv4Addr> {
+            self.iface.address(None)
+        }
+
+        /// Returns the IPv4 destination address of MTU.
+        pub fn destination(&self) -> $crate::result::Result<std::net::Ipv4Addr> {
+            self.iface.destination(None)
+        }
+
+        /// Returns the IPv4 broadcast address of MTU.
+        pub fn broadcast(&self) -> $crate::result::Result<std::net::Ipv4Addr> {
+            self.iface.broadcast(None)
+        }
+
+        /// Returns the IPv4 netmask address of MTU.
+        pub fn netmask(&self) -> $crate::result::Result<std::net::Ipv4Addr> {
+            self.iface.netmask(None)
+        }
+
+        /// Returns the flags of MTU.
+        pub fn flags(&self) -> $crate::result::Result<i16> {
+            self.iface.flags(None)
+        }
+
+        /// Returns the interface index of Tun/Tap device.
+        pub fn index(&self) -> i32 {
+            self.iface.index()
+        }
+
+        /// Returns the kernel counters of Tun/Tap device, which are shared by all queues.
+        pub fn stats(&self) -> $crate::result::Result<$crate::Stats> {
+            self.iface.stats()
+        }
+
+        /// Adds a route through the device to the main routing table.
+        ///
+        /// The device must be up, otherwise the kernel refuses the route.
+        pub fn add_route(&self, route: $crate::Route) -> $crate::result::Result<()> {
+            self.iface.add_route(&route)
+        }
+
+        /// Removes a route through the device from the main routing table.
+        pub fn remove_route(&self, route: $crate::Route) -> $crate::result::Result<()> {
+            self.iface.remove_route(&route)
+        }
+
+        /// Returns the IPv4 and IPv6 unicast routes of the main routing table which go through
+        /// the device.
+        pub fn routes(&self) -> $crate::result::Result<Vec<$crate::Route>> {
+            self.iface.routes(true)
+        }
+
+        /// Returns the global IPv6 addresses of device along with their prefix length.
+        pub fn ipv6_addresses(&self) -> $crate::result::Result<Vec<(std::net::Ipv6Addr, u8)>> {
+            self.iface.ipv6_addresses()
+        }
+
+        /// Dumps the configuration of device, which can be used to create an identical device.
+        ///
+        /// Routes which the kernel derived from the addresses of device are not included. The
+        /// offloads are only known if they were set when this device was built.
+        pub fn config(&self) -> $crate::result::Result<$crate::TunConfig> {
+            self.iface.config()
+        }
+
+        /// Makes the device persistent or clears the persistent flag (`TUNSETPERSIST`).
+        ///
+        /// A non-persistent device is removed once its last file descriptor is closed, so this can
+        /// be used to delete a persistent device after attaching to it.
+        pub fn set_persist(&self, persist: bool) -> $crate::result::Result<()> {
+            self.iface.persist(persist)
+        }
+
+        /// Returns the `IFF_*` flags supported by the tun driver of the running kernel
+        /// (`TUNGETFEATURES`), as reported by the clone device at `/dev/net/tun`.
+        ///
+        /// Use [`features_at`](#method.features_at) or
+        /// [`TunBuilder::features`](struct.TunBuilder.html#method.features) if the clone device is
+        /// located elsewhere.
+        pub fn features() -> $crate::result::Result<i32> {
+            Self::features_at("/dev/net/tun")
+        }
+
+        /// Returns the `IFF_*` flags supported by the tun driver, as reported by the clone device
+        /// at `path` (`TUNGETFEATURES`).
+        pub fn features_at(path: impl AsRef<std::path::Path>) -> $crate::result::Result<i32> {
+            use std::os::unix::io::AsRawFd;
+            let file = std::fs::File::open(path)?;
+            $crate::linux::interface::Interface::features(file.as_raw_fd())
+        }
+
+        /// Attaches the device to the bridge (or any other master device, such as a bond) named
+        /// `master` (`IFLA_MASTER`).
+        pub fn set_master(&self, master: &str) -> $crate::result::Result<()> {
+            self.iface.master(Some(master))
+        }
+
+        /// Detaches the device from its bridge or master device.
+        pub fn clear_master(&self) -> $crate::result::Result<()> {
+            self.iface.master(None)
+        }
+
+        /// Moves the device into another network namespace (`IFLA_NET_NS_FD`).
+        ///
+        /// Packets can still be received and sent through this handle after the move, however
+        /// the configuration getters of this handle address the device in the namespace it was
+        /// created in, so they fail once the device has left that namespace.
+        pub fn move_to_netns(&self, netns: impl Into<$crate::NetNs>) -> $crate::result::Result<()> {
+            netns.into().with_fd(|fd| self.iface.move_to_netns(fd))
+        }
+
+        /// Returns the flow of `packet`, which was received from this device, skipping the
+        /// packet information, virtio-net and Ethernet headers as configured.
+        ///
+        /// Returns `None` if `packet` is not an IPv4 or IPv6 packet, or if any of the headers
+        /// preceding the IP header, or the IP header itself, is truncated or malformed.
+        pub fn flow_key(&self, packet: &[u8]) -> Option<$crate::flow::FlowKey> {
+            $crate::flow::FlowKey::from_device(self.iface.attach_flags(), packet)
+        }
+
+        /// Makes the kernel pass every packet it sends to this multiqueue device to the queue
+        /// chosen by the RSS hash of its flow with `key`, or restores the default choice if
+        /// `key` is `None` (`TUNSETSTEERINGEBPF`).
+        ///
+        /// The queue of a packet is then the one returned by
+        /// [`FlowKey::rss_queue`](flow/struct.FlowKey.html#method.rss_queue) for the key of
+        /// [`flow_key`](#method.flow_key), as long as the queues are neither detached nor
+        /// closed. The steering program is loaded with `bpf(2)`, which requires `CAP_BPF` unless
+        /// unprivileged BPF is enabled.
+        pub fn set_rss_steering(&self, key: Option<&[u8; 40]>) -> $crate::result::Result<()> {
+            match key {
+                Some(key) => {
+                    let prog = $crate::linux::steering::load(key)?;
+                    self.iface.steering(Some(&prog))
+                }
+                None => self.iface.steering(None),
+            }
+        }
+
+        /// Parses `packet`, which was received from this device, skipping the packet
+        /// information and virtio-net headers as configured.
+        ///
+        /// Returns `None` if any of the headers is truncated or malformed.
+        pub fn parse_packet<'a>(&self, packet: &'a [u8]) -> Option<$crate::packet::Packet<'a>> {
+            $crate::packet::Packet::from_device(self.iface.attach_flags(), packet)
+        }
+
+        /// Sets the hardware filter of a TAP device (`TUNSETTXFILTER`).
+        ///
+        /// Once set, the kernel drops every frame whose destination is not one of `addrs` before it
+        /// is queued to the device, so it never reaches [`recv`](struct.Tun.html#method.recv).
+        /// Multicast frames are passed as well if `accept_all_multicast` is true.
+        ///
+        /// Passing an empty slice disables the filter. Only TAP devices support filtering, calling
+        /// this method on a TUN device returns an error.
+        pub fn set_mac_filter(
+            &self,
+            addrs: &[$crate::MacAddr],
+            accept_all_multicast: bool,
+        ) -> $crate::result::Result<()> {
+            self.iface.tx_filter(addrs, accept_all_multicast)
+        }
+    };
+}
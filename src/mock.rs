@@ -0,0 +1,166 @@
+use crate::device::Device;
+use crate::linux::io::TunIo;
+use crate::result::Result;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use tokio::io::unix::AsyncFd;
+
+/// Represents one end of an in-memory pair of devices, which requires no privileges.
+///
+/// The pair is backed by a `SOCK_SEQPACKET` socket pair, so packet boundaries are preserved
+/// like on a Tun/Tap device: every packet sent on one end is received as a whole on the other
+/// end. Usually one end is handed to the code under test in place of a [`Tun`](struct.Tun.html),
+/// while the test plays the role of the kernel on the other end.
+///
+/// Like on a device, sending a packet larger than the MTU fails with `EMSGSIZE`, and
+/// receiving returns `0` once the other end is dropped.
+///
+/// ```
+/// # #[tokio::main]
+/// # async fn main() -> tokio_tun::result::Result<()> {
+/// use tokio_tun::{Device, MockTun};
+///
+/// let (tun, kernel) = MockTun::pair("mock0", 1500)?;
+/// kernel.send(&[0x45, 0, 0, 20]).await?;
+///
+/// let mut buf = [0u8; 1500];
+/// let n = tun.recv(&mut buf).await?;
+/// assert_eq!(&buf[..n], &[0x45, 0, 0, 20]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockTun {
+    name: String,
+    mtu: i32,
+    io: AsyncFd<TunIo>,
+}
+
+impl MockTun {
+    /// Creates a new pair of connected devices, both named `name` with the given MTU.
+    ///
+    /// The devices are registered with the current runtime.
+    pub fn pair(name: &str, mtu: i32) -> Result<(Self, Self)> {
+        let mut fds = [0; 2];
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // Owned before registering, so both ends are closed if registering fails.
+        let (a, b) = unsafe { (TunIo::from_raw_fd(fds[0]), TunIo::from_raw_fd(fds[1])) };
+        let end = |io| -> Result<Self> {
+            Ok(Self {
+                name: name.into(),
+                mtu,
+                io: unsafe { AsyncFd::register(io) }?,
+            })
+        };
+        Ok((end(a)?, end(b)?))
+    }
+}
+
+impl MockTun {
+    /// Fails with `EMSGSIZE` if `buf` does not fit into the MTU.
+    fn check_len(&self, buf: &[u8]) -> io::Result<()> {
+        if buf.len() > self.mtu.max(0) as usize {
+            return Err(io::Error::from_raw_os_error(libc::EMSGSIZE));
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for MockTun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+impl Device for MockTun {
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> + Send {
+        self.io
+            .async_io(tokio::io::Interest::READABLE, |io| io.recv(buf))
+    }
+
+    fn send(&self, buf: &[u8]) -> impl Future<Output = io::Result<usize>> + Send {
+        let len = self.check_len(buf);
+        async move {
+            len?;
+            self.io
+                .async_io(tokio::io::Interest::WRITABLE, |io| io.send(buf))
+                .await
+        }
+    }
+
+    fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.get_ref().recv(buf)
+    }
+
+    fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.check_len(buf)?;
+        self.io.get_ref().send(buf)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mtu(&self) -> Result<i32> {
+        Ok(self.mtu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pair_transmits_packets() {
+        let (tun, kernel) = MockTun::pair("mock0", 1500).unwrap();
+        assert_eq!(tun.name(), "mock0");
+        assert_eq!(kernel.mtu().unwrap(), 1500);
+
+        tun.send(&[1, 2, 3]).await.unwrap();
+        tun.send(&[4; 1500]).await.unwrap();
+        let mut buf = [0u8; 2048];
+        assert_eq!(kernel.recv(&mut buf).await.unwrap(), 3);
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(kernel.recv(&mut buf).await.unwrap(), 1500);
+
+        kernel.try_send(&[5, 6]).unwrap();
+        assert_eq!(tun.recv(&mut buf).await.unwrap(), 2);
+        assert_eq!(&buf[..2], &[5, 6]);
+        let err = tun.try_recv(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[tokio::test]
+    async fn recv_returns_eof_when_other_end_is_dropped() {
+        let (tun, kernel) = MockTun::pair("mock0", 1500).unwrap();
+        kernel.send(&[1]).await.unwrap();
+        drop(kernel);
+        let mut buf = [0u8; 1500];
+        assert_eq!(tun.recv(&mut buf).await.unwrap(), 1);
+        assert_eq!(tun.recv(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn send_rejects_packets_larger_than_mtu() {
+        let (tun, kernel) = MockTun::pair("mock0", 1280).unwrap();
+        let err = tun.send(&[0; 1281]).await.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EMSGSIZE));
+        let err = tun.try_send(&[0; 1281]).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EMSGSIZE));
+
+        // Nothing was sent.
+        let mut buf = [0u8; 2048];
+        let err = kernel.try_recv(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::flow::RSS_KEY;
+use crate::result::Result;
+use crate::tun::Tun;
+use std::future::poll_fn;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::Poll;
+
+/// Represents the queues of a multiqueue Tun/Tap device as a single device.
+///
+/// Packets are received from all of the queues in turn, and sent through the queue chosen by
+/// the flow of the packet, so that packets of one flow are never reordered.
+///
+/// ```no_run
+/// # async fn run() -> tokio_tun::result::Result<()> {
+/// use tokio_tun::{MultiQueueTun, TunBuilder};
+///
+/// let tun = MultiQueueTun::new(TunBuilder::new().name("tun0").up().try_build_mq(4)?)?;
+///
+/// let mut buf = [0u8; 1500];
+/// loop {
+///     let (queue, n) = tun.recv(&mut buf).await?;
+///     println!("reading {} bytes from queue {}", n, queue);
+///     tun.send(&buf[..n]).await?;
+/// }
+/// # }
+/// ```
+pub struct MultiQueueTun {
+    queues: Vec<Tun>,
+    /// Queue which is polled first by the next `recv`.
+    next: AtomicUsize,
+}
+
+impl MultiQueueTun {
+    /// Creates a new instance of [`MultiQueueTun`](struct.MultiQueueTun.html) from the queues
+    /// returned by [`try_build_mq`](struct.TunBuilder.html#method.try_build_mq).
+    pub fn new(queues: Vec<Tun>) -> Result<Self> {
+        let first = queues.first().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least one queue is required",
+            )
+        })?;
+        if queues.iter().any(|tun| tun.index() != first.index()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "queues must belong to the same device",
+            )
+            .into());
+        }
+        Ok(Self {
+            queues,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the queues of device.
+    pub fn queues(&self) -> &[Tun] {
+        &self.queues
+    }
+
+    /// Consumes this instance and returns the queues of device.
+    pub fn into_inner(self) -> Vec<Tun> {
+        self.queues
+    }
+
+    /// Receives a packet from any of the queues, and returns the index of queue and the length
+    /// of packet.
+    ///
+    /// Queues are polled in turn starting after the one which received the last packet, so a
+    /// busy queue cannot starve the others. Only one task should wait in `recv` at a time, use
+    /// the individual [`queues`](struct.MultiQueueTun.html#method.queues) to receive from
+    /// several tasks.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, usize)> {
+        poll_fn(|cx| {
+            let start = self.next.load(Ordering::Relaxed);
+            for offset in 0..self.queues.len() {
+                let queue = (start + offset) % self.queues.len();
+                if let Poll::Ready(res) = self.queues[queue].poll_recv(cx, buf) {
+                    self.next.store(queue + 1, Ordering::Relaxed);
+                    return Poll::Ready(res.map(|n| (queue, n)));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Sends a packet through the queue chosen by its flow.
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.queues[self.queue_of(buf)].send(buf).await
+    }
+
+    /// Returns the index of the queue which [`send`](struct.MultiQueueTun.html#method.send)
+    /// uses for `packet`, by the RSS hash of its [`FlowKey`](flow/struct.FlowKey.html) with
+    /// the default key. Packets which are not IP packets are sent through the first queue.
+    ///
+    /// The kernel passes the packets whose flow has the same key to this queue as well, once
+    /// [`set_rss_steering`](struct.Tun.html#method.set_rss_steering) attached the default key.
+    pub fn queue_of(&self, packet: &[u8]) -> usize {
+        self.queues[0]
+            .flow_key(packet)
+            .map_or(0, |key| key.rss_queue(&RSS_KEY, self.queues.len()))
+    }
+}
@@ -0,0 +1,234 @@
+//! Management of auxiliary network links through rtnetlink.
+//!
+//! This module is mostly useful to set up topologies around Tun/Tap devices, e.g. in integration
+//! tests, without shelling out to `ip`:
+//!
+//! ```no_run
+//! # fn main() -> tokio_tun::result::Result<()> {
+//! use tokio_tun::netlink::Handle;
+//!
+//! let mut handle = Handle::new_in("/var/run/netns/test")?;
+//! handle.create_bridge("br0")?;
+//! handle.create_veth("veth0", "veth1")?;
+//! handle.set_master("veth0", Some("br0"))?;
+//! handle.set_up("br0")?;
+//! handle.set_up("veth0")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::linux::netlink::{self, ifinfomsg, Message, Netlink};
+use crate::linux::netns::NetNs;
+use crate::result::Result;
+
+/// Represents a `NETLINK_ROUTE` socket to create, configure and delete links.
+///
+/// All operations apply to the network namespace the handle was created in.
+pub struct Handle {
+    netlink: Netlink,
+}
+
+impl Handle {
+    /// Creates a new handle in the network namespace of the calling thread.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            netlink: Netlink::new()?,
+        })
+    }
+
+    /// Creates a new handle in the given network namespace.
+    ///
+    /// The calling thread stays in its own namespace. Entering a namespace requires
+    /// `CAP_SYS_ADMIN`.
+    pub fn new_in(netns: impl Into<NetNs>) -> Result<Self> {
+        netns.into().run(Self::new)
+    }
+
+    /// Creates a new bridge named `name`.
+    pub fn create_bridge(&mut self, name: &str) -> Result<()> {
+        self.create(name, "bridge", |msg| msg)
+    }
+
+    /// Creates a new dummy link named `name`.
+    pub fn create_dummy(&mut self, name: &str) -> Result<()> {
+        self.create(name, "dummy", |msg| msg)
+    }
+
+    /// Creates a new pair of virtual ethernet links named `name` and `peer`.
+    ///
+    /// Packets sent on one end of the pair are received on the other one. Use
+    /// [`move_to_netns`](struct.Handle.html#method.move_to_netns) to move either end to another
+    /// network namespace.
+    pub fn create_veth(&mut self, name: &str, peer: &str) -> Result<()> {
+        self.create(name, "veth", |msg| {
+            msg.nested(netlink::IFLA_INFO_DATA, |msg| {
+                msg.nested(netlink::VETH_INFO_PEER, |msg| {
+                    msg.header(&ifinfomsg::default())
+                        .attr_str(netlink::IFLA_IFNAME, peer)
+                })
+            })
+        })
+    }
+
+    /// Deletes the link named `name`.
+    ///
+    /// Deleting one end of a veth pair deletes the other one as well.
+    pub fn delete_link(&mut self, name: &str) -> Result<()> {
+        let msg = Message::new(netlink::RTM_DELLINK, 0)
+            .header(&ifinfomsg::default())
+            .attr_str(netlink::IFLA_IFNAME, name);
+        self.netlink.request(msg)?;
+        Ok(())
+    }
+
+    /// Sets up the link named `name`.
+    pub fn set_up(&mut self, name: &str) -> Result<()> {
+        self.set_flags(name, libc::IFF_UP as u32)
+    }
+
+    /// Sets down the link named `name`.
+    pub fn set_down(&mut self, name: &str) -> Result<()> {
+        self.set_flags(name, 0)
+    }
+
+    /// Enslaves the link named `name` to the bridge (or other master device) named `master`,
+    /// or releases it if `master` is `None`.
+    pub fn set_master(&mut self, name: &str, master: Option<&str>) -> Result<()> {
+        let master = match master {
+            Some(master) => self.index(master)?,
+            None => 0,
+        };
+        let msg = Self::link_message(name).attr_u32(netlink::IFLA_MASTER, master);
+        self.netlink.request(msg)?;
+        Ok(())
+    }
+
+    /// Moves the link named `name` into another network namespace.
+    pub fn move_to_netns(&mut self, name: &str, netns: impl Into<NetNs>) -> Result<()> {
+        netns.into().with_fd(|fd| {
+            let msg = Self::link_message(name).attr_u32(netlink::IFLA_NET_NS_FD, fd as u32);
+            self.netlink.request(msg)?;
+            Ok(())
+        })
+    }
+
+    /// Returns the interface index of the link named `name`.
+    pub fn index(&mut self, name: &str) -> Result<u32> {
+        let msg = Message::new(netlink::RTM_GETLINK, 0)
+            .header(&ifinfomsg::default())
+            .attr_str(netlink::IFLA_IFNAME, name);
+        self.netlink
+            .request(msg)?
+            .iter()
+            .filter(|res| res.kind == netlink::RTM_NEWLINK)
+            .find_map(|res| netlink::read::<ifinfomsg>(&res.payload))
+            .map(|hdr| hdr.ifi_index as u32)
+            .ok_or_else(|| std::io::Error::other("missing RTM_NEWLINK response").into())
+    }
+
+    fn create(
+        &mut self,
+        name: &str,
+        kind: &str,
+        data: impl FnOnce(Message) -> Message,
+    ) -> Result<()> {
+        let msg = Message::new(
+            netlink::RTM_NEWLINK,
+            netlink::NLM_F_CREATE | netlink::NLM_F_EXCL,
+        )
+        .header(&ifinfomsg::default())
+        .attr_str(netlink::IFLA_IFNAME, name)
+        .nested(netlink::IFLA_LINKINFO, |msg| {
+            data(msg.attr_str(netlink::IFLA_INFO_KIND, kind))
+        });
+        self.netlink.request(msg)?;
+        Ok(())
+    }
+
+    fn set_flags(&mut self, name: &str, flags: u32) -> Result<()> {
+        let msg = Message::new(netlink::RTM_NEWLINK, 0)
+            .header(&ifinfomsg {
+                ifi_flags: flags,
+                ifi_change: libc::IFF_UP as u32,
+                ..Default::default()
+            })
+            .attr_str(netlink::IFLA_IFNAME, name);
+        self.netlink.request(msg)?;
+        Ok(())
+    }
+
+    fn link_message(name: &str) -> Message {
+        Message::new(netlink::RTM_NEWLINK, 0)
+            .header(&ifinfomsg::default())
+            .attr_str(netlink::IFLA_IFNAME, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::alloc::tests::{exists, privileged};
+    use std::fs;
+
+    fn master(name: &str) -> Option<String> {
+        let link = fs::read_link(format!("/sys/class/net/{}/master", name)).ok()?;
+        Some(link.file_name()?.to_str()?.to_owned())
+    }
+
+    fn is_up(name: &str) -> bool {
+        let flags = fs::read_to_string(format!("/sys/class/net/{}/flags", name)).unwrap();
+        let flags = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).unwrap();
+        flags & libc::IFF_UP as u32 != 0
+    }
+
+    #[test]
+    fn bridge_with_veth() {
+        if !privileged() {
+            return;
+        }
+        let mut handle = Handle::new().unwrap();
+        handle.create_bridge("tnlbr0").unwrap();
+        handle.create_veth("tnlveth0", "tnlveth1").unwrap();
+        assert!(exists("tnlbr0") && exists("tnlveth0") && exists("tnlveth1"));
+        assert!(handle.create_bridge("tnlbr0").is_err());
+
+        handle.set_master("tnlveth0", Some("tnlbr0")).unwrap();
+        assert_eq!(master("tnlveth0").as_deref(), Some("tnlbr0"));
+        handle.set_master("tnlveth0", None).unwrap();
+        assert_eq!(master("tnlveth0"), None);
+
+        handle.set_up("tnlbr0").unwrap();
+        assert!(is_up("tnlbr0"));
+        handle.set_down("tnlbr0").unwrap();
+        assert!(!is_up("tnlbr0"));
+
+        // Deleting one end of the pair deletes its peer.
+        handle.delete_link("tnlveth1").unwrap();
+        assert!(!exists("tnlveth0") && !exists("tnlveth1"));
+        handle.delete_link("tnlbr0").unwrap();
+        assert!(!exists("tnlbr0"));
+    }
+
+    #[test]
+    fn dummy() {
+        if !privileged() {
+            return;
+        }
+        let mut handle = Handle::new().unwrap();
+        if let Err(err) = handle.create_dummy("tnldummy0") {
+            let err = err.downcast::<std::io::Error>().unwrap();
+            assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP), "{}", err);
+            eprintln!("skipped, the kernel does not support dummy links");
+            return;
+        }
+        assert!(exists("tnldummy0"));
+        let index = handle.index("tnldummy0").unwrap();
+        let sysfs = fs::read_to_string("/sys/class/net/tnldummy0/ifindex").unwrap();
+        assert_eq!(sysfs.trim().parse::<u32>().unwrap(), index);
+
+        handle.delete_link("tnldummy0").unwrap();
+        assert!(!exists("tnldummy0"));
+        assert!(handle.index("tnldummy0").is_err());
+        assert!(handle.delete_link("tnldummy0").is_err());
+    }
+}
@@ -0,0 +1,369 @@
+//! Zero-copy views of the headers of packets received from a Tun/Tap device.
+//!
+//! Every view borrows the buffer it was created from, and is only created if the buffer holds
+//! the complete header, so its accessors never fail:
+//!
+//! ```
+//! use tokio_tun::packet::{Network, Packet, Transport};
+//! use tokio_tun::DeviceKind;
+//!
+//! // An IPv4/UDP packet from 10.0.0.2:1234 to 10.0.0.1:53, as read from a TUN device.
+//! let buf = [
+//!     0x45, 0, 0, 32, 0, 0, 0x40, 0, 64, 17, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1, //
+//!     0x04, 0xd2, 0, 53, 0, 12, 0, 0, 1, 2, 3, 4,
+//! ];
+//! let packet = Packet::parse(&buf, DeviceKind::Tun, false).unwrap();
+//! let Network::Ipv4(ip) = packet.network() else { panic!() };
+//! assert_eq!(ip.source(), std::net::Ipv4Addr::new(10, 0, 0, 2));
+//! let Some(Transport::Udp(udp)) = packet.transport() else { panic!() };
+//! assert_eq!((udp.dst_port(), udp.payload()), (53, &[1, 2, 3, 4][..]));
+//! ```
+
+pub mod checksum;
+
+mod builder;
+mod ethernet;
+mod icmp;
+mod ip;
+mod tcp;
+mod udp;
+
+pub use self::builder::PacketBuilder;
+pub use self::ethernet::{ArpPacket, EthernetFrame, VlanTag};
+pub use self::icmp::{Echo, IcmpPacket, Icmpv6Packet};
+pub use self::ip::{
+    ExtensionHeader, ExtensionHeaders, Ipv4Packet, Ipv4PacketMut, Ipv6Fragment, Ipv6Packet,
+    Ipv6PacketMut,
+};
+pub use self::tcp::TcpSegment;
+pub use self::udp::UdpDatagram;
+
+use crate::config::DeviceKind;
+
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+pub const ETH_P_8021Q: u16 = 0x8100;
+pub const ETH_P_8021AD: u16 = 0x88a8;
+
+/// Represents the packet information header (`struct tun_pi`), which precedes every packet of
+/// a device created without `IFF_NO_PI`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PacketInfo {
+    pub flags: u16,
+    /// Ethertype of the packet, e.g. `0x0800` for IPv4.
+    pub protocol: u16,
+}
+
+impl PacketInfo {
+    pub const LEN: usize = 4;
+
+    /// Returns `true` if the packet was truncated because the read buffer was too small
+    /// (`TUN_PKT_STRIP`).
+    pub fn is_truncated(&self) -> bool {
+        self.flags & 0x0001 != 0
+    }
+}
+
+/// Represents a packet as received from a device, with the views of its headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    info: Option<PacketInfo>,
+    ethernet: Option<EthernetFrame<'a>>,
+    network: Network<'a>,
+}
+
+/// Represents the network layer of a [`Packet`](struct.Packet.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network<'a> {
+    Ipv4(Ipv4Packet<'a>),
+    Ipv6(Ipv6Packet<'a>),
+    Arp(ArpPacket<'a>),
+    /// A protocol without a view, identified by its ethertype.
+    Other {
+        ethertype: u16,
+        payload: &'a [u8],
+    },
+}
+
+/// Represents the transport layer of a [`Packet`](struct.Packet.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport<'a> {
+    Tcp(TcpSegment<'a>),
+    Udp(UdpDatagram<'a>),
+    Icmp(IcmpPacket<'a>),
+    Icmpv6(Icmpv6Packet<'a>),
+    /// A protocol without a view, identified by its IP protocol number.
+    Other {
+        protocol: u8,
+        payload: &'a [u8],
+    },
+}
+
+impl<'a> Packet<'a> {
+    /// Parses a packet received from a device of `kind`, which is preceded by packet
+    /// information if `packet_info` is true.
+    ///
+    /// Returns `None` if any of the headers is truncated or malformed.
+    pub fn parse(buf: &'a [u8], kind: DeviceKind, packet_info: bool) -> Option<Self> {
+        Self::with(buf, kind == DeviceKind::Tap, packet_info, false)
+    }
+
+    /// Parses a packet received from a device created with `flags`, skipping the virtio-net
+    /// header as well.
+    pub(crate) fn from_device(flags: i16, buf: &'a [u8]) -> Option<Self> {
+        Self::with(
+            buf,
+            flags & libc::IFF_TAP as i16 != 0,
+            flags & libc::IFF_NO_PI as i16 == 0,
+            flags & libc::IFF_VNET_HDR as i16 != 0,
+        )
+    }
+
+    fn with(buf: &'a [u8], tap: bool, packet_info: bool, vnet_hdr: bool) -> Option<Self> {
+        let mut buf = buf;
+        let mut info = None;
+        if packet_info {
+            info = Some(PacketInfo {
+                flags: be16(buf.get(..PacketInfo::LEN)?, 0),
+                protocol: be16(buf, 2),
+            });
+            buf = &buf[PacketInfo::LEN..];
+        }
+        if vnet_hdr {
+            // `struct virtio_net_hdr`
+            buf = buf.get(10..)?;
+        }
+        let (ethernet, ethertype, payload) = if tap {
+            let frame = EthernetFrame::new(buf)?;
+            (Some(frame), frame.ethertype(), frame.payload())
+        } else {
+            let ethertype = match info {
+                Some(info) => info.protocol,
+                None => match buf.first()? >> 4 {
+                    4 => ETH_P_IP,
+                    6 => ETH_P_IPV6,
+                    _ => return None,
+                },
+            };
+            (None, ethertype, buf)
+        };
+        Some(Self {
+            info,
+            ethernet,
+            network: Network::new(ethertype, payload)?,
+        })
+    }
+
+    /// Returns the packet information, if the device prepends it.
+    pub fn info(&self) -> Option<PacketInfo> {
+        self.info
+    }
+
+    /// Returns the Ethernet frame, if the packet was received from a TAP device.
+    pub fn ethernet(&self) -> Option<EthernetFrame<'a>> {
+        self.ethernet
+    }
+
+    pub fn network(&self) -> Network<'a> {
+        self.network
+    }
+
+    /// Returns the transport layer, see [`Network::transport`](enum.Network.html#method.transport).
+    pub fn transport(&self) -> Option<Transport<'a>> {
+        self.network.transport()
+    }
+}
+
+impl<'a> Network<'a> {
+    /// Parses `payload` as the protocol identified by `ethertype`.
+    ///
+    /// Returns `None` if the header of a protocol with a view is truncated or malformed.
+    pub fn new(ethertype: u16, payload: &'a [u8]) -> Option<Self> {
+        Some(match ethertype {
+            ETH_P_IP => Self::Ipv4(Ipv4Packet::new(payload)?),
+            ETH_P_IPV6 => Self::Ipv6(Ipv6Packet::new(payload)?),
+            ETH_P_ARP => Self::Arp(ArpPacket::new(payload)?),
+            _ => Self::Other { ethertype, payload },
+        })
+    }
+
+    /// Returns the transport layer of an IPv4 or IPv6 packet.
+    ///
+    /// Returns `None` for other protocols, for fragments, which must be reassembled first, and
+    /// if the header of the transport protocol is truncated or malformed.
+    pub fn transport(&self) -> Option<Transport<'a>> {
+        match self {
+            Self::Ipv4(packet) => packet.transport(),
+            Self::Ipv6(packet) => packet.transport(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Transport<'a> {
+    /// Parses `payload` as the protocol identified by the IP protocol number `protocol`.
+    ///
+    /// Returns `None` if the header of a protocol with a view is truncated or malformed.
+    pub fn new(protocol: u8, payload: &'a [u8]) -> Option<Self> {
+        Some(match protocol as i32 {
+            libc::IPPROTO_TCP => Self::Tcp(TcpSegment::new(payload)?),
+            libc::IPPROTO_UDP => Self::Udp(UdpDatagram::new(payload)?),
+            libc::IPPROTO_ICMP => Self::Icmp(IcmpPacket::new(payload)?),
+            libc::IPPROTO_ICMPV6 => Self::Icmpv6(Icmpv6Packet::new(payload)?),
+            _ => Self::Other { protocol, payload },
+        })
+    }
+}
+
+/// Reads a big-endian `u16` at `offset`, which the caller has checked to be in bounds.
+fn be16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+/// Reads a big-endian `u32` at `offset`, which the caller has checked to be in bounds.
+fn be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(array(buf, offset))
+}
+
+/// Copies `N` bytes at `offset`, which the caller has checked to be in bounds.
+fn array<const N: usize>(buf: &[u8], offset: usize) -> [u8; N] {
+    buf[offset..offset + N].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mac::MacAddr;
+    use std::net::Ipv4Addr;
+
+    const TUN: i16 = libc::IFF_TUN as i16;
+    const TAP: i16 = libc::IFF_TAP as i16;
+    const NO_PI: i16 = libc::IFF_NO_PI as i16;
+    const VNET_HDR: i16 = libc::IFF_VNET_HDR as i16;
+
+    fn builder() -> PacketBuilder {
+        PacketBuilder::ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 1))
+    }
+
+    fn udp_port(packet: Option<Packet<'_>>) -> Option<u16> {
+        match packet?.transport()? {
+            Transport::Udp(udp) => Some(udp.dst_port()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_packet_info() {
+        let buf = builder().packet_info(true).udp(1, 53).build(&[]).unwrap();
+        let packet = Packet::parse(&buf, DeviceKind::Tun, true).unwrap();
+        assert_eq!(
+            packet.info(),
+            Some(PacketInfo {
+                flags: 0,
+                protocol: ETH_P_IP,
+            })
+        );
+        assert_eq!(udp_port(Some(packet)), Some(53));
+        assert_eq!(udp_port(Packet::from_device(TUN, &buf)), Some(53));
+
+        for len in 0..PacketInfo::LEN {
+            assert_eq!(Packet::parse(&buf[..len], DeviceKind::Tun, true), None);
+        }
+        // The ethertype of the packet information decides the protocol.
+        let mut buf = buf;
+        buf[2..4].copy_from_slice(&ETH_P_IPV6.to_be_bytes());
+        assert_eq!(Packet::parse(&buf, DeviceKind::Tun, true), None);
+        buf[1] = 0x01;
+        buf[2..4].copy_from_slice(&ETH_P_IP.to_be_bytes());
+        let packet = Packet::parse(&buf, DeviceKind::Tun, true).unwrap();
+        assert!(packet.info().unwrap().is_truncated());
+    }
+
+    #[test]
+    fn detects_ip_version_without_packet_info() {
+        let buf = builder().udp(1, 53).build(&[]).unwrap();
+        assert_eq!(
+            udp_port(Packet::parse(&buf, DeviceKind::Tun, false)),
+            Some(53)
+        );
+        assert_eq!(udp_port(Packet::from_device(TUN | NO_PI, &buf)), Some(53));
+        assert_eq!(Packet::parse(&[], DeviceKind::Tun, false), None);
+        assert_eq!(Packet::parse(&[0x50], DeviceKind::Tun, false), None);
+    }
+
+    #[test]
+    fn skips_vnet_header() {
+        let packet = builder().udp(1, 53).build(&[]).unwrap();
+        let buf = [&[0; 10][..], &packet].concat();
+        assert_eq!(
+            udp_port(Packet::from_device(TUN | NO_PI | VNET_HDR, &buf)),
+            Some(53)
+        );
+        assert_eq!(Packet::from_device(TUN | NO_PI | VNET_HDR, &buf[..9]), None);
+
+        // Packet information precedes the virtio-net header.
+        let packet = builder()
+            .packet_info(true)
+            .ethernet(
+                MacAddr::new(2, 0, 0, 0, 0, 2),
+                MacAddr::new(2, 0, 0, 0, 0, 1),
+            )
+            .udp(1, 53)
+            .build(&[])
+            .unwrap();
+        let buf = [&packet[..4], &[0; 10], &packet[4..]].concat();
+        let parsed = Packet::from_device(TAP | VNET_HDR, &buf).unwrap();
+        assert_eq!(parsed.info().unwrap().protocol, ETH_P_IP);
+        assert_eq!(
+            parsed.ethernet().unwrap().source(),
+            MacAddr::new(2, 0, 0, 0, 0, 2)
+        );
+        assert_eq!(udp_port(Some(parsed)), Some(53));
+        assert_eq!(
+            Packet::from_device(TAP | VNET_HDR, &buf[..4 + 10 + 13]),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_transport_headers() {
+        let tcp = builder().tcp(1, 80).build(&[]).unwrap();
+        let udp = builder().udp(1, 53).build(&[1, 2, 3]).unwrap();
+        let icmp = builder().echo_request(1, 1).build(&[]).unwrap();
+        for (buf, header_len) in [(tcp, 20), (udp, 8), (icmp, 8)] {
+            for len in 0..header_len {
+                let mut buf = buf[..20 + len].to_vec();
+                buf[2..4].copy_from_slice(&(20 + len as u16).to_be_bytes());
+                let packet = Packet::parse(&buf, DeviceKind::Tun, false).unwrap();
+                assert_eq!(packet.transport(), None, "{} bytes", len);
+            }
+        }
+
+        // The data offset of TCP points past the end.
+        let mut buf = builder().tcp(1, 80).build(&[]).unwrap();
+        buf[32] = 0x60;
+        assert_eq!(
+            Packet::parse(&buf, DeviceKind::Tun, false)
+                .unwrap()
+                .transport(),
+            None
+        );
+        buf[32] = 0x40;
+        assert_eq!(
+            Packet::parse(&buf, DeviceKind::Tun, false)
+                .unwrap()
+                .transport(),
+            None
+        );
+        // The length of UDP exceeds the packet.
+        let mut buf = builder().udp(1, 53).build(&[1, 2, 3]).unwrap();
+        buf[24..26].copy_from_slice(&12u16.to_be_bytes());
+        assert_eq!(
+            Packet::parse(&buf, DeviceKind::Tun, false)
+                .unwrap()
+                .transport(),
+            None
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use super::checksum;
+use super::{PacketInfo, ETH_P_IP, ETH_P_IPV6};
+use crate::mac::MacAddr;
+use crate::result::Result;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Builds IPv4 and IPv6 packets with valid checksums, e.g. to inject them with
+/// [`Tun::send`](../struct.Tun.html#method.send).
+///
+/// ```
+/// use std::net::Ipv4Addr;
+/// use tokio_tun::packet::{Packet, PacketBuilder, TcpSegment, Transport};
+/// use tokio_tun::{DeviceKind, MacAddr};
+///
+/// let packet = PacketBuilder::ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 1))
+///     .ethernet(MacAddr::new(2, 0, 0, 0, 0, 2), MacAddr::new(2, 0, 0, 0, 0, 1))
+///     .tcp(40000, 80)
+///     .flags(TcpSegment::SYN)
+///     .sequence(1)
+///     .build(&[])?;
+///
+/// let packet = Packet::parse(&packet, DeviceKind::Tap, false).unwrap();
+/// let Some(Transport::Tcp(tcp)) = packet.transport() else { panic!() };
+/// assert!(tcp.has_flags(TcpSegment::SYN));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketBuilder {
+    packet_info: bool,
+    ethernet: Option<(MacAddr, MacAddr)>,
+    src: IpAddr,
+    dst: IpAddr,
+    ttl: u8,
+    transport: Header,
+    sequence: u32,
+    acknowledgment: u32,
+    flags: u16,
+    window: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Header {
+    None {
+        protocol: u8,
+    },
+    Tcp {
+        src_port: u16,
+        dst_port: u16,
+    },
+    Udp {
+        src_port: u16,
+        dst_port: u16,
+    },
+    Icmp {
+        icmp_type: u8,
+        code: u8,
+        rest: [u8; 4],
+    },
+}
+
+impl PacketBuilder {
+    /// Creates a new IPv4 packet builder.
+    pub fn ipv4(src: Ipv4Addr, dst: Ipv4Addr) -> Self {
+        Self::new(src.into(), dst.into())
+    }
+
+    /// Creates a new IPv6 packet builder.
+    pub fn ipv6(src: Ipv6Addr, dst: Ipv6Addr) -> Self {
+        Self::new(src.into(), dst.into())
+    }
+
+    fn new(src: IpAddr, dst: IpAddr) -> Self {
+        Self {
+            packet_info: false,
+            ethernet: None,
+            src,
+            dst,
+            ttl: 64,
+            transport: Header::None {
+                protocol: libc::IPPROTO_RAW as u8,
+            },
+            sequence: 0,
+            acknowledgment: 0,
+            flags: 0,
+            window: 65535,
+        }
+    }
+
+    /// Prepends the packet information header, as expected by devices without `IFF_NO_PI`.
+    pub fn packet_info(mut self, packet_info: bool) -> Self {
+        self.packet_info = packet_info;
+        self
+    }
+
+    /// Prepends an Ethernet header, as expected by TAP devices.
+    pub fn ethernet(mut self, src: MacAddr, dst: MacAddr) -> Self {
+        self.ethernet = Some((src, dst));
+        self
+    }
+
+    /// Sets the time to live, or hop limit of IPv6. Default value is `64`.
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the protocol of the payload, which follows the IP header as is, except for the
+    /// checksum of an ICMP, TCP or UDP header at its start. Default value is `255` (reserved).
+    pub fn protocol(mut self, protocol: u8) -> Self {
+        self.transport = Header::None { protocol };
+        self
+    }
+
+    pub fn udp(mut self, src_port: u16, dst_port: u16) -> Self {
+        self.transport = Header::Udp { src_port, dst_port };
+        self
+    }
+
+    /// Adds a TCP header without options, see
+    /// [`sequence`](struct.PacketBuilder.html#method.sequence) and the following methods for
+    /// its other fields.
+    pub fn tcp(mut self, src_port: u16, dst_port: u16) -> Self {
+        self.transport = Header::Tcp { src_port, dst_port };
+        self
+    }
+
+    /// Sets the sequence number of TCP. Default value is `0`.
+    pub fn sequence(mut self, sequence: u32) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Sets the acknowledgment number of TCP. Default value is `0`.
+    pub fn acknowledgment(mut self, acknowledgment: u32) -> Self {
+        self.acknowledgment = acknowledgment;
+        self
+    }
+
+    /// Sets the control bits of TCP, e.g. `TcpSegment::SYN`. Default value is `0`.
+    pub fn flags(mut self, flags: u16) -> Self {
+        self.flags = flags & 0x01ff;
+        self
+    }
+
+    /// Sets the window of TCP. Default value is `65535`.
+    pub fn window(mut self, window: u16) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Adds an ICMP header to IPv4 packets, or an ICMPv6 header to IPv6 packets, where
+    /// `rest` is the rest of the header following the checksum.
+    pub fn icmp(mut self, icmp_type: u8, code: u8, rest: [u8; 4]) -> Self {
+        self.transport = Header::Icmp {
+            icmp_type,
+            code,
+            rest,
+        };
+        self
+    }
+
+    /// Adds an ICMP or ICMPv6 echo request header.
+    pub fn echo_request(self, identifier: u16, sequence: u16) -> Self {
+        let icmp_type = if self.src.is_ipv4() { 8 } else { 128 };
+        self.icmp(icmp_type, 0, echo(identifier, sequence))
+    }
+
+    /// Adds an ICMP or ICMPv6 echo reply header.
+    pub fn echo_reply(self, identifier: u16, sequence: u16) -> Self {
+        let icmp_type = if self.src.is_ipv4() { 0 } else { 129 };
+        self.icmp(icmp_type, 0, echo(identifier, sequence))
+    }
+
+    /// Builds a packet which carries `payload`.
+    pub fn build(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write(payload, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Appends a packet which carries `payload` to `buf`, so that a buffer can be reused for
+    /// many packets.
+    pub fn write(&self, payload: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+        let (protocol, header_len) = match self.transport {
+            Header::None { protocol } => (protocol, 0),
+            Header::Tcp { .. } => (libc::IPPROTO_TCP as u8, 20),
+            Header::Udp { .. } => (libc::IPPROTO_UDP as u8, 8),
+            Header::Icmp { .. } if self.src.is_ipv4() => (libc::IPPROTO_ICMP as u8, 8),
+            Header::Icmp { .. } => (libc::IPPROTO_ICMPV6 as u8, 8),
+        };
+        let transport_len = header_len + payload.len();
+        let ip_len = match self.src {
+            IpAddr::V4(_) => 20 + transport_len,
+            IpAddr::V6(_) => transport_len,
+        };
+        if ip_len > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet is too large").into());
+        }
+        let ethertype = if self.src.is_ipv4() {
+            ETH_P_IP
+        } else {
+            ETH_P_IPV6
+        };
+
+        if self.packet_info {
+            buf.extend_from_slice(&[0; PacketInfo::LEN - 2]);
+            buf.extend_from_slice(&ethertype.to_be_bytes());
+        }
+        if let Some((src, dst)) = self.ethernet {
+            buf.extend_from_slice(&dst.octets());
+            buf.extend_from_slice(&src.octets());
+            buf.extend_from_slice(&ethertype.to_be_bytes());
+        }
+        match (self.src, self.dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let start = buf.len();
+                // Version and header length, DSCP and ECN
+                buf.extend_from_slice(&[0x45, 0]);
+                buf.extend_from_slice(&(ip_len as u16).to_be_bytes());
+                // Identification, don't fragment
+                buf.extend_from_slice(&[0, 0, 0x40, 0, self.ttl, protocol, 0, 0]);
+                buf.extend_from_slice(&src.octets());
+                buf.extend_from_slice(&dst.octets());
+                let checksum = checksum::checksum(&buf[start..]);
+                buf[start + 10..start + 12].copy_from_slice(&checksum.to_be_bytes());
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                buf.extend_from_slice(&[0x60, 0, 0, 0]);
+                buf.extend_from_slice(&(ip_len as u16).to_be_bytes());
+                buf.extend_from_slice(&[protocol, self.ttl]);
+                buf.extend_from_slice(&src.octets());
+                buf.extend_from_slice(&dst.octets());
+            }
+            _ => unreachable!("addresses of different families"),
+        }
+
+        let start = buf.len();
+        match self.transport {
+            Header::None { .. } => {}
+            Header::Tcp { src_port, dst_port } => {
+                buf.extend_from_slice(&src_port.to_be_bytes());
+                buf.extend_from_slice(&dst_port.to_be_bytes());
+                buf.extend_from_slice(&self.sequence.to_be_bytes());
+                buf.extend_from_slice(&self.acknowledgment.to_be_bytes());
+                // Data offset of 5 words, followed by the control bits.
+                buf.extend_from_slice(&(5 << 12 | self.flags).to_be_bytes());
+                buf.extend_from_slice(&self.window.to_be_bytes());
+                // Checksum and urgent pointer
+                buf.extend_from_slice(&[0; 4]);
+            }
+            Header::Udp { src_port, dst_port } => {
+                buf.extend_from_slice(&src_port.to_be_bytes());
+                buf.extend_from_slice(&dst_port.to_be_bytes());
+                buf.extend_from_slice(&(transport_len as u16).to_be_bytes());
+                buf.extend_from_slice(&[0; 2]);
+            }
+            Header::Icmp {
+                icmp_type,
+                code,
+                rest,
+            } => {
+                buf.extend_from_slice(&[icmp_type, code, 0, 0]);
+                buf.extend_from_slice(&rest);
+            }
+        }
+        buf.extend_from_slice(payload);
+        checksum::fill_transport(self.src, self.dst, protocol, &mut buf[start..]);
+        Ok(())
+    }
+}
+
+fn echo(identifier: u16, sequence: u16) -> [u8; 4] {
+    let [a, b] = identifier.to_be_bytes();
+    let [c, d] = sequence.to_be_bytes();
+    [a, b, c, d]
+}
@@ -0,0 +1,312 @@
+//! The internet checksum of IPv4, ICMP, TCP and UDP headers (RFC 1071).
+//!
+//! ```
+//! use tokio_tun::packet::checksum::{self, Checksum};
+//!
+//! let header = [0x45, 0, 0, 20, 0, 0, 0x40, 0, 64, 17, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1];
+//! let sum = checksum::checksum(&header);
+//! let mut header = header;
+//! header[10..12].copy_from_slice(&sum.to_be_bytes());
+//! // A header including its checksum sums up to zero.
+//! assert_eq!(checksum::checksum(&header), 0);
+//!
+//! // Rewriting the source address only needs the old and new address (RFC 1624).
+//! let updated = checksum::update(sum, &header[12..16], &[192, 168, 0, 2]);
+//! header[12..16].copy_from_slice(&[192, 168, 0, 2]);
+//! header[10..12].fill(0);
+//! assert_eq!(updated, Checksum::new().push(&header).finish());
+//! ```
+
+use std::net::IpAddr;
+
+/// Accumulates the one's complement sum of data, which may be pushed in parts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    sum: u64,
+    /// Whether an odd number of bytes was pushed, so the next byte is the low byte of a word.
+    odd: bool,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a new instance with the pseudo-header of TCP, UDP and ICMPv6, where `len` is
+    /// the length of the transport header and its payload.
+    pub fn pseudo_header(src: IpAddr, dst: IpAddr, protocol: u8, len: u32) -> Self {
+        // The order of words does not matter, so this is the same for IPv4 and IPv6.
+        Self::new()
+            .push_addr(src)
+            .push_addr(dst)
+            .push(&[0, protocol])
+            .push(&len.to_be_bytes())
+    }
+
+    pub fn push(mut self, data: &[u8]) -> Self {
+        let mut data = data;
+        if self.odd {
+            if let Some((&first, rest)) = data.split_first() {
+                self.sum += first as u64;
+                self.odd = false;
+                data = rest;
+            }
+        }
+        let mut words = data.chunks_exact(2);
+        for word in &mut words {
+            self.sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+        }
+        if let [last] = words.remainder() {
+            self.sum += (*last as u64) << 8;
+            self.odd = true;
+        }
+        self
+    }
+
+    fn push_addr(self, addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => self.push(&addr.octets()),
+            IpAddr::V6(addr) => self.push(&addr.octets()),
+        }
+    }
+
+    /// Returns the checksum, i.e. the one's complement of the sum.
+    pub fn finish(self) -> u16 {
+        !fold(self.sum)
+    }
+}
+
+/// Returns the checksum of `data`.
+pub fn checksum(data: &[u8]) -> u16 {
+    Checksum::new().push(data).finish()
+}
+
+/// Returns `checksum` updated for replacing `old` by `new` in the checksummed data (RFC 1624).
+///
+/// Both must have the same length and start at an even offset of the data, which holds for
+/// the addresses and ports in IP, TCP and UDP headers.
+///
+/// # Panics
+///
+/// Panics if `old` and `new` differ in length.
+pub fn update(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    assert_eq!(old.len(), new.len(), "old and new data differ in length");
+    // HC' = ~(~HC + ~m + m')
+    let mut sum = !checksum as u64;
+    for (old, new) in words(old).zip(words(new)) {
+        sum += !old as u64 + new as u64;
+    }
+    !fold(sum)
+}
+
+fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+fn words(data: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    data.chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or_default()]))
+}
+
+/// Offset of the checksum within the header of `protocol`.
+fn offset_of(protocol: u8) -> Option<usize> {
+    match protocol as i32 {
+        libc::IPPROTO_TCP => Some(16),
+        libc::IPPROTO_UDP => Some(6),
+        libc::IPPROTO_ICMP | libc::IPPROTO_ICMPV6 => Some(2),
+        _ => None,
+    }
+}
+
+/// Computes the checksum of the transport header and payload in `transport`.
+pub(super) fn fill_transport(src: IpAddr, dst: IpAddr, protocol: u8, transport: &mut [u8]) {
+    let Some(offset) = offset_of(protocol).filter(|offset| offset + 2 <= transport.len()) else {
+        return;
+    };
+    transport[offset..offset + 2].fill(0);
+    let checksum = match protocol as i32 {
+        libc::IPPROTO_ICMP => Checksum::new(),
+        _ => Checksum::pseudo_header(src, dst, protocol, transport.len() as u32),
+    };
+    write(
+        protocol,
+        transport,
+        offset,
+        checksum.push(transport).finish(),
+    );
+}
+
+/// Updates the checksum in `transport` for replacing `old` by `new` in the pseudo-header or
+/// the ports, which ICMP has neither of.
+pub(super) fn update_transport(protocol: u8, transport: &mut [u8], old: &[u8], new: &[u8]) {
+    let Some(offset) = offset_of(protocol)
+        .filter(|_| protocol as i32 != libc::IPPROTO_ICMP)
+        .filter(|offset| offset + 2 <= transport.len())
+    else {
+        return;
+    };
+    let checksum = u16::from_be_bytes([transport[offset], transport[offset + 1]]);
+    if checksum == 0 && protocol as i32 == libc::IPPROTO_UDP {
+        // UDP over IPv4 without checksum
+        return;
+    }
+    write(protocol, transport, offset, update(checksum, old, new));
+}
+
+fn write(protocol: u8, transport: &mut [u8], offset: usize, checksum: u16) {
+    // Zero means that a UDP datagram has no checksum, so it is sent as its complement.
+    let checksum = match checksum {
+        0 if protocol as i32 == libc::IPPROTO_UDP => 0xffff,
+        checksum => checksum,
+    };
+    transport[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Ipv4PacketMut, Ipv6PacketMut, PacketBuilder};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const DST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const SRC6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+    const DST6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+
+    /// Payloads of odd and even length.
+    fn payloads() -> impl Iterator<Item = Vec<u8>> {
+        [0, 1, 2, 3, 7, 64, 1001]
+            .into_iter()
+            .map(|len| (0..len).map(|i| (i * 37 + 11) as u8).collect())
+    }
+
+    fn builders(ipv4: PacketBuilder, ipv6: PacketBuilder) -> [PacketBuilder; 8] {
+        [
+            ipv4.clone().tcp(40000, 80),
+            ipv4.clone().udp(40000, 53),
+            ipv4.clone().echo_request(7, 1),
+            ipv4.udp(0xffff, 0),
+            ipv6.clone().tcp(40000, 80),
+            ipv6.clone().udp(40000, 53),
+            ipv6.clone().echo_request(7, 1),
+            ipv6.udp(0xffff, 0),
+        ]
+    }
+
+    /// Rewrites the addresses and ports of `packet`, as source and destination NAT would.
+    fn rewrite(packet: &mut [u8], src_port: u16) {
+        match packet[0] >> 4 {
+            4 => {
+                let mut ip = Ipv4PacketMut::new(packet).unwrap();
+                ip.set_source(Ipv4Addr::new(192, 168, 17, 1));
+                ip.set_destination(Ipv4Addr::new(172, 16, 254, 3));
+                ip.set_ttl(63);
+                ip.set_src_port(src_port);
+                ip.set_dst_port(8053);
+            }
+            _ => {
+                let mut ip = Ipv6PacketMut::new(packet).unwrap();
+                ip.set_source("2001:db8::1:2".parse().unwrap());
+                ip.set_destination("2001:db8:ffff::3".parse().unwrap());
+                ip.set_hop_limit(63);
+                ip.set_src_port(src_port);
+                ip.set_dst_port(8053);
+            }
+        }
+    }
+
+    /// Returns `packet` with its checksums computed from scratch.
+    fn recomputed(packet: &[u8]) -> Vec<u8> {
+        let mut packet = packet.to_vec();
+        match packet[0] >> 4 {
+            4 => Ipv4PacketMut::new(&mut packet).unwrap().fill_checksums(),
+            _ => Ipv6PacketMut::new(&mut packet).unwrap().fill_checksums(),
+        }
+        packet
+    }
+
+    #[test]
+    fn incremental_update_matches_recomputation() {
+        let ipv4 = PacketBuilder::ipv4(SRC, DST);
+        let ipv6 = PacketBuilder::ipv6(SRC6, DST6);
+        for payload in payloads() {
+            for builder in builders(ipv4.clone(), ipv6.clone()) {
+                let mut packet = builder.build(&payload).unwrap();
+                rewrite(&mut packet, 12345);
+                assert_eq!(packet, recomputed(&packet), "{:?}", builder);
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_update_handles_every_port() {
+        // Covers the checksums which are computed as zero, which UDP sends as `0xffff`.
+        let ipv4 = PacketBuilder::ipv4(SRC, DST);
+        let ipv6 = PacketBuilder::ipv6(SRC6, DST6);
+        for builder in [
+            ipv4.clone().udp(1, 2),
+            ipv4.tcp(1, 2),
+            ipv6.clone().udp(1, 2),
+            ipv6.tcp(1, 2),
+        ] {
+            let packet = builder.build(b"odd").unwrap();
+            for port in 0..=u16::MAX {
+                let mut packet = packet.clone();
+                rewrite(&mut packet, port);
+                assert_eq!(packet, recomputed(&packet), "{:?} port {}", builder, port);
+            }
+        }
+    }
+
+    #[test]
+    fn icmp_checksum_has_no_pseudo_header() {
+        let mut packet = PacketBuilder::ipv4(SRC, DST)
+            .echo_request(7, 1)
+            .build(b"ping")
+            .unwrap();
+        let icmp = packet[20..].to_vec();
+        rewrite(&mut packet, 0);
+        assert_eq!(&packet[20..], &icmp[..]);
+        assert_eq!(checksum(&packet[20..]), 0);
+    }
+
+    #[test]
+    fn udp_without_checksum_keeps_it() {
+        let mut packet = PacketBuilder::ipv4(SRC, DST)
+            .udp(40000, 53)
+            .build(b"query")
+            .unwrap();
+        packet[26..28].fill(0);
+        rewrite(&mut packet, 12345);
+        assert_eq!(&packet[26..28], &[0, 0]);
+        assert_eq!(checksum(&packet[..20]), 0);
+        assert_eq!(&packet[20..24], &[0x30, 0x39, 0x1f, 0x75]);
+    }
+
+    #[test]
+    fn computed_checksums_verify() {
+        for payload in payloads() {
+            let packet = PacketBuilder::ipv4(SRC, DST)
+                .udp(40000, 53)
+                .build(&payload)
+                .unwrap();
+            let transport = &packet[20..];
+            let sum = Checksum::pseudo_header(SRC.into(), DST.into(), 17, transport.len() as u32)
+                .push(transport)
+                .finish();
+            assert_eq!(sum, 0, "{} bytes", payload.len());
+        }
+    }
+
+    #[test]
+    fn split_pushes_match() {
+        let data: Vec<u8> = (0..101).collect();
+        for split in 0..data.len() {
+            let (a, b) = data.split_at(split);
+            assert_eq!(Checksum::new().push(a).push(b).finish(), checksum(&data));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use super::{array, be16, ETH_P_8021AD, ETH_P_8021Q, ETH_P_IP};
+use crate::mac::MacAddr;
+use std::net::Ipv4Addr;
+
+/// A view of an Ethernet II frame, which may carry 802.1Q and 802.1ad tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetFrame<'a> {
+    buf: &'a [u8],
+    header_len: usize,
+}
+
+/// Represents an 802.1Q or 802.1ad tag of an [`EthernetFrame`](struct.EthernetFrame.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VlanTag {
+    /// `0x8100` for 802.1Q, or `0x88a8` for the outer tag of 802.1ad.
+    pub tpid: u16,
+    /// Priority code point.
+    pub pcp: u8,
+    /// Drop eligible indicator.
+    pub dei: bool,
+    /// VLAN identifier.
+    pub vid: u16,
+}
+
+impl<'a> EthernetFrame<'a> {
+    /// Length of the header without tags.
+    pub const HEADER_LEN: usize = 14;
+
+    /// Creates a view of the frame in `buf`, or returns `None` if its header is truncated.
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        let mut header_len = Self::HEADER_LEN;
+        while buf.len() >= header_len
+            && matches!(be16(buf, header_len - 2), ETH_P_8021Q | ETH_P_8021AD)
+        {
+            header_len += 4;
+        }
+        (buf.len() >= header_len).then_some(Self { buf, header_len })
+    }
+
+    pub fn destination(&self) -> MacAddr {
+        array(self.buf, 0).into()
+    }
+
+    pub fn source(&self) -> MacAddr {
+        array(self.buf, 6).into()
+    }
+
+    /// Returns the tags of the frame, outermost first.
+    pub fn vlan_tags(&self) -> impl Iterator<Item = VlanTag> + 'a {
+        let buf = self.buf;
+        (12..self.header_len - 2).step_by(4).map(move |offset| {
+            let tci = be16(buf, offset + 2);
+            VlanTag {
+                tpid: be16(buf, offset),
+                pcp: (tci >> 13) as u8,
+                dei: tci & 0x1000 != 0,
+                vid: tci & 0x0fff,
+            }
+        })
+    }
+
+    /// Returns the ethertype of the payload, following any tags.
+    pub fn ethertype(&self) -> u16 {
+        be16(self.buf, self.header_len - 2)
+    }
+
+    /// Returns the length of the header, including tags.
+    pub fn header_len(&self) -> usize {
+        self.header_len
+    }
+
+    /// Returns the payload, which may be followed by padding.
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[self.header_len..]
+    }
+}
+
+/// A view of an ARP packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ArpPacket<'a> {
+    /// Creates a view of the packet in `buf`, or returns `None` if it is truncated.
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        let len = 8 + 2 * (*buf.get(4)? as usize + *buf.get(5)? as usize);
+        (buf.len() >= len).then(|| Self { buf: &buf[..len] })
+    }
+
+    /// Returns the hardware type, e.g. `1` for Ethernet.
+    pub fn hardware_type(&self) -> u16 {
+        be16(self.buf, 0)
+    }
+
+    /// Returns the ethertype of the protocol addresses, e.g. `0x0800` for IPv4.
+    pub fn protocol_type(&self) -> u16 {
+        be16(self.buf, 2)
+    }
+
+    /// Returns the operation, `1` for a request and `2` for a reply.
+    pub fn operation(&self) -> u16 {
+        be16(self.buf, 6)
+    }
+
+    pub fn sender_hardware_addr(&self) -> &'a [u8] {
+        &self.buf[8..8 + self.hardware_len()]
+    }
+
+    pub fn sender_protocol_addr(&self) -> &'a [u8] {
+        let offset = 8 + self.hardware_len();
+        &self.buf[offset..offset + self.protocol_len()]
+    }
+
+    pub fn target_hardware_addr(&self) -> &'a [u8] {
+        let offset = 8 + self.hardware_len() + self.protocol_len();
+        &self.buf[offset..offset + self.hardware_len()]
+    }
+
+    pub fn target_protocol_addr(&self) -> &'a [u8] {
+        &self.buf[8 + 2 * self.hardware_len() + self.protocol_len()..]
+    }
+
+    /// Returns the sender MAC address, if the hardware addresses are 6 bytes long.
+    pub fn sender_mac(&self) -> Option<MacAddr> {
+        <[u8; 6]>::try_from(self.sender_hardware_addr())
+            .ok()
+            .map(Into::into)
+    }
+
+    /// Returns the sender IPv4 address, if the protocol addresses are IPv4 addresses.
+    pub fn sender_ip(&self) -> Option<Ipv4Addr> {
+        self.ipv4(self.sender_protocol_addr())
+    }
+
+    /// Returns the target MAC address, if the hardware addresses are 6 bytes long.
+    pub fn target_mac(&self) -> Option<MacAddr> {
+        <[u8; 6]>::try_from(self.target_hardware_addr())
+            .ok()
+            .map(Into::into)
+    }
+
+    /// Returns the target IPv4 address, if the protocol addresses are IPv4 addresses.
+    pub fn target_ip(&self) -> Option<Ipv4Addr> {
+        self.ipv4(self.target_protocol_addr())
+    }
+
+    fn hardware_len(&self) -> usize {
+        self.buf[4] as usize
+    }
+
+    fn protocol_len(&self) -> usize {
+        self.buf[5] as usize
+    }
+
+    fn ipv4(&self, addr: &[u8]) -> Option<Ipv4Addr> {
+        if self.protocol_type() != ETH_P_IP {
+            return None;
+        }
+        <[u8; 4]>::try_from(addr).ok().map(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ETH_P_ARP;
+
+    const DST: [u8; 6] = [0xff; 6];
+    const SRC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+
+    fn frame(tags: &[[u8; 4]], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut buf = [DST, SRC].concat();
+        for tag in tags {
+            buf.extend_from_slice(tag);
+        }
+        buf.extend_from_slice(&ethertype.to_be_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let buf = frame(&[], ETH_P_IP, &[]);
+        for len in 0..EthernetFrame::HEADER_LEN {
+            assert_eq!(EthernetFrame::new(&buf[..len]), None, "{} bytes", len);
+        }
+        let frame = EthernetFrame::new(&buf).unwrap();
+        assert_eq!((frame.ethertype(), frame.payload()), (ETH_P_IP, &[][..]));
+    }
+
+    #[test]
+    fn parses_vlan_tags() {
+        // Priority 5 and VLAN 100
+        let buf = frame(&[[0x81, 0x00, 0xa0, 100]], ETH_P_IP, &[1, 2]);
+        let frame = EthernetFrame::new(&buf).unwrap();
+        assert_eq!(frame.source(), MacAddr::from(SRC));
+        assert_eq!(frame.destination(), MacAddr::from(DST));
+        let tags: Vec<_> = frame.vlan_tags().collect();
+        assert_eq!(
+            tags,
+            [VlanTag {
+                tpid: ETH_P_8021Q,
+                pcp: 5,
+                dei: false,
+                vid: 100,
+            }]
+        );
+        assert_eq!(frame.header_len(), 18);
+        assert_eq!(
+            (frame.ethertype(), frame.payload()),
+            (ETH_P_IP, &[1, 2][..])
+        );
+    }
+
+    #[test]
+    fn parses_qinq_tags() {
+        let buf = frame(
+            &[[0x88, 0xa8, 0x10, 10], [0x81, 0x00, 0, 20]],
+            ETH_P_IP,
+            &[1],
+        );
+        let frame = EthernetFrame::new(&buf).unwrap();
+        let tags: Vec<_> = frame
+            .vlan_tags()
+            .map(|tag| (tag.tpid, tag.dei, tag.vid))
+            .collect();
+        assert_eq!(tags, [(ETH_P_8021AD, true, 10), (ETH_P_8021Q, false, 20)]);
+        assert_eq!(frame.header_len(), 22);
+        assert_eq!((frame.ethertype(), frame.payload()), (ETH_P_IP, &[1][..]));
+
+        // The inner tag is cut off.
+        assert_eq!(EthernetFrame::new(&buf[..19]), None);
+        assert_eq!(EthernetFrame::new(&buf[..21]), None);
+    }
+
+    #[test]
+    fn parses_arp() {
+        let request = [
+            &[0, 1, 0x08, 0x00, 6, 4, 0, 1][..],
+            &SRC,
+            &[10, 0, 0, 2],
+            &[0; 6],
+            &[10, 0, 0, 1],
+        ]
+        .concat();
+        let arp = ArpPacket::new(&request).unwrap();
+        assert_eq!((arp.hardware_type(), arp.operation()), (1, 1));
+        assert_eq!(arp.sender_mac(), Some(MacAddr::from(SRC)));
+        assert_eq!(arp.sender_ip(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(arp.target_ip(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+
+        for len in 0..request.len() {
+            assert_eq!(ArpPacket::new(&request[..len]), None, "{} bytes", len);
+        }
+        let buf = frame(&[], ETH_P_ARP, &request);
+        let frame = EthernetFrame::new(&buf).unwrap();
+        assert_eq!(ArpPacket::new(frame.payload()), Some(arp));
+    }
+}
@@ -0,0 +1,80 @@
+use super::be16;
+
+/// Represents the identifier and sequence number of an echo request or reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Echo {
+    pub identifier: u16,
+    pub sequence: u16,
+}
+
+macro_rules! icmp {
+    ($(#[$attr:meta])* $name:ident, $request:expr, $reply:expr) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct $name<'a> {
+            buf: &'a [u8],
+        }
+
+        impl<'a> $name<'a> {
+            pub const HEADER_LEN: usize = 8;
+            pub const ECHO_REQUEST: u8 = $request;
+            pub const ECHO_REPLY: u8 = $reply;
+
+            /// Creates a view of the message in `buf`, or returns `None` if its header is
+            /// truncated.
+            pub fn new(buf: &'a [u8]) -> Option<Self> {
+                (buf.len() >= Self::HEADER_LEN).then_some(Self { buf })
+            }
+
+            pub fn icmp_type(&self) -> u8 {
+                self.buf[0]
+            }
+
+            pub fn code(&self) -> u8 {
+                self.buf[1]
+            }
+
+            pub fn checksum(&self) -> u16 {
+                be16(self.buf, 2)
+            }
+
+            /// Returns the four bytes following the checksum, whose meaning depends on the
+            /// type of message.
+            pub fn rest_of_header(&self) -> [u8; 4] {
+                super::array(self.buf, 4)
+            }
+
+            /// Returns the identifier and sequence number of an echo request or reply.
+            pub fn echo(&self) -> Option<Echo> {
+                matches!(self.icmp_type(), Self::ECHO_REQUEST | Self::ECHO_REPLY).then(|| Echo {
+                    identifier: be16(self.buf, 4),
+                    sequence: be16(self.buf, 6),
+                })
+            }
+
+            /// Returns the data following the header, e.g. the invoking packet of an error
+            /// message.
+            pub fn payload(&self) -> &'a [u8] {
+                &self.buf[Self::HEADER_LEN..]
+            }
+
+            pub fn as_bytes(&self) -> &'a [u8] {
+                self.buf
+            }
+        }
+    };
+}
+
+icmp!(
+    /// A view of an ICMP message.
+    IcmpPacket,
+    8,
+    0
+);
+
+icmp!(
+    /// A view of an ICMPv6 message.
+    Icmpv6Packet,
+    128,
+    129
+);
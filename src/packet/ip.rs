@@ -0,0 +1,689 @@
+use super::{array, be16, be32, checksum, Transport};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// A view of an IPv4 packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Packet<'a> {
+    /// Bounded by the total length, so that padding is excluded.
+    buf: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Creates a view of the packet in `buf`, or returns `None` if it is not an IPv4 packet or
+    /// shorter than its total length. Bytes following the total length are ignored.
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        let header_len = (*buf.first()? as usize & 0x0f) * 4;
+        if buf[0] >> 4 != 4 || header_len < 20 || buf.len() < 20 {
+            return None;
+        }
+        let total_len = be16(buf, 2) as usize;
+        (header_len <= total_len && total_len <= buf.len()).then(|| Self {
+            buf: &buf[..total_len],
+        })
+    }
+
+    /// Returns the length of the header, including options.
+    pub fn header_len(&self) -> usize {
+        (self.buf[0] as usize & 0x0f) * 4
+    }
+
+    /// Returns the differentiated services code point.
+    pub fn dscp(&self) -> u8 {
+        self.buf[1] >> 2
+    }
+
+    /// Returns the explicit congestion notification.
+    pub fn ecn(&self) -> u8 {
+        self.buf[1] & 0x03
+    }
+
+    pub fn total_len(&self) -> u16 {
+        be16(self.buf, 2)
+    }
+
+    pub fn identification(&self) -> u16 {
+        be16(self.buf, 4)
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        self.buf[6] & 0x40 != 0
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.buf[6] & 0x20 != 0
+    }
+
+    /// Returns the offset of the fragment in bytes.
+    pub fn fragment_offset(&self) -> u16 {
+        (be16(self.buf, 6) & 0x1fff) * 8
+    }
+
+    /// Returns `true` if the packet is a fragment of a larger one.
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.fragment_offset() != 0
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.buf[8]
+    }
+
+    /// Returns the protocol of the payload, e.g. `6` for TCP.
+    pub fn protocol(&self) -> u8 {
+        self.buf[9]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        be16(self.buf, 10)
+    }
+
+    pub fn source(&self) -> Ipv4Addr {
+        array::<4>(self.buf, 12).into()
+    }
+
+    pub fn destination(&self) -> Ipv4Addr {
+        array::<4>(self.buf, 16).into()
+    }
+
+    pub fn options(&self) -> &'a [u8] {
+        &self.buf[20..self.header_len()]
+    }
+
+    pub fn header(&self) -> &'a [u8] {
+        &self.buf[..self.header_len()]
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[self.header_len()..]
+    }
+
+    /// Returns the whole packet, without the padding which followed it.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    /// Returns the transport layer, or `None` if the packet is a fragment or the header of the
+    /// transport protocol is truncated or malformed.
+    pub fn transport(&self) -> Option<Transport<'a>> {
+        if self.is_fragment() {
+            return None;
+        }
+        Transport::new(self.protocol(), self.payload())
+    }
+}
+
+/// A view of an IPv6 packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Packet<'a> {
+    /// Bounded by the payload length, so that padding is excluded.
+    buf: &'a [u8],
+}
+
+/// Represents an extension header of an [`Ipv6Packet`](struct.Ipv6Packet.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtensionHeader<'a> {
+    /// Protocol number of this header, e.g. `44` for a fragment header.
+    pub protocol: u8,
+    /// Protocol number of the following header.
+    pub next_header: u8,
+    /// The whole header, including the next header and length fields.
+    pub data: &'a [u8],
+}
+
+/// Iterates over the extension headers of an [`Ipv6Packet`](struct.Ipv6Packet.html).
+///
+/// Iteration stops at the first header which is not an extension header, or which is
+/// truncated.
+#[derive(Debug, Clone)]
+pub struct ExtensionHeaders<'a> {
+    next_header: u8,
+    rest: &'a [u8],
+    truncated: bool,
+}
+
+/// Represents the fragment header of an [`Ipv6Packet`](struct.Ipv6Packet.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv6Fragment {
+    /// Offset of the fragment in bytes.
+    pub offset: u16,
+    pub more_fragments: bool,
+    pub identification: u32,
+}
+
+impl<'a> Ipv6Packet<'a> {
+    pub const HEADER_LEN: usize = 40;
+
+    /// Creates a view of the packet in `buf`, or returns `None` if it is not an IPv6 packet or
+    /// shorter than its payload length. Bytes following the payload are ignored.
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < Self::HEADER_LEN || buf[0] >> 4 != 6 {
+            return None;
+        }
+        let len = Self::HEADER_LEN + be16(buf, 4) as usize;
+        (len <= buf.len()).then(|| Self { buf: &buf[..len] })
+    }
+
+    pub fn traffic_class(&self) -> u8 {
+        (be16(self.buf, 0) >> 4) as u8
+    }
+
+    pub fn flow_label(&self) -> u32 {
+        be32(self.buf, 0) & 0x000f_ffff
+    }
+
+    pub fn payload_len(&self) -> u16 {
+        be16(self.buf, 4)
+    }
+
+    /// Returns the protocol of the header following the fixed one, which may be an extension
+    /// header.
+    pub fn next_header(&self) -> u8 {
+        self.buf[6]
+    }
+
+    pub fn hop_limit(&self) -> u8 {
+        self.buf[7]
+    }
+
+    pub fn source(&self) -> Ipv6Addr {
+        array::<16>(self.buf, 8).into()
+    }
+
+    pub fn destination(&self) -> Ipv6Addr {
+        array::<16>(self.buf, 24).into()
+    }
+
+    /// Returns the payload, including the extension headers.
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[Self::HEADER_LEN..]
+    }
+
+    /// Returns the whole packet, without the padding which followed it.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    pub fn extension_headers(&self) -> ExtensionHeaders<'a> {
+        ExtensionHeaders {
+            next_header: self.next_header(),
+            rest: self.payload(),
+            truncated: false,
+        }
+    }
+
+    /// Returns the fragment header, if any.
+    pub fn fragment(&self) -> Option<Ipv6Fragment> {
+        self.extension_headers()
+            .find(|header| header.protocol as i32 == libc::IPPROTO_FRAGMENT)
+            .map(|header| Ipv6Fragment {
+                offset: be16(header.data, 2) & 0xfff8,
+                more_fragments: header.data[3] & 0x01 != 0,
+                identification: be32(header.data, 4),
+            })
+    }
+
+    /// Returns `true` if the packet is a fragment of a larger one. Atomic fragments, i.e.
+    /// fragment headers of unfragmented packets, are not.
+    pub fn is_fragment(&self) -> bool {
+        self.fragment()
+            .is_some_and(|fragment| fragment.offset != 0 || fragment.more_fragments)
+    }
+
+    /// Returns the protocol following the extension headers, e.g. `6` for TCP.
+    pub fn protocol(&self) -> u8 {
+        self.upper_layer().0
+    }
+
+    /// Returns the transport layer, or `None` if the packet is a fragment or the extension
+    /// headers or the header of the transport protocol are truncated or malformed.
+    pub fn transport(&self) -> Option<Transport<'a>> {
+        if self.is_fragment() {
+            return None;
+        }
+        let (protocol, payload) = self.upper_layer();
+        Transport::new(protocol, payload?)
+    }
+
+    /// Returns the protocol and payload following the extension headers, the payload is
+    /// `None` if they are truncated.
+    fn upper_layer(&self) -> (u8, Option<&'a [u8]>) {
+        let mut headers = self.extension_headers();
+        headers.by_ref().for_each(drop);
+        (
+            headers.next_header,
+            (!headers.truncated).then_some(headers.rest),
+        )
+    }
+}
+
+impl<'a> Iterator for ExtensionHeaders<'a> {
+    type Item = ExtensionHeader<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.truncated {
+            return None;
+        }
+        let len = match self.next_header as i32 {
+            libc::IPPROTO_HOPOPTS | libc::IPPROTO_ROUTING | libc::IPPROTO_DSTOPTS => {
+                self.rest.get(1).map(|&len| (len as usize + 1) * 8)
+            }
+            libc::IPPROTO_FRAGMENT => Some(8),
+            libc::IPPROTO_AH => self.rest.get(1).map(|&len| (len as usize + 2) * 4),
+            _ => return None,
+        };
+        match len.filter(|&len| len <= self.rest.len()) {
+            Some(len) => {
+                let header = ExtensionHeader {
+                    protocol: self.next_header,
+                    next_header: self.rest[0],
+                    data: &self.rest[..len],
+                };
+                self.next_header = header.next_header;
+                self.rest = &self.rest[len..];
+                Some(header)
+            }
+            None => {
+                self.truncated = true;
+                None
+            }
+        }
+    }
+}
+
+/// A mutable view of an IPv4 packet, whose setters keep the checksums valid.
+///
+/// ```
+/// use std::net::Ipv4Addr;
+/// use tokio_tun::packet::{Ipv4PacketMut, PacketBuilder};
+///
+/// let mut packet = PacketBuilder::ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 1))
+///     .udp(1234, 53)
+///     .build(b"query")?;
+/// // Source NAT
+/// let mut ip = Ipv4PacketMut::new(&mut packet).unwrap();
+/// ip.set_source(Ipv4Addr::new(192, 168, 0, 2));
+/// ip.set_src_port(40000);
+///
+/// let expected = PacketBuilder::ipv4(Ipv4Addr::new(192, 168, 0, 2), Ipv4Addr::new(10, 0, 0, 1))
+///     .udp(40000, 53)
+///     .build(b"query")?;
+/// assert_eq!(packet, expected);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ipv4PacketMut<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Ipv4PacketMut<'a> {
+    /// Creates a mutable view of the packet in `buf`, see
+    /// [`Ipv4Packet::new`](struct.Ipv4Packet.html#method.new).
+    pub fn new(buf: &'a mut [u8]) -> Option<Self> {
+        let len = Ipv4Packet::new(buf)?.buf.len();
+        Some(Self {
+            buf: &mut buf[..len],
+        })
+    }
+
+    pub fn view(&self) -> Ipv4Packet<'_> {
+        Ipv4Packet { buf: self.buf }
+    }
+
+    pub fn set_source(&mut self, addr: Ipv4Addr) {
+        self.set_addr(12, addr.octets());
+    }
+
+    pub fn set_destination(&mut self, addr: Ipv4Addr) {
+        self.set_addr(16, addr.octets());
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.set_header(8, &[ttl, self.buf[9]]);
+    }
+
+    /// Sets the source port of TCP or UDP, and returns `false` if the packet has no ports,
+    /// e.g. a non-first fragment.
+    pub fn set_src_port(&mut self, port: u16) -> bool {
+        set_port(self.transport(), 0, port)
+    }
+
+    /// Sets the destination port of TCP or UDP, and returns `false` if the packet has no
+    /// ports, e.g. a non-first fragment.
+    pub fn set_dst_port(&mut self, port: u16) -> bool {
+        set_port(self.transport(), 2, port)
+    }
+
+    /// Computes the checksums of the header and of ICMP, TCP and UDP from scratch, e.g. after
+    /// changing the payload. The checksum of the transport protocol is left as is for
+    /// fragments.
+    pub fn fill_checksums(&mut self) {
+        let header_len = self.view().header_len();
+        self.buf[10..12].fill(0);
+        let checksum = checksum::checksum(&self.buf[..header_len]);
+        self.buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+        let (src, dst) = (self.view().source(), self.view().destination());
+        if !self.view().is_fragment() {
+            let protocol = self.view().protocol();
+            checksum::fill_transport(
+                src.into(),
+                dst.into(),
+                protocol,
+                &mut self.buf[header_len..],
+            );
+        }
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let header_len = self.view().header_len();
+        &mut self.buf[header_len..]
+    }
+
+    /// Returns the protocol and header of the transport layer, unless this is a non-first
+    /// fragment.
+    fn transport(&mut self) -> Option<(u8, &mut [u8])> {
+        let view = self.view();
+        if view.fragment_offset() != 0 {
+            return None;
+        }
+        let (protocol, header_len) = (view.protocol(), view.header_len());
+        Some((protocol, &mut self.buf[header_len..]))
+    }
+
+    fn set_header(&mut self, offset: usize, new: &[u8]) {
+        let range = offset..offset + new.len();
+        let checksum = checksum::update(self.view().checksum(), &self.buf[range.clone()], new);
+        self.buf[range].copy_from_slice(new);
+        self.buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    fn set_addr(&mut self, offset: usize, new: [u8; 4]) {
+        let old: [u8; 4] = array(self.buf, offset);
+        self.set_header(offset, &new);
+        if let Some((protocol, transport)) = self.transport() {
+            checksum::update_transport(protocol, transport, &old, &new);
+        }
+    }
+}
+
+/// A mutable view of an IPv6 packet, whose setters keep the checksums valid.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ipv6PacketMut<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Ipv6PacketMut<'a> {
+    /// Creates a mutable view of the packet in `buf`, see
+    /// [`Ipv6Packet::new`](struct.Ipv6Packet.html#method.new).
+    pub fn new(buf: &'a mut [u8]) -> Option<Self> {
+        let len = Ipv6Packet::new(buf)?.buf.len();
+        Some(Self {
+            buf: &mut buf[..len],
+        })
+    }
+
+    pub fn view(&self) -> Ipv6Packet<'_> {
+        Ipv6Packet { buf: self.buf }
+    }
+
+    pub fn set_source(&mut self, addr: Ipv6Addr) {
+        self.set_addr(8, addr.octets());
+    }
+
+    pub fn set_destination(&mut self, addr: Ipv6Addr) {
+        self.set_addr(24, addr.octets());
+    }
+
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.buf[7] = hop_limit;
+    }
+
+    /// Sets the source port of TCP or UDP, and returns `false` if the packet has no ports,
+    /// e.g. a non-first fragment.
+    pub fn set_src_port(&mut self, port: u16) -> bool {
+        set_port(self.transport(), 0, port)
+    }
+
+    /// Sets the destination port of TCP or UDP, and returns `false` if the packet has no
+    /// ports, e.g. a non-first fragment.
+    pub fn set_dst_port(&mut self, port: u16) -> bool {
+        set_port(self.transport(), 2, port)
+    }
+
+    /// Computes the checksum of ICMPv6, TCP and UDP from scratch, e.g. after changing the
+    /// payload. It is left as is for fragments.
+    pub fn fill_checksums(&mut self) {
+        let (src, dst) = (self.view().source(), self.view().destination());
+        if self.view().is_fragment() {
+            return;
+        }
+        if let Some((protocol, transport)) = self.transport() {
+            checksum::fill_transport(src.into(), dst.into(), protocol, transport);
+        }
+    }
+
+    /// Returns the payload, including the extension headers.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[Ipv6Packet::HEADER_LEN..]
+    }
+
+    /// Returns the protocol and header of the transport layer, unless this is a non-first
+    /// fragment or the extension headers are truncated.
+    fn transport(&mut self) -> Option<(u8, &mut [u8])> {
+        let view = self.view();
+        if view.fragment().is_some_and(|fragment| fragment.offset != 0) {
+            return None;
+        }
+        let (protocol, payload) = view.upper_layer();
+        let offset = self.buf.len() - payload?.len();
+        Some((protocol, &mut self.buf[offset..]))
+    }
+
+    fn set_addr(&mut self, offset: usize, new: [u8; 16]) {
+        let old: [u8; 16] = array(self.buf, offset);
+        self.buf[offset..offset + 16].copy_from_slice(&new);
+        if let Some((protocol, transport)) = self.transport() {
+            checksum::update_transport(protocol, transport, &old, &new);
+        }
+    }
+}
+
+/// Sets the port at `offset` of a TCP or UDP header, and updates its checksum.
+fn set_port(transport: Option<(u8, &mut [u8])>, offset: usize, port: u16) -> bool {
+    let Some((protocol, transport)) = transport.filter(|(protocol, transport)| {
+        matches!(*protocol as i32, libc::IPPROTO_TCP | libc::IPPROTO_UDP) && transport.len() >= 4
+    }) else {
+        return false;
+    };
+    let old: [u8; 2] = array(transport, offset);
+    let new = port.to_be_bytes();
+    transport[offset..offset + 2].copy_from_slice(&new);
+    checksum::update_transport(protocol, transport, &old, &new);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketBuilder;
+
+    fn ipv4() -> Vec<u8> {
+        PacketBuilder::ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 1))
+            .udp(1234, 53)
+            .build(b"query")
+            .unwrap()
+    }
+
+    fn ipv6() -> Vec<u8> {
+        PacketBuilder::ipv6("fd00::2".parse().unwrap(), "fd00::1".parse().unwrap())
+            .udp(1234, 53)
+            .build(b"query")
+            .unwrap()
+    }
+
+    /// Inserts `headers` between the fixed header and the payload of `packet`, where `first`
+    /// is the protocol of the first of them.
+    fn with_extension_headers(packet: &[u8], first: u8, headers: &[u8]) -> Vec<u8> {
+        let mut buf = packet[..Ipv6Packet::HEADER_LEN].to_vec();
+        buf[6] = first;
+        let payload_len = (packet.len() - Ipv6Packet::HEADER_LEN + headers.len()) as u16;
+        buf[4..6].copy_from_slice(&payload_len.to_be_bytes());
+        buf.extend_from_slice(headers);
+        buf.extend_from_slice(&packet[Ipv6Packet::HEADER_LEN..]);
+        buf
+    }
+
+    fn fragment_header(next_header: u8, offset: u16, more_fragments: bool) -> [u8; 8] {
+        let [a, b] = (offset | more_fragments as u16).to_be_bytes();
+        [next_header, 0, a, b, 0, 0, 0x12, 0x34]
+    }
+
+    #[test]
+    fn ipv4_parses_udp() {
+        let buf = ipv4();
+        let packet = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(packet.header_len(), 20);
+        assert_eq!(packet.total_len() as usize, buf.len());
+        assert!(packet.dont_fragment() && !packet.is_fragment());
+        let Some(Transport::Udp(udp)) = packet.transport() else {
+            panic!()
+        };
+        assert_eq!((udp.src_port(), udp.payload()), (1234, &b"query"[..]));
+    }
+
+    #[test]
+    fn ipv4_rejects_truncated_header() {
+        let buf = ipv4();
+        for len in 0..20 {
+            assert_eq!(Ipv4Packet::new(&buf[..len]), None, "{} bytes", len);
+        }
+        // The UDP header is cut by the total length.
+        let mut buf = buf[..24].to_vec();
+        buf[2..4].copy_from_slice(&24u16.to_be_bytes());
+        assert_eq!(Ipv4Packet::new(&buf).unwrap().transport(), None);
+    }
+
+    #[test]
+    fn ipv4_rejects_invalid_header_len() {
+        let mut buf = ipv4();
+        buf[0] = 0x44;
+        assert_eq!(Ipv4Packet::new(&buf), None);
+        // 60 bytes of header exceed the total length of 33 bytes.
+        buf[0] = 0x4f;
+        assert_eq!(Ipv4Packet::new(&buf), None);
+        buf[0] = 0x65;
+        assert_eq!(Ipv4Packet::new(&buf), None);
+    }
+
+    #[test]
+    fn ipv4_checks_total_len() {
+        let mut buf = ipv4();
+        let len = buf.len();
+        buf[2..4].copy_from_slice(&(len as u16 + 1).to_be_bytes());
+        assert_eq!(Ipv4Packet::new(&buf), None);
+        buf[2..4].copy_from_slice(&19u16.to_be_bytes());
+        assert_eq!(Ipv4Packet::new(&buf), None);
+
+        // Padding, e.g. of a short Ethernet frame, is excluded.
+        let mut buf = ipv4();
+        buf.extend_from_slice(&[0; 10]);
+        let packet = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(packet.as_bytes().len(), len);
+        let Some(Transport::Udp(udp)) = packet.transport() else {
+            panic!()
+        };
+        assert_eq!(udp.payload(), b"query");
+    }
+
+    #[test]
+    fn ipv4_fragments_have_no_transport() {
+        let mut buf = ipv4();
+        // First fragment
+        buf[6] = 0x20;
+        let packet = Ipv4Packet::new(&buf).unwrap();
+        assert!(packet.is_fragment() && packet.more_fragments());
+        assert_eq!(packet.transport(), None);
+        // Last fragment at offset 8
+        buf[6..8].copy_from_slice(&1u16.to_be_bytes());
+        let packet = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(packet.fragment_offset(), 8);
+        assert!(packet.is_fragment() && !packet.more_fragments());
+        assert_eq!(packet.transport(), None);
+    }
+
+    #[test]
+    fn ipv6_rejects_truncated_header() {
+        let buf = ipv6();
+        for len in 0..Ipv6Packet::HEADER_LEN {
+            assert_eq!(Ipv6Packet::new(&buf[..len]), None, "{} bytes", len);
+        }
+        assert_eq!(Ipv6Packet::new(&buf[..buf.len() - 1]), None);
+
+        let mut buf = buf;
+        buf[0] = 0x40;
+        assert_eq!(Ipv6Packet::new(&buf), None);
+    }
+
+    #[test]
+    fn ipv6_skips_extension_headers() {
+        let udp = libc::IPPROTO_UDP as u8;
+        let fragment = libc::IPPROTO_FRAGMENT as u8;
+        // Hop-by-hop options with padding, followed by an atomic fragment.
+        let mut headers = vec![fragment, 0, 1, 4, 0, 0, 0, 0];
+        headers.extend_from_slice(&fragment_header(udp, 0, false));
+        let buf = with_extension_headers(&ipv6(), libc::IPPROTO_HOPOPTS as u8, &headers);
+        let packet = Ipv6Packet::new(&buf).unwrap();
+        let protocols: Vec<_> = packet.extension_headers().map(|h| h.protocol).collect();
+        assert_eq!(protocols, [libc::IPPROTO_HOPOPTS as u8, fragment]);
+        assert_eq!(packet.protocol(), udp);
+        assert!(packet.fragment().is_some() && !packet.is_fragment());
+        let Some(Transport::Udp(udp)) = packet.transport() else {
+            panic!()
+        };
+        assert_eq!(udp.payload(), b"query");
+    }
+
+    #[test]
+    fn ipv6_rejects_extension_headers_past_end() {
+        let hopopts = libc::IPPROTO_HOPOPTS as u8;
+        // The length of the hop-by-hop options claims 16 bytes.
+        let buf = with_extension_headers(&ipv6()[..40], hopopts, &[17, 1, 0, 0, 0, 0, 0, 0]);
+        let packet = Ipv6Packet::new(&buf).unwrap();
+        assert_eq!(packet.extension_headers().count(), 0);
+        assert_eq!(packet.transport(), None);
+
+        // The fragment header is cut after 4 bytes.
+        let fragment = fragment_header(libc::IPPROTO_UDP as u8, 0, false);
+        let buf =
+            with_extension_headers(&ipv6()[..40], libc::IPPROTO_FRAGMENT as u8, &fragment[..4]);
+        let packet = Ipv6Packet::new(&buf).unwrap();
+        assert_eq!(packet.fragment(), None);
+        assert_eq!(packet.transport(), None);
+
+        // The length field itself is missing.
+        let buf = with_extension_headers(&ipv6()[..40], hopopts, &[17]);
+        assert_eq!(Ipv6Packet::new(&buf).unwrap().transport(), None);
+    }
+
+    #[test]
+    fn ipv6_fragments_have_no_transport() {
+        let udp = libc::IPPROTO_UDP as u8;
+        let fragment = libc::IPPROTO_FRAGMENT as u8;
+        let first = with_extension_headers(&ipv6(), fragment, &fragment_header(udp, 0, true));
+        let packet = Ipv6Packet::new(&first).unwrap();
+        assert_eq!(
+            packet.fragment(),
+            Some(Ipv6Fragment {
+                offset: 0,
+                more_fragments: true,
+                identification: 0x1234,
+            })
+        );
+        assert!(packet.is_fragment());
+        assert_eq!(packet.transport(), None);
+
+        let last = with_extension_headers(&ipv6(), fragment, &fragment_header(udp, 1280, false));
+        let packet = Ipv6Packet::new(&last).unwrap();
+        assert_eq!(packet.fragment().unwrap().offset, 1280);
+        assert!(packet.is_fragment());
+        assert_eq!(packet.transport(), None);
+    }
+}
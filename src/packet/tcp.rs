@@ -0,0 +1,80 @@
+use super::{be16, be32};
+
+/// A view of a TCP segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpSegment<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub const FIN: u16 = 0x001;
+    pub const SYN: u16 = 0x002;
+    pub const RST: u16 = 0x004;
+    pub const PSH: u16 = 0x008;
+    pub const ACK: u16 = 0x010;
+    pub const URG: u16 = 0x020;
+    pub const ECE: u16 = 0x040;
+    pub const CWR: u16 = 0x080;
+
+    /// Creates a view of the segment in `buf`, or returns `None` if its header is truncated
+    /// or malformed.
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        let header_len = (*buf.get(12)? as usize >> 4) * 4;
+        (header_len >= 20 && header_len <= buf.len()).then_some(Self { buf })
+    }
+
+    pub fn src_port(&self) -> u16 {
+        be16(self.buf, 0)
+    }
+
+    pub fn dst_port(&self) -> u16 {
+        be16(self.buf, 2)
+    }
+
+    pub fn sequence(&self) -> u32 {
+        be32(self.buf, 4)
+    }
+
+    pub fn acknowledgment(&self) -> u32 {
+        be32(self.buf, 8)
+    }
+
+    /// Returns the length of the header, including options.
+    pub fn header_len(&self) -> usize {
+        (self.buf[12] as usize >> 4) * 4
+    }
+
+    /// Returns the control bits, e.g. `TcpSegment::SYN | TcpSegment::ACK`.
+    pub fn flags(&self) -> u16 {
+        be16(self.buf, 12) & 0x01ff
+    }
+
+    /// Returns `true` if all of `flags` are set.
+    pub fn has_flags(&self, flags: u16) -> bool {
+        self.flags() & flags == flags
+    }
+
+    pub fn window(&self) -> u16 {
+        be16(self.buf, 14)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        be16(self.buf, 16)
+    }
+
+    pub fn urgent_pointer(&self) -> u16 {
+        be16(self.buf, 18)
+    }
+
+    pub fn options(&self) -> &'a [u8] {
+        &self.buf[20..self.header_len()]
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[self.header_len()..]
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+}
@@ -0,0 +1,50 @@
+use super::be16;
+
+/// A view of a UDP datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpDatagram<'a> {
+    /// Bounded by the length field.
+    buf: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    pub const HEADER_LEN: usize = 8;
+
+    /// Creates a view of the datagram in `buf`, or returns `None` if it is shorter than its
+    /// length field. A length of zero, as used by IPv6 jumbograms, covers all of `buf`.
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < Self::HEADER_LEN {
+            return None;
+        }
+        let len = match be16(buf, 4) as usize {
+            0 => buf.len(),
+            len => len,
+        };
+        (len >= Self::HEADER_LEN && len <= buf.len()).then(|| Self { buf: &buf[..len] })
+    }
+
+    pub fn src_port(&self) -> u16 {
+        be16(self.buf, 0)
+    }
+
+    pub fn dst_port(&self) -> u16 {
+        be16(self.buf, 2)
+    }
+
+    pub fn length(&self) -> u16 {
+        be16(self.buf, 4)
+    }
+
+    /// Returns the checksum, zero if it was not computed (IPv4 only).
+    pub fn checksum(&self) -> u16 {
+        be16(self.buf, 6)
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[Self::HEADER_LEN..]
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+}
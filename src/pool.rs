@@ -0,0 +1,432 @@
+use crate::linux::alloc;
+use crate::linux::interface::Interface;
+use crate::linux::io::TunIo;
+use crate::linux::params::Params;
+use crate::result::Result;
+use crate::tun::Tun;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::runtime::{self, Handle};
+use tokio::sync::oneshot;
+
+/// Handles the packets received by a [`TunPool`](struct.TunPool.html).
+pub trait PacketHandler: Send + Sync + 'static {
+    /// Handles `packet`, which was received by the queue `tun` with index `queue`.
+    ///
+    /// The next packet of this queue is only received once the returned future completes.
+    fn handle(&self, tun: &Tun, queue: usize, packet: &[u8]) -> impl Future<Output = ()> + Send;
+}
+
+/// Represents the options of a [`TunPool`](struct.TunPool.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolOptions {
+    queues: Option<usize>,
+    cpus: Vec<usize>,
+    buffer_size: usize,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            queues: None,
+            cpus: Vec::new(),
+            buffer_size: 65536,
+        }
+    }
+}
+
+impl PoolOptions {
+    /// Creates a new instance of [`PoolOptions`](struct.PoolOptions.html).
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the number of queues which are attached initially. Default value is all of them.
+    pub fn queues(mut self, queues: usize) -> Self {
+        self.queues = Some(queues);
+        self
+    }
+
+    /// Runs each queue on its own thread with a current-thread runtime, pinned to the CPU
+    /// `cpus[queue % cpus.len()]`. By default, queues are spawned as tasks on the runtime
+    /// which builds the pool.
+    pub fn pin_cpus(mut self, cpus: impl IntoIterator<Item = usize>) -> Self {
+        self.cpus = cpus.into_iter().collect();
+        self
+    }
+
+    /// Sets the size of the receive buffer of each queue. Default value is 64 KiB.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+}
+
+/// Represents a multiqueue Tun/Tap device whose queues are served by workers calling a
+/// [`PacketHandler`](trait.PacketHandler.html). Use
+/// [`try_build_pool`](struct.TunBuilder.html#method.try_build_pool) to create a new instance
+/// of [`TunPool`](struct.TunPool.html).
+///
+/// All queues are allocated up front, and [`resize`](struct.TunPool.html#method.resize)
+/// attaches or detaches them with `TUNSETQUEUE`, so the kernel only spreads packets over the
+/// attached ones.
+///
+/// ```no_run
+/// # async fn run() -> tokio_tun::result::Result<()> {
+/// use tokio_tun::{PacketHandler, PoolOptions, Tun, TunBuilder};
+///
+/// struct Echo;
+///
+/// impl PacketHandler for Echo {
+///     async fn handle(&self, tun: &Tun, _queue: usize, packet: &[u8]) {
+///         let _ = tun.send(packet).await;
+///     }
+/// }
+///
+/// let mut pool = TunBuilder::new()
+///     .name("tun0")
+///     .up()
+///     .try_build_pool(4, Echo, &PoolOptions::new().queues(2).pin_cpus([0, 1, 2, 3]))?;
+/// pool.resize(4).await?;
+/// pool.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TunPool<H> {
+    iface: Arc<Interface>,
+    handler: Arc<H>,
+    options: PoolOptions,
+    runtime: Handle,
+    slots: Vec<Slot>,
+    queues: usize,
+}
+
+struct Slot {
+    /// Keeps the queue open while no worker serves it.
+    io: TunIo,
+    worker: Option<Worker>,
+}
+
+struct Worker {
+    stop: oneshot::Sender<()>,
+    done: oneshot::Receiver<io::Result<()>>,
+}
+
+impl<H: PacketHandler> TunPool<H> {
+    /// Creates a new device with `max_queues` queues and starts the workers.
+    pub(crate) fn new(
+        params: Params,
+        max_queues: usize,
+        handler: H,
+        options: &PoolOptions,
+    ) -> Result<Self> {
+        let queues = options.queues.unwrap_or(max_queues);
+        if queues == 0 || queues > max_queues {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "queues must be between 1 and the maximum number of queues",
+            )
+            .into());
+        }
+        if !options.cpus.is_empty() {
+            let allowed = allowed_cpus()?;
+            if let Some(cpu) = options.cpus.iter().find(|cpu| !allowed.contains(cpu)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CPU {} is not available", cpu),
+                )
+                .into());
+            }
+        }
+        let runtime = Handle::try_current()?;
+        // Always multiqueue, so that it can be resized even if it starts with a single queue.
+        let params = Params {
+            flags: params.flags | libc::IFF_MULTI_QUEUE as i16,
+            ..params
+        };
+        let (iface, ios) = alloc::allocate(params, max_queues)?;
+        let mut pool = Self {
+            iface: Arc::new(iface),
+            handler: Arc::new(handler),
+            options: options.clone(),
+            runtime,
+            slots: ios
+                .into_iter()
+                .map(|io| Slot { io, worker: None })
+                .collect(),
+            queues,
+        };
+        for slot in &pool.slots[queues..] {
+            Interface::set_queue(slot.io.as_raw_fd(), false)?;
+        }
+        for queue in 0..queues {
+            pool.start(queue)?;
+        }
+        Ok(pool)
+    }
+
+    /// Returns the number of attached queues.
+    pub fn queues(&self) -> usize {
+        self.queues
+    }
+
+    /// Returns the number of allocated queues, which is the upper bound of
+    /// [`resize`](struct.TunPool.html#method.resize).
+    pub fn max_queues(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Attaches or detaches queues, so that `queues` of them are served.
+    ///
+    /// Detached queues are stopped gracefully first, packets which are still queued for them
+    /// are dropped by the kernel. An error of a stopped worker is returned.
+    pub async fn resize(&mut self, queues: usize) -> Result<()> {
+        if queues == 0 || queues > self.slots.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "queues must be between 1 and the maximum number of queues",
+            )
+            .into());
+        }
+        let mut res = Ok(());
+        while self.queues > queues {
+            let queue = self.queues - 1;
+            let stopped = self.stop(queue).await;
+            Interface::set_queue(self.slots[queue].io.as_raw_fd(), false)?;
+            self.queues = queue;
+            res = res.and(stopped);
+        }
+        while self.queues < queues {
+            let queue = self.queues;
+            Interface::set_queue(self.slots[queue].io.as_raw_fd(), true)?;
+            self.queues += 1;
+            self.start(queue)?;
+        }
+        Ok(res?)
+    }
+
+    /// Stops all workers after the packets they are handling, and returns the first error
+    /// which made a worker stop.
+    ///
+    /// Dropping the pool stops the workers as well, without waiting for them.
+    pub async fn shutdown(mut self) -> Result<()> {
+        let mut res = Ok(());
+        for queue in 0..self.queues {
+            res = res.and(self.stop(queue).await);
+        }
+        Ok(res?)
+    }
+
+    tun_methods!();
+
+    /// Starts a worker serving `queue` on a duplicate of its file descriptor, so that the
+    /// queue outlives a worker which panicked.
+    fn start(&mut self, queue: usize) -> Result<()> {
+        let io = self.slots[queue].io.try_clone()?;
+        let (stop, stopped) = oneshot::channel();
+        let (finish, done) = oneshot::channel();
+        let iface = self.iface.clone();
+        let handler = self.handler.clone();
+        let buffer_size = self.options.buffer_size;
+        let serve = async move {
+            let tun = Tun::with(iface, io)?;
+            serve(&tun, queue, &*handler, stopped, buffer_size).await
+        };
+        if self.options.cpus.is_empty() {
+            self.runtime.spawn(async move {
+                let _ = finish.send(serve.await);
+            });
+        } else {
+            let cpu = self.options.cpus[queue % self.options.cpus.len()];
+            std::thread::Builder::new()
+                .name(format!("{}-{}", self.iface.name(), queue))
+                .spawn(move || {
+                    let res = pin_to(cpu).and_then(|()| {
+                        runtime::Builder::new_current_thread()
+                            .enable_io()
+                            .build()?
+                            .block_on(serve)
+                    });
+                    let _ = finish.send(res);
+                })?;
+        }
+        self.slots[queue].worker = Some(Worker { stop, done });
+        Ok(())
+    }
+
+    /// Stops the worker serving `queue` and waits for it.
+    async fn stop(&mut self, queue: usize) -> io::Result<()> {
+        let Some(worker) = self.slots[queue].worker.take() else {
+            return Ok(());
+        };
+        let _ = worker.stop.send(());
+        worker
+            .done
+            .await
+            .unwrap_or_else(|_| Err(io::Error::other("worker panicked")))
+    }
+}
+
+/// Receives packets of `tun` and passes them to `handler` until `stopped` completes.
+async fn serve<H: PacketHandler>(
+    tun: &Tun,
+    queue: usize,
+    handler: &H,
+    mut stopped: oneshot::Receiver<()>,
+    buffer_size: usize,
+) -> io::Result<()> {
+    let mut buf = vec![0; buffer_size];
+    loop {
+        let res = {
+            let mut recv = pin!(tun.recv(&mut buf));
+            // Dropping the sender counts as a request to stop as well.
+            poll_fn(|cx| match Pin::new(&mut stopped).poll(cx) {
+                Poll::Ready(_) => Poll::Ready(None),
+                Poll::Pending => recv.as_mut().poll(cx).map(Some),
+            })
+            .await
+        };
+        match res {
+            Some(Ok(n)) => handler.handle(tun, queue, &buf[..n]).await,
+            Some(Err(err)) => return Err(err),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Returns the CPUs which the calling thread may run on.
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+/// Pins the calling thread to `cpu`.
+fn pin_to(cpu: usize) -> io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeviceKind;
+    use crate::linux::alloc::tests::{attached, exists, privileged};
+    use crate::packet::{Packet, Transport};
+    use crate::TunBuilder;
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const PORT: u16 = 9;
+
+    /// Records the queues which received a datagram to `PORT`.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<usize>>>);
+
+    impl PacketHandler for Recorder {
+        async fn handle(&self, tun: &Tun, queue: usize, packet: &[u8]) {
+            assert_eq!(tun.name(), "pool0");
+            let packet = Packet::parse(packet, DeviceKind::Tun, false);
+            if let Some(Transport::Udp(udp)) = packet.and_then(|packet| packet.transport()) {
+                if udp.dst_port() == PORT {
+                    self.0.lock().unwrap().push(queue);
+                }
+            }
+        }
+    }
+
+    impl Recorder {
+        /// Sends datagrams of many flows through the device, and returns the queues which
+        /// received them.
+        async fn queues(&self) -> BTreeSet<usize> {
+            self.0.lock().unwrap().clear();
+            for _ in 0..64 {
+                // Every socket is bound to another port, so the kernel picks queues by flow.
+                let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+                socket.send_to(b"pool", ("10.253.0.2", PORT)).unwrap();
+            }
+            for _ in 0..500 {
+                if self.0.lock().unwrap().len() == 64 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let queues = self.0.lock().unwrap();
+            assert_eq!(queues.len(), 64);
+            queues.iter().copied().collect()
+        }
+    }
+
+    /// Returns the number of queues attached to device `name`, which the kernel exposes as its
+    /// transmit queues.
+    fn tx_queues(name: &str) -> usize {
+        fs::read_dir(format!("/sys/class/net/{}/queues", name))
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("tx-")
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn resize_and_shutdown() {
+        if !privileged() {
+            return;
+        }
+        let recorder = Recorder::default();
+        let mut pool = TunBuilder::new()
+            .name("pool0")
+            .address(Ipv4Addr::new(10, 253, 0, 1))
+            .netmask(Ipv4Addr::new(255, 255, 255, 0))
+            .packet_info(false)
+            .up()
+            .try_build_pool(4, recorder.clone(), &PoolOptions::new().queues(2))
+            .unwrap();
+        // Every worker serves a duplicate of the file descriptor of its queue.
+        assert_eq!((pool.queues(), pool.max_queues()), (2, 4));
+        assert_eq!(tx_queues("pool0"), 2);
+        assert_eq!(attached("pool0"), 4 + 2);
+        assert!(recorder.queues().await.is_subset(&BTreeSet::from([0, 1])));
+
+        pool.resize(4).await.unwrap();
+        assert_eq!(tx_queues("pool0"), 4);
+        assert_eq!(attached("pool0"), 4 + 4);
+        assert!(recorder.queues().await.len() > 2);
+
+        pool.resize(1).await.unwrap();
+        assert_eq!(tx_queues("pool0"), 1);
+        assert_eq!(attached("pool0"), 4 + 1);
+        assert_eq!(recorder.queues().await, BTreeSet::from([0]));
+
+        let err = pool.resize(5).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "queues must be between 1 and the maximum number of queues"
+        );
+
+        // The workers are gone along with their references to the handler.
+        pool.shutdown().await.unwrap();
+        assert_eq!(Arc::strong_count(&recorder.0), 1);
+        assert!(!exists("pool0"));
+    }
+}
@@ -0,0 +1,453 @@
+use crate::mac::MacAddr;
+use crate::packet::checksum::{self, Checksum};
+use crate::packet::{
+    IcmpPacket, Icmpv6Packet, Network, Packet, PacketBuilder, Transport, ETH_P_ARP, ETH_P_IP,
+    ETH_P_IPV6,
+};
+use crate::tun::Tun;
+use std::io;
+use std::net::IpAddr;
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// Represents a Tun/Tap device which replies to ICMP and ICMPv6 echo requests for its
+/// addresses, so that the other end of a tunnel is pingable.
+///
+/// Echo requests are answered by [`recv`](struct.Responder.html#method.recv), which returns
+/// all other packets. On TAP devices, ARP requests and neighbor solicitations for the addresses
+/// are answered as well once [`resolve`](struct.Responder.html#method.resolve) is enabled, so
+/// that the kernel can resolve them.
+///
+/// ```no_run
+/// # async fn run() -> tokio_tun::result::Result<()> {
+/// use std::net::Ipv4Addr;
+/// use tokio_tun::{Responder, TunBuilder};
+///
+/// let tun = TunBuilder::new()
+///     .name("tun0")
+///     .address(Ipv4Addr::new(10, 0, 0, 1))
+///     .netmask(Ipv4Addr::new(255, 255, 255, 0))
+///     .up()
+///     .try_build()?;
+/// // `ping 10.0.0.2` is answered.
+/// let tun = Responder::new(tun).address(Ipv4Addr::new(10, 0, 0, 2));
+///
+/// let mut buf = [0u8; 1500];
+/// loop {
+///     let n = tun.recv(&mut buf).await?;
+///     println!("reading {} bytes: {:?}", n, &buf[..n]);
+/// }
+/// # }
+/// ```
+pub struct Responder {
+    tun: Tun,
+    addresses: Vec<IpAddr>,
+    mac: MacAddr,
+    resolve: bool,
+}
+
+impl Responder {
+    /// Creates a new instance of [`Responder`](struct.Responder.html), which replies for none
+    /// of the addresses until they are added by
+    /// [`address`](struct.Responder.html#method.address).
+    pub fn new(tun: Tun) -> Self {
+        Self {
+            tun,
+            addresses: Vec::new(),
+            mac: MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            resolve: false,
+        }
+    }
+
+    /// Adds an IPv4 or IPv6 address whose echo requests are answered.
+    pub fn address(mut self, address: impl Into<IpAddr>) -> Self {
+        self.addresses.push(address.into());
+        self
+    }
+
+    /// Sets the MAC address which the addresses resolve to on a TAP device. Default value is
+    /// `02:00:00:00:00:01`.
+    pub fn mac(mut self, mac: MacAddr) -> Self {
+        self.mac = mac;
+        self
+    }
+
+    /// Answers ARP requests and neighbor solicitations for the addresses on a TAP device.
+    /// Default value is `false`.
+    ///
+    /// The replies claim the addresses for [`mac`](struct.Responder.html#method.mac), so they
+    /// take over the addresses from other hosts if the device is bridged to a link.
+    pub fn resolve(mut self, resolve: bool) -> Self {
+        self.resolve = resolve;
+        self
+    }
+
+    /// Returns the addresses whose echo requests are answered.
+    pub fn addresses(&self) -> &[IpAddr] {
+        &self.addresses
+    }
+
+    /// Returns the device.
+    pub fn tun(&self) -> &Tun {
+        &self.tun
+    }
+
+    /// Consumes this instance and returns the device.
+    pub fn into_inner(self) -> Tun {
+        self.tun
+    }
+
+    /// Receives a packet which was not answered, and returns its length.
+    ///
+    /// Replies are sent before waiting for the next packet. A reply which can not be sent is
+    /// dropped like a packet lost on the link, so errors are only those of receiving.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.tun.recv(buf).await?;
+            match self.reply(&buf[..n]) {
+                Some(reply) => {
+                    let _ = self.tun.send(&reply).await;
+                }
+                None => return Ok(n),
+            }
+        }
+    }
+
+    /// Sends a packet through the device.
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.tun.send(buf).await
+    }
+
+    /// Returns the reply to `packet`, which was received from the device, or `None` if it is
+    /// not answered. Requests with an invalid checksum are not answered.
+    ///
+    /// This is used by [`recv`](struct.Responder.html#method.recv), and may be used to answer
+    /// packets which were received otherwise, e.g. by the queues of a multiqueue device.
+    pub fn reply(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        let flags = self.tun.attach_flags();
+        reply(flags, &self.addresses, self.mac, self.resolve, packet)
+    }
+}
+
+/// Returns the reply to `packet`, which was received from a device created with `flags`, on
+/// behalf of `addresses` which resolve to `mac`. ARP requests and neighbor solicitations are
+/// only answered if `resolve` is true.
+fn reply(
+    flags: i16,
+    addresses: &[IpAddr],
+    mac: MacAddr,
+    resolve: bool,
+    packet: &[u8],
+) -> Option<Vec<u8>> {
+    let packet = Packet::from_device(flags, packet)?;
+    let ethernet = packet.ethernet().map(|frame| (mac, frame.source()));
+    match (packet.network(), packet.transport()) {
+        (Network::Ipv4(ip), Some(Transport::Icmp(icmp))) => {
+            let echo = icmp.echo()?;
+            if icmp.icmp_type() != IcmpPacket::ECHO_REQUEST
+                || !addresses.contains(&ip.destination().into())
+                || checksum::checksum(icmp.as_bytes()) != 0
+            {
+                return None;
+            }
+            let mut buf = prefix(flags, ETH_P_IP, ethernet);
+            PacketBuilder::ipv4(ip.destination(), ip.source())
+                .echo_reply(echo.identifier, echo.sequence)
+                .write(icmp.payload(), &mut buf)
+                .ok()?;
+            Some(buf)
+        }
+        (Network::Ipv6(ip), Some(Transport::Icmpv6(icmp))) => {
+            let len = icmp.as_bytes().len() as u32;
+            let protocol = libc::IPPROTO_ICMPV6 as u8;
+            let sum =
+                Checksum::pseudo_header(ip.source().into(), ip.destination().into(), protocol, len)
+                    .push(icmp.as_bytes())
+                    .finish();
+            if sum != 0 {
+                return None;
+            }
+            let (builder, payload) = match icmp.icmp_type() {
+                Icmpv6Packet::ECHO_REQUEST if addresses.contains(&ip.destination().into()) => {
+                    let echo = icmp.echo()?;
+                    let builder = PacketBuilder::ipv6(ip.destination(), ip.source())
+                        .echo_reply(echo.identifier, echo.sequence);
+                    (builder, icmp.payload().to_vec())
+                }
+                // Solicitations which may have been forwarded by a router are invalid
+                // (RFC 4861 section 7.1.1).
+                NEIGHBOR_SOLICITATION
+                    if resolve
+                        && ethernet.is_some()
+                        && ip.hop_limit() == 255
+                        && icmp.code() == 0 =>
+                {
+                    let target: [u8; 16] = icmp.payload().get(..16)?.try_into().ok()?;
+                    // Duplicate address detection is not answered.
+                    if !addresses.contains(&target.into()) || ip.source().is_unspecified() {
+                        return None;
+                    }
+                    // Solicited and override flags
+                    let builder = PacketBuilder::ipv6(target.into(), ip.source())
+                        .ttl(255)
+                        .icmp(NEIGHBOR_ADVERTISEMENT, 0, [0x60, 0, 0, 0]);
+                    // The target address, followed by the target link-layer address option.
+                    let mut payload = target.to_vec();
+                    payload.extend_from_slice(&[2, 1]);
+                    payload.extend_from_slice(&mac.octets());
+                    (builder, payload)
+                }
+                _ => return None,
+            };
+            let mut buf = prefix(flags, ETH_P_IPV6, ethernet);
+            builder.write(&payload, &mut buf).ok()?;
+            Some(buf)
+        }
+        (Network::Arp(arp), _) if resolve => {
+            let (sender_mac, sender_ip) = (arp.sender_mac()?, arp.sender_ip()?);
+            let target_ip = arp.target_ip()?;
+            if arp.operation() != ARP_REQUEST || !addresses.contains(&target_ip.into()) {
+                return None;
+            }
+            let mut buf = prefix(flags, ETH_P_ARP, Some((mac, sender_mac)));
+            // Ethernet and IPv4, followed by the lengths of their addresses.
+            buf.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4]);
+            buf.extend_from_slice(&ARP_REPLY.to_be_bytes());
+            buf.extend_from_slice(&mac.octets());
+            buf.extend_from_slice(&target_ip.octets());
+            buf.extend_from_slice(&sender_mac.octets());
+            buf.extend_from_slice(&sender_ip.octets());
+            Some(buf)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the headers which precede a reply of `ethertype`, as configured by `flags`.
+fn prefix(flags: i16, ethertype: u16, ethernet: Option<(MacAddr, MacAddr)>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if flags & libc::IFF_NO_PI as i16 == 0 {
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&ethertype.to_be_bytes());
+    }
+    if flags & libc::IFF_VNET_HDR as i16 != 0 {
+        // An empty `struct virtio_net_hdr`
+        buf.extend_from_slice(&[0; 10]);
+    }
+    if let Some((src, dst)) = ethernet {
+        buf.extend_from_slice(&dst.octets());
+        buf.extend_from_slice(&src.octets());
+        buf.extend_from_slice(&ethertype.to_be_bytes());
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Ipv6PacketMut;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const TUN: i16 = (libc::IFF_TUN | libc::IFF_NO_PI) as i16;
+    const TAP: i16 = (libc::IFF_TAP | libc::IFF_NO_PI) as i16;
+    const MAC: MacAddr = MacAddr::new(2, 0, 0, 0, 0, 1);
+    const PEER_MAC: MacAddr = MacAddr::new(2, 0, 0, 0, 0, 2);
+    const ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const ADDR6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+    const PEER6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+
+    fn answer(flags: i16, packet: &[u8]) -> Option<Vec<u8>> {
+        reply(flags, &[ADDR.into(), ADDR6.into()], MAC, false, packet)
+    }
+
+    /// Answers `packet` like [`answer`], and ARP requests and neighbor solicitations as well.
+    fn resolve(flags: i16, packet: &[u8]) -> Option<Vec<u8>> {
+        reply(flags, &[ADDR.into(), ADDR6.into()], MAC, true, packet)
+    }
+
+    /// Returns a neighbor solicitation for `target`, sent to its solicited-node address.
+    fn solicitation(target: Ipv6Addr) -> PacketBuilder {
+        let [.., a, b] = target.octets();
+        let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00 | a as u16, b as u16);
+        PacketBuilder::ipv6(PEER6, group)
+            .ethernet(PEER_MAC, MacAddr::new(0x33, 0x33, 0xff, 0, a, b))
+            .ttl(255)
+            .icmp(NEIGHBOR_SOLICITATION, 0, [0; 4])
+    }
+
+    fn solicitation_payload(target: Ipv6Addr) -> Vec<u8> {
+        // The target address, followed by the source link-layer address option.
+        [&target.octets()[..], &[1, 1], &PEER_MAC.octets()].concat()
+    }
+
+    #[test]
+    fn answers_echo_request() {
+        let request = PacketBuilder::ipv4(PEER, ADDR)
+            .echo_request(7, 3)
+            .build(b"ping")
+            .unwrap();
+        let expected = PacketBuilder::ipv4(ADDR, PEER)
+            .echo_reply(7, 3)
+            .build(b"ping")
+            .unwrap();
+        assert_eq!(answer(TUN, &request), Some(expected));
+
+        let request = PacketBuilder::ipv6(PEER6, ADDR6)
+            .echo_request(7, 3)
+            .build(b"ping6")
+            .unwrap();
+        let expected = PacketBuilder::ipv6(ADDR6, PEER6)
+            .echo_reply(7, 3)
+            .build(b"ping6")
+            .unwrap();
+        assert_eq!(answer(TUN, &request), Some(expected));
+    }
+
+    #[test]
+    fn answers_with_headers_of_device() {
+        let request = PacketBuilder::ipv4(PEER, ADDR)
+            .packet_info(true)
+            .ethernet(PEER_MAC, MAC)
+            .echo_request(7, 3)
+            .build(b"ping")
+            .unwrap();
+        let expected = PacketBuilder::ipv4(ADDR, PEER)
+            .packet_info(true)
+            .ethernet(MAC, PEER_MAC)
+            .echo_reply(7, 3)
+            .build(b"ping")
+            .unwrap();
+        assert_eq!(
+            answer(libc::IFF_TAP as i16, &request),
+            Some(expected.clone())
+        );
+
+        // The virtio-net header follows the packet information.
+        let flags = (libc::IFF_TAP | libc::IFF_VNET_HDR) as i16;
+        let request = [&request[..4], &[0; 10], &request[4..]].concat();
+        let expected = [&expected[..4], &[0; 10], &expected[4..]].concat();
+        assert_eq!(answer(flags, &request), Some(expected));
+    }
+
+    #[test]
+    fn ignores_other_packets() {
+        let builder = PacketBuilder::ipv4(PEER, ADDR);
+        let reply = builder.clone().echo_reply(7, 3).build(b"pong").unwrap();
+        assert_eq!(answer(TUN, &reply), None);
+        let udp = builder.udp(1, 7).build(b"echo").unwrap();
+        assert_eq!(answer(TUN, &udp), None);
+        let other = PacketBuilder::ipv4(PEER, Ipv4Addr::new(10, 0, 0, 3))
+            .echo_request(7, 3)
+            .build(b"ping")
+            .unwrap();
+        assert_eq!(answer(TUN, &other), None);
+    }
+
+    #[test]
+    fn ignores_invalid_checksums() {
+        let mut request = PacketBuilder::ipv4(PEER, ADDR)
+            .echo_request(7, 3)
+            .build(b"ping")
+            .unwrap();
+        request[28] ^= 1;
+        assert_eq!(answer(TUN, &request), None);
+
+        let mut request = PacketBuilder::ipv6(PEER6, ADDR6)
+            .echo_request(7, 3)
+            .build(b"ping")
+            .unwrap();
+        request[48] ^= 1;
+        assert_eq!(answer(TUN, &request), None);
+    }
+
+    #[test]
+    fn answers_neighbor_solicitation() {
+        let request = solicitation(ADDR6)
+            .build(&solicitation_payload(ADDR6))
+            .unwrap();
+        // Neighbor solicitations are only answered when resolving addresses.
+        assert_eq!(answer(TAP, &request), None);
+        let reply = resolve(TAP, &request).unwrap();
+        let packet = Packet::from_device(TAP, &reply).unwrap();
+        let frame = packet.ethernet().unwrap();
+        assert_eq!((frame.source(), frame.destination()), (MAC, PEER_MAC));
+        let Network::Ipv6(ip) = packet.network() else {
+            panic!()
+        };
+        assert_eq!((ip.source(), ip.destination()), (ADDR6, PEER6));
+        assert_eq!(ip.hop_limit(), 255);
+
+        let advertisement = [&ADDR6.octets()[..], &[2, 1], &MAC.octets()].concat();
+        let expected = PacketBuilder::ipv6(ADDR6, PEER6)
+            .ethernet(MAC, PEER_MAC)
+            .ttl(255)
+            .icmp(NEIGHBOR_ADVERTISEMENT, 0, [0x60, 0, 0, 0])
+            .build(&advertisement)
+            .unwrap();
+        assert_eq!(reply, expected);
+    }
+
+    #[test]
+    fn ignores_invalid_neighbor_solicitation() {
+        let payload = solicitation_payload(ADDR6);
+        // Forwarded by a router
+        let request = solicitation(ADDR6).ttl(64).build(&payload).unwrap();
+        assert_eq!(resolve(TAP, &request), None);
+        // Not a TAP device
+        let request = solicitation(ADDR6).build(&payload).unwrap();
+        assert_eq!(resolve(TUN, &request[14..]), None);
+        // Another target
+        let target = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 3);
+        let request = solicitation(target)
+            .build(&solicitation_payload(target))
+            .unwrap();
+        assert_eq!(resolve(TAP, &request), None);
+        // Duplicate address detection
+        let mut request = solicitation(ADDR6).build(&payload).unwrap();
+        Ipv6PacketMut::new(&mut request[14..])
+            .unwrap()
+            .set_source(Ipv6Addr::UNSPECIFIED);
+        assert_eq!(resolve(TAP, &request), None);
+    }
+
+    #[test]
+    fn answers_arp_request() {
+        let arp = |operation: u16, target: Ipv4Addr| {
+            [
+                &[0xff; 6][..],
+                &PEER_MAC.octets(),
+                &ETH_P_ARP.to_be_bytes(),
+                &[0, 1, 0x08, 0x00, 6, 4],
+                &operation.to_be_bytes(),
+                &PEER_MAC.octets(),
+                &PEER.octets(),
+                &[0; 6],
+                &target.octets(),
+            ]
+            .concat()
+        };
+        let expected = [
+            &PEER_MAC.octets()[..],
+            &MAC.octets(),
+            &ETH_P_ARP.to_be_bytes(),
+            &[0, 1, 0x08, 0x00, 6, 4],
+            &ARP_REPLY.to_be_bytes(),
+            &MAC.octets(),
+            &ADDR.octets(),
+            &PEER_MAC.octets(),
+            &PEER.octets(),
+        ]
+        .concat();
+        assert_eq!(answer(TAP, &arp(ARP_REQUEST, ADDR)), None);
+        assert_eq!(resolve(TAP, &arp(ARP_REQUEST, ADDR)), Some(expected));
+        assert_eq!(resolve(TAP, &arp(ARP_REPLY, ADDR)), None);
+        assert_eq!(
+            resolve(TAP, &arp(ARP_REQUEST, Ipv4Addr::new(10, 0, 0, 3))),
+            None
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use std::net::IpAddr;
+
+/// Represents a route through a Tun/Tap device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Route {
+    /// Network address of the destination prefix.
+    pub destination: IpAddr,
+    /// Length of the destination prefix in bits.
+    pub prefix_len: u8,
+    /// Optional next hop, packets are delivered directly on the link if it is `None`.
+    pub gateway: Option<IpAddr>,
+    /// Optional metric (priority) of the route, lower values are preferred.
+    pub metric: Option<u32>,
+}
+
+impl Route {
+    /// Creates a new route to `destination/prefix_len` without gateway and metric.
+    pub fn new(destination: IpAddr, prefix_len: u8) -> Self {
+        Self {
+            destination,
+            prefix_len,
+            gateway: None,
+            metric: None,
+        }
+    }
+
+    /// Sets the gateway of route.
+    pub fn gateway(mut self, gateway: IpAddr) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    /// Sets the metric of route.
+    pub fn metric(mut self, metric: u32) -> Self {
+        self.metric = Some(metric);
+        self
+    }
+}
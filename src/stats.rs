@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Represents the kernel counters of a Tun/Tap device (`IFLA_STATS64`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+    pub rx_fifo_errors: u64,
+    pub tx_fifo_errors: u64,
+    pub multicast: u64,
+}
+
+impl Stats {
+    /// Parses a `struct rtnl_link_stats64`.
+    pub(crate) fn from_stats64(buf: &[u8]) -> Option<Self> {
+        let field = |index: usize| -> Option<u64> {
+            let bytes = buf.get(index * 8..index * 8 + 8)?;
+            Some(u64::from_ne_bytes(bytes.try_into().ok()?))
+        };
+        Some(Self {
+            rx_packets: field(0)?,
+            tx_packets: field(1)?,
+            rx_bytes: field(2)?,
+            tx_bytes: field(3)?,
+            rx_errors: field(4)?,
+            tx_errors: field(5)?,
+            rx_dropped: field(6)?,
+            tx_dropped: field(7)?,
+            multicast: field(8)?,
+            rx_fifo_errors: field(14)?,
+            tx_fifo_errors: field(18)?,
+        })
+    }
+}
+
+/// Represents the userspace counters of a single queue.
+///
+/// Note that from the point of view of the device, packets received by a queue are transmitted
+/// by the kernel, so `rx_packets` here is accounted in `tx_packets` of [`Stats`](struct.Stats.html).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct QueueCounters {
+    rx_packets: AtomicU64,
+    tx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    tx_bytes: AtomicU64,
+    rx_errors: AtomicU64,
+    tx_errors: AtomicU64,
+}
+
+impl QueueCounters {
+    pub fn rx(&self, res: &std::io::Result<usize>) {
+        match res {
+            Ok(n) => {
+                self.rx_packets.fetch_add(1, Ordering::Relaxed);
+                self.rx_bytes.fetch_add(*n as u64, Ordering::Relaxed);
+            }
+            Err(err) if err.kind() != std::io::ErrorKind::WouldBlock => {
+                self.rx_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {}
+        }
+    }
+
+    pub fn tx(&self, res: &std::io::Result<usize>) {
+        match res {
+            Ok(n) => {
+                self.tx_packets.fetch_add(1, Ordering::Relaxed);
+                self.tx_bytes.fetch_add(*n as u64, Ordering::Relaxed);
+            }
+            Err(err) if err.kind() != std::io::ErrorKind::WouldBlock => {
+                self.tx_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {}
+        }
+    }
+
+    pub fn snapshot(&self) -> QueueStats {
+        QueueStats {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            rx_errors: self.rx_errors.load(Ordering::Relaxed),
+            tx_errors: self.tx_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::linux::netlink::{self, ifinfomsg, Attrs};
+
+    /// Returns an `RTM_NEWLINK` payload with `IFLA_MTU` and `IFLA_STATS64` attributes, where
+    /// the latter holds the 25 counters of `struct rtnl_link_stats64`.
+    fn link(counters: &[u64]) -> Vec<u8> {
+        let stats: Vec<u8> = counters.iter().flat_map(|c| c.to_ne_bytes()).collect();
+        let mut buf = vec![0; std::mem::size_of::<ifinfomsg>()];
+        buf.extend_from_slice(&8u16.to_ne_bytes());
+        buf.extend_from_slice(&netlink::IFLA_MTU.to_ne_bytes());
+        buf.extend_from_slice(&1500u32.to_ne_bytes());
+        buf.extend_from_slice(&(4 + stats.len() as u16).to_ne_bytes());
+        buf.extend_from_slice(&netlink::IFLA_STATS64.to_ne_bytes());
+        buf.extend_from_slice(&stats);
+        buf
+    }
+
+    fn stats(link: &[u8]) -> Option<Stats> {
+        Attrs::after::<ifinfomsg>(link)
+            .find(|&(kind, _)| kind == netlink::IFLA_STATS64)
+            .and_then(|(_, payload)| Stats::from_stats64(payload))
+    }
+
+    #[test]
+    fn decodes_stats64() {
+        // Every counter holds 100 plus its index, except for the ones of interest.
+        let mut counters: Vec<u64> = (0..25).map(|i| 100 + i).collect();
+        counters[8] = 0x0102_0304_0506_0708;
+        counters[14] = 14_000;
+        counters[18] = 18_000;
+        assert_eq!(
+            stats(&link(&counters)),
+            Some(Stats {
+                rx_packets: 100,
+                tx_packets: 101,
+                rx_bytes: 102,
+                tx_bytes: 103,
+                rx_errors: 104,
+                tx_errors: 105,
+                rx_dropped: 106,
+                tx_dropped: 107,
+                multicast: 0x0102_0304_0506_0708,
+                rx_fifo_errors: 14_000,
+                tx_fifo_errors: 18_000,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_stats64() {
+        // Older kernels report 23 counters, `tx_fifo_errors` being the 19th.
+        let counters: Vec<u64> = (0..23).collect();
+        assert_eq!(stats(&link(&counters)).unwrap().tx_fifo_errors, 18);
+        assert_eq!(stats(&link(&counters[..18])), None);
+    }
+}
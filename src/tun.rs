@@ -1,32 +1,24 @@
+use crate::events::{self, LinkEvents, LinkState};
+use crate::linux::alloc;
 use crate::linux::interface::Interface;
 use crate::linux::io::TunIo;
 use crate::linux::params::Params;
 use crate::result::Result;
+use crate::stats::{QueueCounters, QueueStats};
 use std::io;
 use std::io::{Read, Write};
-use std::net::Ipv4Addr;
-use std::os::raw::c_char;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{self, Context, Poll};
 use tokio::io::unix::AsyncFd;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-// Taken from the `futures` crate
-macro_rules! ready {
-    ($e:expr $(,)?) => {
-        match $e {
-            std::task::Poll::Ready(t) => t,
-            std::task::Poll::Pending => return std::task::Poll::Pending,
-        }
-    };
-}
-
 /// Represents a Tun/Tap device. Use [`TunBuilder`](struct.TunBuilder.html) to create a new instance of [`Tun`](struct.Tun.html).
 pub struct Tun {
     iface: Arc<Interface>,
     io: AsyncFd<TunIo>,
+    counters: QueueCounters,
 }
 
 impl AsRawFd for Tun {
@@ -45,7 +37,11 @@ impl AsyncRead for Tun {
         loop {
             let mut guard = ready!(self_mut.io.poll_read_ready_mut(cx))?;
 
-            match guard.try_io(|inner| inner.get_mut().read(buf.initialize_unfilled())) {
+            let res = guard.try_io(|inner| inner.get_mut().read(buf.initialize_unfilled()));
+            if let Ok(res) = &res {
+                self_mut.counters.rx(res);
+            }
+            match res {
                 Ok(Ok(n)) => {
                     buf.set_filled(buf.filled().len() + n);
                     return Poll::Ready(Ok(()));
@@ -68,7 +64,10 @@ impl AsyncWrite for Tun {
             let mut guard = ready!(self_mut.io.poll_write_ready_mut(cx))?;
 
             match guard.try_io(|inner| inner.get_mut().write(buf)) {
-                Ok(result) => return Poll::Ready(result),
+                Ok(result) => {
+                    self_mut.counters.tx(&result);
+                    return Poll::Ready(result);
+                }
                 Err(_would_block) => continue,
             }
         }
@@ -94,47 +93,54 @@ impl AsyncWrite for Tun {
 impl Tun {
     /// Creates a new instance of Tun/Tap device.
     pub(crate) fn new(params: Params) -> Result<Self> {
-        let iface = Self::allocate(params, 1)?;
-        let fd = iface.files()[0];
-        Ok(Self {
-            iface: Arc::new(iface),
-            io: AsyncFd::new(TunIo::from(fd))?,
-        })
+        Ok(Self::new_mq(params, 1)?.remove(0))
     }
 
     /// Creates a new instance of Tun/Tap device.
     pub(crate) fn new_mq(params: Params, queues: usize) -> Result<Vec<Self>> {
-        let iface = Self::allocate(params, queues)?;
-        let mut tuns = Vec::with_capacity(queues);
+        let (iface, queues) = alloc::allocate(params, queues)?;
         let iface = Arc::new(iface);
-        for &fd in iface.files() {
-            tuns.push(Self {
-                iface: iface.clone(),
-                io: AsyncFd::new(TunIo::from(fd))?,
-            })
-        }
-        Ok(tuns)
+        queues
+            .into_iter()
+            .map(|io| Ok(Self::with(iface.clone(), io)?))
+            .collect()
     }
 
-    fn allocate(params: Params, queues: usize) -> Result<Interface> {
-        static TUN: &[u8] = b"/dev/net/tun\0";
+    /// Registers a queue of device with the runtime of the current context.
+    pub(crate) fn with(iface: Arc<Interface>, io: TunIo) -> io::Result<Self> {
+        Ok(Self {
+            iface,
+            io: unsafe { AsyncFd::register(io) }?,
+            counters: Default::default(),
+        })
+    }
+
+    /// Creates a new instance of [`Tun`](struct.Tun.html) from a file descriptor which is
+    /// already attached to a Tun/Tap device, e.g. one inherited from or passed by a privileged
+    /// process.
+    ///
+    /// The file descriptor is switched to non-blocking mode, and the name and flags of device
+    /// are recovered with `TUNGETIFF`, so all getters work as for a device built with
+    /// [`TunBuilder`](struct.TunBuilder.html).
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        Ok(Self::from_fds(vec![fd])?.remove(0))
+    }
 
-        let fds = (0..queues)
-            .map(|_| unsafe {
-                libc::open(
-                    TUN.as_ptr().cast::<c_char>(),
-                    libc::O_RDWR | libc::O_NONBLOCK,
-                )
-            })
-            .collect::<Vec<_>>();
+    /// Creates instances of [`Tun`](struct.Tun.html) from attached file descriptors, where
+    /// consecutive file descriptors of the same device share it as queues.
+    pub(crate) fn from_fds(fds: Vec<OwnedFd>) -> Result<Vec<Self>> {
+        alloc::attach(fds)?
+            .into_iter()
+            .map(|(iface, io)| Ok(Self::with(iface, io)?))
+            .collect()
+    }
 
-        let iface = Interface::new(
-            fds,
-            params.name.as_deref().unwrap_or_default(),
-            params.flags,
-        )?;
-        iface.init(params)?;
-        Ok(iface)
+    /// Deregisters the device from the runtime and returns its file descriptor, which is left
+    /// in non-blocking mode.
+    ///
+    /// The device is not removed as long as the returned file descriptor is open.
+    pub fn into_fd(self) -> OwnedFd {
+        self.io.into_inner().into_fd()
     }
 
     /// Receives a packet from the Tun/Tap interface
@@ -145,7 +151,10 @@ impl Tun {
             let mut guard = self.io.readable().await?;
 
             match guard.try_io(|inner| inner.get_ref().recv(buf)) {
-                Ok(res) => return res,
+                Ok(res) => {
+                    self.counters.rx(&res);
+                    return res;
+                }
                 Err(_) => continue,
             }
         }
@@ -159,7 +168,10 @@ impl Tun {
             let mut guard = self.io.writable().await?;
 
             match guard.try_io(|inner| inner.get_ref().send(buf)) {
-                Ok(res) => return res,
+                Ok(res) => {
+                    self.counters.tx(&res);
+                    return res;
+                }
                 Err(_) => continue,
             }
         }
@@ -171,7 +183,9 @@ impl Tun {
     ///
     /// This method takes &self, so it is possible to call this method concurrently with other methods on this struct.
     pub fn try_recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.io.get_ref().recv(buf)
+        let res = self.io.get_ref().recv(buf);
+        self.counters.rx(&res);
+        res
     }
 
     /// Try to send a packet to the Tun/Tap interface
@@ -180,41 +194,62 @@ impl Tun {
     ///
     /// This method takes &self, so it is possible to call this method concurrently with other methods on this struct.
     pub fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.io.get_ref().send(buf)
+        let res = self.io.get_ref().send(buf);
+        self.counters.tx(&res);
+        res
     }
 
-    /// Returns the name of Tun/Tap device.
-    pub fn name(&self) -> &str {
-        self.iface.name()
-    }
-
-    /// Returns the value of MTU.
-    pub fn mtu(&self) -> Result<i32> {
-        self.iface.mtu(None)
-    }
-
-    /// Returns the IPv4 address of MTU.
-    pub fn address(&self) -> Result<Ipv4Addr> {
-        self.iface.address(None)
-    }
+    /// Polls for a packet, only the waker passed to the most recent call is woken.
+    pub(crate) fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.io.poll_read_ready(cx))?;
 
-    /// Returns the IPv4 destination address of MTU.
-    pub fn destination(&self) -> Result<Ipv4Addr> {
-        self.iface.destination(None)
+            match guard.try_io(|inner| inner.get_ref().recv(buf)) {
+                Ok(res) => {
+                    self.counters.rx(&res);
+                    return Poll::Ready(res);
+                }
+                Err(_) => continue,
+            }
+        }
     }
 
-    /// Returns the IPv4 broadcast address of MTU.
-    pub fn broadcast(&self) -> Result<Ipv4Addr> {
-        self.iface.broadcast(None)
+    /// Returns the flags which the queues of device were attached with.
+    pub(crate) fn attach_flags(&self) -> i16 {
+        self.iface.attach_flags()
     }
 
-    /// Returns the IPv4 netmask address of MTU.
-    pub fn netmask(&self) -> Result<Ipv4Addr> {
-        self.iface.netmask(None)
+    /// Returns the counters of packets received and sent through this queue.
+    ///
+    /// These are maintained in userspace, so each handle returned by
+    /// [`try_build_mq`](struct.TunBuilder.html#method.try_build_mq) has its own counters.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.counters.snapshot()
     }
 
-    /// Returns the flags of MTU.
-    pub fn flags(&self) -> Result<i16> {
-        self.iface.flags(None)
-    }
+    /// Returns a stream of changes of the device, such as being set down, a new MTU, added or
+    /// removed addresses or its deletion.
+    ///
+    /// Changes are reported by the kernel through a `NETLINK_ROUTE` socket, regardless of
+    /// whether they were made by this process or by someone else.
+    pub fn events(&self) -> Result<LinkEvents> {
+        let netlink = self.iface.subscribe(events::GROUPS)?;
+        // Fetched after subscribing, so that no change is missed in between.
+        let link = self.iface.link()?;
+        let (_, state) = LinkState::parse(&link)
+            .ok_or_else(|| io::Error::other("invalid RTM_NEWLINK response"))?;
+        let addresses = self.iface.addresses()?;
+        Ok(LinkEvents::new(
+            netlink,
+            self.iface.clone(),
+            state,
+            addresses,
+        )?)
+    }
+
+    tun_methods!();
 }
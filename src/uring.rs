@@ -0,0 +1,532 @@
+use crate::linux::alloc;
+use crate::linux::interface::Interface;
+use crate::linux::io::TunIo;
+use crate::linux::params::Params;
+use crate::result::Result;
+use crate::stats::{QueueCounters, QueueStats};
+use io_uring::{opcode, squeue, types, IoUring};
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+
+/// Room for the packet information, virtio-net and 802.1Q Ethernet headers on top of MTU.
+const HEADROOM: usize = 64;
+
+/// Tags of `user_data`, the lower 32 bits hold the index of buffer.
+const READ: u64 = 1 << 32;
+const WRITE: u64 = 2 << 32;
+const CANCEL: u64 = 3 << 32;
+const TIMEOUT: u64 = 4 << 32;
+
+/// Represents the options of the rings of [`UringTun`](struct.UringTun.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UringOptions {
+    entries: u16,
+    buffer_size: Option<usize>,
+    sqpoll: Option<Duration>,
+}
+
+impl Default for UringOptions {
+    fn default() -> Self {
+        Self {
+            entries: 64,
+            buffer_size: None,
+            sqpoll: None,
+        }
+    }
+}
+
+impl UringOptions {
+    /// Creates a new instance of [`UringOptions`](struct.UringOptions.html).
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the number of reads kept in flight, which is also the number of packets which may
+    /// be queued for sending. Default value is 64.
+    pub fn entries(mut self, entries: u16) -> Self {
+        self.entries = entries;
+        self
+    }
+
+    /// Sets the size of each buffer, longer packets are truncated. Default value is the MTU of
+    /// device plus room for headers, or 64 KiB if offloads are enabled.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = Some(buffer_size);
+        self
+    }
+
+    /// Lets a kernel thread poll the submission queue, so submitting does not need a system
+    /// call until the thread has been idle for `idle`.
+    pub fn sqpoll(mut self, idle: Duration) -> Self {
+        self.sqpoll = Some(idle);
+        self
+    }
+}
+
+/// Represents a queue of Tun/Tap device driven by `io_uring`. Use
+/// [`try_build_uring`](struct.TunBuilder.html#method.try_build_uring) to create a new instance
+/// of [`UringTun`](struct.UringTun.html).
+///
+/// Every queue has its own ring with a set of registered buffers. Reads into all of them are
+/// kept in flight, and completed packets are returned without any system call until none is
+/// left. Sent packets are copied into a free buffer and queued, then submitted together with
+/// the next read that has to wait, or by [`flush`](struct.UringTun.html#method.flush).
+///
+/// Registered buffers count towards `RLIMIT_MEMLOCK` unless the process has `CAP_IPC_LOCK`.
+///
+/// ```no_run
+/// # async fn run() -> tokio_tun::result::Result<()> {
+/// use tokio_tun::{TunBuilder, UringOptions};
+///
+/// let mut tun = TunBuilder::new()
+///     .name("tun0")
+///     .up()
+///     .try_build_uring(&UringOptions::new())?;
+///
+/// loop {
+///     let mut packets = Vec::new();
+///     tun.recv_batch(|packet| packets.push(packet.to_vec())).await?;
+///     for packet in packets {
+///         tun.send(&packet).await?;
+///     }
+///     tun.flush()?;
+/// }
+/// # }
+/// ```
+pub struct UringTun {
+    iface: Arc<Interface>,
+    // Deregistered before the ring is closed.
+    ready: AsyncFd<RawFd>,
+    ring: IoUring,
+    io: TunIo,
+    // The first half is read into, the second half is written from.
+    bufs: Box<[u8]>,
+    buffer_size: usize,
+    entries: u16,
+    /// Indices and results of completed reads, in order of completion.
+    completed: VecDeque<(u16, i32)>,
+    /// Indices of buffers which are free to be written from.
+    free: Vec<u16>,
+    /// Number of submission queue entries whose completion has not been reaped.
+    in_flight: usize,
+    /// First error of a write, as writes are completed after `send` returns.
+    error: Option<io::Error>,
+    counters: QueueCounters,
+}
+
+impl AsRawFd for UringTun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+impl UringTun {
+    /// Creates new queues of Tun/Tap device, each with its own ring.
+    pub(crate) fn new_mq(
+        params: Params,
+        queues: usize,
+        options: &UringOptions,
+    ) -> Result<Vec<Self>> {
+        if options.entries == 0 || options.entries > 4096 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "entries must be between 1 and 4096",
+            )
+            .into());
+        }
+        let offloads = params.offloads.is_some_and(|offloads| offloads != 0);
+        let (iface, ios) = alloc::allocate(params, queues)?;
+        let iface = Arc::new(iface);
+        let buffer_size = match options.buffer_size {
+            Some(buffer_size) => buffer_size,
+            None if offloads => 65536,
+            None => iface.mtu(None)? as usize + HEADROOM,
+        };
+        ios.into_iter()
+            .map(|io| Self::with(iface.clone(), io, buffer_size, options))
+            .collect()
+    }
+
+    fn with(
+        iface: Arc<Interface>,
+        io: TunIo,
+        buffer_size: usize,
+        options: &UringOptions,
+    ) -> Result<Self> {
+        // The ring waits for the device itself, it would only get `EAGAIN` otherwise.
+        alloc::set_nonblocking(io.as_raw_fd(), false)?;
+
+        let entries = options.entries;
+        let mut builder = IoUring::builder();
+        if let Some(idle) = options.sqpoll {
+            builder.setup_sqpoll(idle.as_millis().try_into().unwrap_or(u32::MAX));
+        }
+        let ring = builder.build(2 * entries as u32)?;
+
+        let mut bufs = vec![0; 2 * entries as usize * buffer_size].into_boxed_slice();
+        let iovec = libc::iovec {
+            iov_base: bufs.as_mut_ptr() as *mut _,
+            iov_len: bufs.len(),
+        };
+        // The buffers are neither moved nor freed while the ring may access them, see `Drop`.
+        unsafe { ring.submitter().register_buffers(&[iovec])? };
+        ring.submitter().register_files(&[io.as_raw_fd()])?;
+
+        let mut tun = Self {
+            iface,
+            ready: unsafe { AsyncFd::register(ring.as_raw_fd()) }?,
+            ring,
+            io,
+            bufs,
+            buffer_size,
+            entries,
+            completed: VecDeque::new(),
+            free: (entries..2 * entries).rev().collect(),
+            in_flight: 0,
+            error: None,
+            counters: Default::default(),
+        };
+        for index in 0..entries {
+            tun.read(index)?;
+        }
+        tun.ring.submit()?;
+        Ok(tun)
+    }
+
+    /// Receives a packet from the Tun/Tap interface into `buf`.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(res) = self.next(|packet| {
+                let n = packet.len().min(buf.len());
+                buf[..n].copy_from_slice(&packet[..n]);
+                n
+            }) {
+                return res;
+            }
+            self.wait().await?;
+        }
+    }
+
+    /// Waits for packets and passes all of the completed ones to `f` without copying them.
+    ///
+    /// Returns the number of packets, or the error of the first failed read.
+    pub async fn recv_batch(&mut self, mut f: impl FnMut(&[u8])) -> io::Result<usize> {
+        loop {
+            let mut count = 0;
+            while let Some(res) = self.next(&mut f) {
+                res?;
+                count += 1;
+            }
+            if count > 0 {
+                return Ok(count);
+            }
+            self.wait().await?;
+        }
+    }
+
+    /// Sends a packet to the Tun/Tap interface.
+    ///
+    /// The packet is queued until the next [`flush`](struct.UringTun.html#method.flush), or
+    /// until a read has to wait. This only waits if all of the buffers are being written.
+    pub async fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.try_send(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => self.wait().await?,
+                res => return res,
+            }
+        }
+    }
+
+    /// Try to receive a packet from the Tun/Tap interface
+    ///
+    /// When there is no pending data, `Err(io::ErrorKind::WouldBlock)` is returned.
+    pub fn try_recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for _ in 0..2 {
+            if let Some(res) = self.next(|packet| {
+                let n = packet.len().min(buf.len());
+                buf[..n].copy_from_slice(&packet[..n]);
+                n
+            }) {
+                return res;
+            }
+            self.ring.submit()?;
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    /// Try to queue a packet for sending to the Tun/Tap interface
+    ///
+    /// When all of the buffers are being written, `Err(io::ErrorKind::WouldBlock)` is returned.
+    pub fn try_send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.buffer_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet is larger than the buffers",
+            ));
+        }
+        self.reap();
+        let index = self.free.pop().ok_or(io::ErrorKind::WouldBlock)?;
+        let offset = index as usize * self.buffer_size;
+        let slot = &mut self.bufs[offset..offset + buf.len()];
+        slot.copy_from_slice(buf);
+        let entry = opcode::WriteFixed::new(types::Fixed(0), slot.as_ptr(), buf.len() as u32, 0)
+            .build()
+            .user_data(WRITE | index as u64);
+        self.push(entry)?;
+        Ok(buf.len())
+    }
+
+    /// Submits the queued packets, and returns the first error of a write completed since the
+    /// last call.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.ring.submit()?;
+        self.reap();
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    /// Returns the counters of packets received and sent through this queue.
+    ///
+    /// Sent packets are accounted once their write has completed.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.counters.snapshot()
+    }
+
+    tun_methods!();
+
+    /// Passes the next completed read to `f` and reads into its buffer again.
+    fn next<T>(&mut self, f: impl FnOnce(&[u8]) -> T) -> Option<io::Result<T>> {
+        if self.completed.is_empty() {
+            self.reap();
+        }
+        let (index, res) = self.completed.pop_front()?;
+        let res = if res < 0 {
+            Err(io::Error::from_raw_os_error(-res))
+        } else {
+            Ok(res as usize)
+        };
+        self.counters.rx(&res);
+        let res = res.map(|n| {
+            let offset = index as usize * self.buffer_size;
+            f(&self.bufs[offset..offset + n])
+        });
+        if let Err(err) = self.read(index) {
+            return Some(Err(err));
+        }
+        Some(res)
+    }
+
+    /// Queues a read into the buffer at `index`.
+    fn read(&mut self, index: u16) -> io::Result<()> {
+        let offset = index as usize * self.buffer_size;
+        let ptr = self.bufs[offset..].as_mut_ptr();
+        let entry = opcode::ReadFixed::new(types::Fixed(0), ptr, self.buffer_size as u32, 0)
+            .build()
+            .user_data(READ | index as u64);
+        self.push(entry)
+    }
+
+    fn push(&mut self, entry: squeue::Entry) -> io::Result<()> {
+        // The entry only refers to the registered buffers, which outlive the ring.
+        while unsafe { self.ring.submission().push(&entry) }.is_err() {
+            self.ring.submit()?;
+        }
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Moves completions out of the completion queue, and returns true if a timeout expired.
+    fn reap(&mut self) -> bool {
+        let mut expired = false;
+        for cqe in self.ring.completion() {
+            self.in_flight -= 1;
+            let index = cqe.user_data() as u16;
+            match cqe.user_data() & !0xffff_ffff {
+                READ => self.completed.push_back((index, cqe.result())),
+                WRITE => {
+                    let res = if cqe.result() < 0 {
+                        Err(io::Error::from_raw_os_error(-cqe.result()))
+                    } else {
+                        Ok(cqe.result() as usize)
+                    };
+                    self.counters.tx(&res);
+                    if let Err(err) = res {
+                        self.error.get_or_insert(err);
+                    }
+                    self.free.push(index);
+                }
+                TIMEOUT => expired |= cqe.result() == -libc::ETIME,
+                _ => {}
+            }
+        }
+        expired
+    }
+
+    /// Cancels the reads in flight and waits for all of the entries to complete, giving up
+    /// once a second passes without any completion.
+    ///
+    /// Passing a timeout to the wait requires `ext_arg` (Linux 5.11), otherwise each wait is
+    /// bounded by a timeout entry, which completes along with the next completion.
+    fn cancel(&mut self, ext_arg: bool) {
+        let cancelled = (0..self.entries).try_for_each(|index| {
+            let entry = opcode::AsyncCancel::new(READ | index as u64)
+                .build()
+                .user_data(CANCEL);
+            self.push(entry)
+        });
+        let timeout = types::Timespec::new().sec(1);
+        let args = types::SubmitArgs::new().timespec(&timeout);
+        while cancelled.is_ok() && self.in_flight > 0 {
+            let res = if ext_arg {
+                self.ring.submitter().submit_with_args(1, &args)
+            } else {
+                let entry = opcode::Timeout::new(&timeout)
+                    .count(1)
+                    .build()
+                    .user_data(TIMEOUT);
+                self.push(entry).and_then(|_| self.ring.submit_and_wait(1))
+            };
+            match res {
+                Err(err) if err.kind() != io::ErrorKind::Interrupted => break,
+                _ if self.reap() => break,
+                _ => {}
+            }
+        }
+    }
+
+    /// Submits the queued entries and waits until there is a completion.
+    async fn wait(&mut self) -> io::Result<()> {
+        self.ring.submit()?;
+        if !self.ring.completion().is_empty() {
+            return Ok(());
+        }
+        let mut guard = self.ready.readable().await?;
+        guard.clear_ready();
+        Ok(())
+    }
+}
+
+impl Drop for UringTun {
+    fn drop(&mut self) {
+        // The kernel writes into the buffers until the reads are complete, so they are
+        // cancelled and reaped before the buffers are freed.
+        self.cancel(self.ring.params().is_feature_ext_arg());
+        // Leaked rather than freed if the kernel may still write into them.
+        if self.in_flight > 0 {
+            std::mem::forget(std::mem::take(&mut self.bufs));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeviceKind;
+    use crate::linux::alloc::tests::{exists, privileged};
+    use crate::packet::{IcmpPacket, Packet, PacketBuilder, Transport};
+    use crate::TunBuilder;
+    use std::net::Ipv4Addr;
+
+    fn builder(name: &str) -> TunBuilder {
+        TunBuilder::new()
+            .name(name)
+            .address(Ipv4Addr::new(10, 252, 0, 1))
+            .netmask(Ipv4Addr::new(255, 255, 255, 0))
+            .packet_info(false)
+            .up()
+    }
+
+    /// Waits for device `name` to be removed, which happens once the ring has released its
+    /// registered file in the background.
+    async fn removed(name: &str) -> bool {
+        for _ in 0..100 {
+            if !exists(name) {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    /// Returns the sequence number of an echo reply, ignoring any other packet.
+    fn echo_reply(packet: &[u8]) -> Option<u16> {
+        match Packet::parse(packet, DeviceKind::Tun, false)?.transport()? {
+            Transport::Icmp(icmp) if icmp.icmp_type() == IcmpPacket::ECHO_REPLY => {
+                Some(icmp.echo()?.sequence)
+            }
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn loopback_echo() {
+        if !privileged() {
+            return;
+        }
+        let options = UringOptions::new().entries(8);
+        let mut tun = builder("uring0").try_build_uring(&options).unwrap();
+        // The kernel answers the echo requests to the address of device.
+        let request =
+            PacketBuilder::ipv4(Ipv4Addr::new(10, 252, 0, 2), Ipv4Addr::new(10, 252, 0, 1));
+        for sequence in 0..4 {
+            let packet = request
+                .clone()
+                .echo_request(7, sequence)
+                .build(b"uring")
+                .unwrap();
+            tun.send(&packet).await.unwrap();
+        }
+        tun.flush().unwrap();
+
+        let mut replies = Vec::new();
+        while replies.len() < 4 {
+            let recv = tun.recv_batch(|packet| replies.extend(echo_reply(packet)));
+            tokio::time::timeout(Duration::from_secs(5), recv)
+                .await
+                .expect("no echo reply")
+                .unwrap();
+        }
+        replies.sort();
+        assert_eq!(replies, [0, 1, 2, 3]);
+        assert_eq!(tun.queue_stats().tx_packets, 4);
+
+        // The write of a packet which is not IP fails after `send` returned.
+        tun.send(&[0; 20]).await.unwrap();
+        let mut flushed = tun.flush();
+        for _ in 0..100 {
+            if flushed.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            flushed = tun.flush();
+        }
+        assert_eq!(flushed.unwrap_err().raw_os_error(), Some(libc::EINVAL));
+        assert_eq!(tun.queue_stats().tx_errors, 1);
+        tun.flush().unwrap();
+
+        // Every read is still in flight, they are cancelled before the buffers are freed.
+        assert!(tun.in_flight >= 8);
+        drop(tun);
+        assert!(removed("uring0").await);
+    }
+
+    #[tokio::test]
+    async fn cancel_without_ext_arg() {
+        if !privileged() {
+            return;
+        }
+        let options = UringOptions::new().entries(8);
+        let mut tun = TunBuilder::new()
+            .name("uring1")
+            .try_build_uring(&options)
+            .unwrap();
+        tun.cancel(false);
+        assert_eq!(tun.in_flight, 0);
+        assert_eq!(tun.completed.len(), 8);
+        drop(tun);
+        assert!(removed("uring1").await);
+    }
+}